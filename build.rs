@@ -2,5 +2,6 @@ use std::error::Error;
 
 fn main() ->Result<(),Box<dyn Error>>{
     tonic_build::compile_protos("src/message.proto")?;
+    tonic_build::compile_protos("src/sparkplug_b.proto")?;
     Ok(())
 }
\ No newline at end of file
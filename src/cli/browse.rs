@@ -0,0 +1,91 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::str::FromStr;
+use opcua::client::prelude::*;
+use serde::Serialize;
+use crate::clients::session_pool::SessionPool;
+use crate::config::configuration::OpcConfig;
+
+/// Levels below the start node; the Server object alone is several levels deep.
+pub const DEFAULT_DEPTH: usize = 4;
+
+#[derive(Serialize)]
+struct BrowseNode {
+    name: String,
+    node_id: String,
+    node_class: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<BrowseNode>,
+}
+
+/// Prints the address space below `node` (the Objects folder by default) following hierarchical references.
+pub fn run(source: &OpcConfig, node: Option<&str>, depth: usize, json: bool) -> Result<(), Box<dyn Error>> {
+    let start = match node {
+        Some(node) => NodeId::from_str(node).map_err(|_| format!("{} is not a valid node id", node))?,
+        None => ObjectId::ObjectsFolder.into(),
+    };
+    let session = SessionPool::connect_session(source, &source.url)?;
+    let session = session.read();
+    let mut visited = HashSet::new();
+    visited.insert(start.clone());
+    let tree = BrowseNode {
+        name: start.to_string(),
+        node_id: start.to_string(),
+        node_class: String::new(),
+        children: browse(&session, &start, depth, &mut visited)?,
+    };
+    session.disconnect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&tree)?);
+    } else {
+        println!("{}", tree.node_id);
+        print_tree(&tree.children, 1);
+    }
+    Ok(())
+}
+
+fn browse(session: &Session, node_id: &NodeId, depth: usize, visited: &mut HashSet<NodeId>) -> Result<Vec<BrowseNode>, StatusCode> {
+    if depth == 0 {
+        return Ok(Vec::new());
+    }
+    let browse_description = BrowseDescription {
+        node_id: node_id.clone(),
+        browse_direction: BrowseDirection::Forward,
+        reference_type_id: ReferenceTypeId::HierarchicalReferences.into(),
+        include_subtypes: true,
+        node_class_mask: 0,
+        result_mask: BrowseDescriptionResultMask::all().bits(),
+    };
+
+    let mut references = Vec::new();
+    let mut results = session.browse(&[browse_description])?.unwrap_or_default();
+    while let Some(result) = results.pop() {
+        references.extend(result.references.unwrap_or_default());
+        if result.continuation_point.is_null() {
+            break;
+        }
+        results = session.browse_next(false, &[result.continuation_point])?.unwrap_or_default();
+    }
+
+    let mut children = Vec::new();
+    for reference in references {
+        let child_id = reference.node_id.node_id;
+        // Hierarchies may loop back on themselves, e.g. through HasNotifier.
+        let first_visit = visited.insert(child_id.clone());
+        children.push(BrowseNode {
+            name: reference.display_name.text.to_string(),
+            node_id: child_id.to_string(),
+            node_class: format!("{:?}", reference.node_class),
+            children: if first_visit { browse(session, &child_id, depth - 1, visited)? } else { Vec::new() },
+        });
+    }
+    Ok(children)
+}
+
+fn print_tree(nodes: &[BrowseNode], indent: usize) {
+    for node in nodes {
+        println!("{}{} ({}) {}", "  ".repeat(indent), node.name, node.node_id, node.node_class);
+        print_tree(&node.children, indent + 1);
+    }
+}
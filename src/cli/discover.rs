@@ -0,0 +1,54 @@
+use std::error::Error;
+use serde::Serialize;
+use crate::clients::session_pool::SessionPool;
+use crate::config::configuration::OpcConfig;
+
+#[derive(Serialize)]
+struct Endpoint {
+    url: String,
+    security_policy: String,
+    security_mode: String,
+    security_level: u8,
+    user_tokens: Vec<String>,
+}
+
+/// Lists what each server of the source offers, so `security_policy` and `security_mode` can be picked.
+pub fn run(source: &OpcConfig, json: bool) -> Result<(), Box<dyn Error>> {
+    let mut endpoints = Vec::new();
+    for url in std::iter::once(&source.url).chain(source.redundant_urls.iter()) {
+        let descriptions = SessionPool::server_endpoints(url)
+            .map_err(|e| format!("Cannot get the endpoints of {}: {}", url, e))?;
+        for description in descriptions {
+            let policy_uri = description.security_policy_uri.as_ref();
+            endpoints.push(Endpoint {
+                url: description.endpoint_url.to_string(),
+                // The part after '#' is the name the configuration expects, e.g. Basic256Sha256.
+                security_policy: policy_uri.rsplit('#').next().unwrap_or(policy_uri).to_string(),
+                security_mode: format!("{:?}", description.security_mode),
+                security_level: description.security_level,
+                user_tokens: description
+                    .user_identity_tokens
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|token| format!("{:?}", token.token_type))
+                    .collect(),
+            });
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&endpoints)?);
+        return Ok(());
+    }
+    for endpoint in endpoints {
+        println!(
+            "{}  policy={} mode={} level={} tokens={}",
+            endpoint.url,
+            endpoint.security_policy,
+            endpoint.security_mode,
+            endpoint.security_level,
+            endpoint.user_tokens.join(",")
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,182 @@
+pub mod browse;
+pub mod discover;
+pub mod resolve;
+pub mod tag_io;
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use crate::config::configuration::{OpcConfig, CONFIG};
+use crate::config::loader;
+use crate::replay::{self, ReplayOptions};
+
+pub const USAGE: &str = "\
+Usage: opc_project_ [--config PATH] [COMMAND] [OPTIONS]
+
+Commands:
+  run                 Collect tags and deliver them to the configured sinks (default)
+  browse              Print the address space of an OPC UA server as a tree
+                        [--source NAME] [--url URL] [--node NODE_ID] [--depth N] [--json]
+  discover            List the endpoints and security policies of an OPC UA server
+                        [--source NAME] [--url URL] [--json]
+  resolve             Show which OPC UA nodes the C2 tags resolve to
+                        [--json]
+  read                Read the current value of a tag or node
+                        (--tag NAME | --node NODE_ID) [--source NAME] [--json]
+  write               Write a value to a tag or node
+                        (--tag NAME | --node NODE_ID) --value VALUE [--type TYPE] [--source NAME]
+  validate-config     Check the configuration and report every problem found
+  replay              Push recorded C2 frames to C2 again
+                        PATH [--speed FACTOR]
+
+Options:
+  --config PATH       Configuration file; OPC_CLIENT_CONFIG or config.json when absent
+  -h, --help          Print this help
+";
+
+/// Selects a configured OPC UA source and optionally points it at another server.
+pub struct SourceOptions {
+    pub source: Option<String>,
+    pub url: Option<String>,
+}
+
+impl SourceOptions {
+    fn parse(args: &mut pico_args::Arguments, with_url: bool) -> Result<Self, pico_args::Error> {
+        Ok(SourceOptions {
+            source: args.opt_value_from_str("--source")?,
+            url: if with_url { args.opt_value_from_str("--url")? } else { None },
+        })
+    }
+
+    /// The named source, or the first one configured; `--url` replaces its servers.
+    pub fn resolve(&self) -> Result<OpcConfig, Box<dyn Error>> {
+        let config = CONFIG.current();
+        let sources = config.get_opc_sources();
+        let source = match &self.source {
+            Some(name) => sources.into_iter().find(|source| &source.name == name).ok_or_else(|| format!("No OPC UA source named {}", name))?,
+            None => sources.into_iter().next().ok_or("No OPC UA source configured")?,
+        };
+        let mut source = source.clone();
+        if let Some(url) = &self.url {
+            source.url = url.clone();
+            source.redundant_urls.clear();
+        }
+        Ok(source)
+    }
+}
+
+/// A tag by C2 name, resolved through the source's address space, or a node by id.
+pub enum Target {
+    Tag(String),
+    Node(String),
+}
+
+impl Target {
+    fn parse(args: &mut pico_args::Arguments) -> Result<Self, Box<dyn Error>> {
+        let tag: Option<String> = args.opt_value_from_str("--tag")?;
+        let node: Option<String> = args.opt_value_from_str("--node")?;
+        match (tag, node) {
+            (Some(tag), None) => Ok(Target::Tag(tag)),
+            (None, Some(node)) => Ok(Target::Node(node)),
+            _ => Err("Give exactly one of --tag or --node".into()),
+        }
+    }
+}
+
+pub enum Command {
+    Run,
+    Browse { source: SourceOptions, node: Option<String>, depth: usize, json: bool },
+    Discover { source: SourceOptions, json: bool },
+    Resolve { json: bool },
+    Read { source: SourceOptions, target: Target, json: bool },
+    Write { source: SourceOptions, target: Target, value: String, value_type: Option<String> },
+    ValidateConfig,
+    Replay(ReplayOptions),
+    Help,
+}
+
+pub struct Cli {
+    pub config: PathBuf,
+    pub command: Command,
+}
+
+impl Cli {
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let mut args = pico_args::Arguments::from_env();
+        if args.contains(["-h", "--help"]) {
+            return Ok(Cli { config: loader::config_path(None), command: Command::Help });
+        }
+        let config = loader::config_path(args.opt_value_from_str("--config")?);
+        let subcommand = args.subcommand()?;
+        let command = match subcommand.as_deref() {
+            None | Some("run") => Command::Run,
+            Some("browse") => Command::Browse {
+                source: SourceOptions::parse(&mut args, true)?,
+                node: args.opt_value_from_str("--node")?,
+                depth: args.opt_value_from_str("--depth")?.unwrap_or(browse::DEFAULT_DEPTH),
+                json: args.contains("--json"),
+            },
+            Some("discover") => Command::Discover { source: SourceOptions::parse(&mut args, true)?, json: args.contains("--json") },
+            Some("resolve") => Command::Resolve { json: args.contains("--json") },
+            Some("read") => Command::Read {
+                source: SourceOptions::parse(&mut args, false)?,
+                target: Target::parse(&mut args)?,
+                json: args.contains("--json"),
+            },
+            Some("write") => Command::Write {
+                source: SourceOptions::parse(&mut args, false)?,
+                target: Target::parse(&mut args)?,
+                value: args.value_from_str("--value")?,
+                value_type: args.opt_value_from_str("--type")?,
+            },
+            Some("validate-config") => Command::ValidateConfig,
+            Some("replay") => {
+                let speed: f64 = args.opt_value_from_str("--speed")?.unwrap_or(1.0);
+                if speed.is_nan() || speed < 0.0 {
+                    return Err(format!("Replay speed must be zero or positive, got {}", speed).into());
+                }
+                let path = args.free_from_str().map_err(|_| "replay needs a recording file or directory")?;
+                Command::Replay(ReplayOptions { path, speed })
+            }
+            Some(other) => return Err(format!("Unknown command {}", other).into()),
+        };
+        let unused = args.finish();
+        if !unused.is_empty() {
+            return Err(format!("Unexpected arguments: {:?}", unused).into());
+        }
+        Ok(Cli { config, command })
+    }
+}
+
+/// Runs a command other than `run`, once the configuration is loaded.
+///
+/// The OPC UA commands use the blocking session API and run off the async workers, where the
+/// sessions' own runtimes can also be dropped.
+pub async fn execute(command: Command) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Browse { source, node, depth, json } => {
+            tokio::task::block_in_place(|| browse::run(&source.resolve()?, node.as_deref(), depth, json))
+        }
+        Command::Discover { source, json } => tokio::task::block_in_place(|| discover::run(&source.resolve()?, json)),
+        Command::Resolve { json } => resolve::run(json).await,
+        Command::Read { source, target, json } => tokio::task::block_in_place(|| tag_io::read(&source.resolve()?, &target, json)),
+        Command::Write { source, target, value, value_type } => {
+            tokio::task::block_in_place(|| tag_io::write(&source.resolve()?, &target, &value, value_type.as_deref()))
+        }
+        Command::Replay(options) => replay::run(options).await,
+        Command::Run | Command::ValidateConfig | Command::Help => Ok(()),
+    }
+}
+
+/// Prints the validation report; the exit status is 0 when the configuration can be used and 2 otherwise.
+pub fn validate_config(path: &Path) -> i32 {
+    match loader::load(path) {
+        Ok((_, report)) => {
+            println!("{}", report);
+            if report.has_errors() { 2 } else { 0 }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            2
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use std::error::Error;
+use serde::Serialize;
+use crate::system_initializer::tag_catalog::TagCatalog;
+use crate::system_initializer::tags_synchronizer::TagSynchronizer;
+
+#[derive(Serialize)]
+struct Resolution {
+    tag: String,
+    source: String,
+    node_ids: Vec<String>,
+}
+
+/// Fetches the tag list from C2 and shows the nodes each tag would be subscribed to, and which match nothing.
+pub async fn run(json: bool) -> Result<(), Box<dyn Error>> {
+    let tags = TagSynchronizer::new()?.get_tags().await?;
+    let tag_catalog = TagCatalog::connect()?;
+
+    let resolutions: Vec<Resolution> = tag_catalog
+        .resolve(&tags)
+        .into_iter()
+        .map(|(tag, source, node_ids)| Resolution {
+            tag: tag.name,
+            source,
+            node_ids: node_ids.iter().map(|node_id| node_id.to_string()).collect(),
+        })
+        .collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&resolutions)?);
+        return Ok(());
+    }
+    let unresolved = resolutions.iter().filter(|resolution| resolution.node_ids.is_empty()).count();
+    for resolution in &resolutions {
+        if resolution.node_ids.is_empty() {
+            println!("{} -> {}: not found", resolution.tag, resolution.source);
+        } else {
+            println!("{} -> {}: {}", resolution.tag, resolution.source, resolution.node_ids.join(", "));
+        }
+    }
+    println!("{} tags, {} resolved, {} not found", resolutions.len(), resolutions.len() - unresolved, unresolved);
+    Ok(())
+}
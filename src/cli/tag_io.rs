@@ -0,0 +1,190 @@
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::Arc;
+use opcua::client::prelude::*;
+use opcua::sync::RwLock;
+use serde::Serialize;
+use crate::cli::Target;
+use crate::clients::session_pool::{SessionPool, TagNode};
+use crate::config::configuration::OpcConfig;
+
+#[derive(Serialize)]
+struct ReadResult {
+    tag: String,
+    node_id: String,
+    value: Option<String>,
+    data_type: Option<String>,
+    status: String,
+    source_timestamp: Option<String>,
+    server_timestamp: Option<String>,
+}
+
+/// The sessions a command runs on. Tags are resolved through the catalog of a full session pool; explicit
+/// node ids need no discovery, so they get a single session.
+enum Connection {
+    Pool(Box<SessionPool>),
+    Session(Arc<RwLock<Session>>),
+}
+
+impl Connection {
+    fn session(&self) -> Arc<RwLock<Session>> {
+        match self {
+            Connection::Pool(session_pool) => session_pool.session(0),
+            Connection::Session(session) => session.clone(),
+        }
+    }
+
+    fn disconnect(&self) {
+        match self {
+            Connection::Pool(session_pool) => session_pool.disconnect(),
+            Connection::Session(session) => session.read().disconnect(),
+        }
+    }
+}
+
+pub fn read(source: &OpcConfig, target: &Target, json: bool) -> Result<(), Box<dyn Error>> {
+    let (connection, nodes) = connect(source, target)?;
+    let values = read_values(&connection.session().read(), &nodes);
+    connection.disconnect();
+    let values = values?;
+    let results: Vec<ReadResult> = nodes
+        .iter()
+        .zip(values)
+        .map(|(node, value)| ReadResult {
+            tag: node.name.clone(),
+            node_id: node.node_id.to_string(),
+            data_type: value.value.as_ref().map(|value| format!("{:?}", value.type_id())),
+            value: value.value.map(|value| value.to_string()),
+            status: value.status.unwrap_or(StatusCode::Good).to_string(),
+            source_timestamp: value.source_timestamp.map(|timestamp| timestamp.to_string()),
+            server_timestamp: value.server_timestamp.map(|timestamp| timestamp.to_string()),
+        })
+        .collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+        return Ok(());
+    }
+    for result in results {
+        println!(
+            "{} ({}) = {} {} [{}] source={} server={}",
+            result.tag,
+            result.node_id,
+            result.value.as_deref().unwrap_or("null"),
+            result.data_type.as_deref().unwrap_or(""),
+            result.status,
+            result.source_timestamp.as_deref().unwrap_or("-"),
+            result.server_timestamp.as_deref().unwrap_or("-"),
+        );
+    }
+    Ok(())
+}
+
+/// Writes `raw` to every node of the target, as `value_type` or else as the type of the node's current value.
+pub fn write(source: &OpcConfig, target: &Target, raw: &str, value_type: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let explicit_type = value_type.map(parse_type).transpose()?;
+    let (connection, nodes) = connect(source, target)?;
+    let written = write_nodes(&connection.session().read(), &nodes, raw, explicit_type);
+    connection.disconnect();
+    written
+}
+
+fn write_nodes(session: &Session, nodes: &[TagNode], raw: &str, explicit_type: Option<VariantTypeId>) -> Result<(), Box<dyn Error>> {
+    for node in nodes {
+        let type_id = match explicit_type {
+            Some(type_id) => type_id,
+            None => {
+                let current = read_values(session, std::slice::from_ref(node))?;
+                current
+                    .first()
+                    .and_then(|value| value.value.as_ref())
+                    .map(Variant::type_id)
+                    .ok_or_else(|| format!("{} has no value to take the type from; pass --type", node.node_id))?
+            }
+        };
+        let value = parse_value(raw, type_id).map_err(|e| format!("Cannot write {} as {:?}: {}", raw, type_id, e))?;
+        let status = write_value(session, node, value)?;
+        println!("{} ({}) <- {} [{}]", node.name, node.node_id, raw, status);
+        if !status.is_good() {
+            return Err(format!("The server rejected the write to {}: {}", node.node_id, status).into());
+        }
+    }
+    Ok(())
+}
+
+fn connect(source: &OpcConfig, target: &Target) -> Result<(Connection, Vec<TagNode>), Box<dyn Error>> {
+    match target {
+        Target::Tag(tag) => {
+            let session_pool = SessionPool::connect(source)?;
+            let nodes = session_pool.resolve(std::slice::from_ref(tag));
+            if nodes.is_empty() {
+                session_pool.disconnect();
+                return Err(format!("No node in {} matches the tag", source.name).into());
+            }
+            Ok((Connection::Pool(Box::new(session_pool)), nodes))
+        }
+        Target::Node(node) => {
+            let node_id = NodeId::from_str(node).map_err(|_| format!("{} is not a valid node id", node))?;
+            let session = SessionPool::connect_session(source, &source.url)?;
+            Ok((Connection::Session(session), vec![TagNode { name: node.clone(), node_id }]))
+        }
+    }
+}
+
+fn read_values(session: &Session, nodes: &[TagNode]) -> Result<Vec<DataValue>, StatusCode> {
+    let nodes_to_read: Vec<ReadValueId> = nodes.iter().map(|node| node.node_id.clone().into()).collect();
+    session.read(&nodes_to_read, TimestampsToReturn::Both, 0.0)
+}
+
+fn write_value(session: &Session, node: &TagNode, value: Variant) -> Result<StatusCode, StatusCode> {
+    let write_value = WriteValue {
+        node_id: node.node_id.clone(),
+        attribute_id: AttributeId::Value as u32,
+        index_range: UAString::null(),
+        value: DataValue::value_only(value),
+    };
+    let results = session.write(&[write_value])?;
+    results.first().copied().ok_or(StatusCode::BadUnexpectedError)
+}
+
+fn parse_type(name: &str) -> Result<VariantTypeId, Box<dyn Error>> {
+    let type_id = match name.to_ascii_lowercase().as_str() {
+        "boolean" | "bool" => VariantTypeId::Boolean,
+        "sbyte" => VariantTypeId::SByte,
+        "byte" => VariantTypeId::Byte,
+        "int16" => VariantTypeId::Int16,
+        "uint16" => VariantTypeId::UInt16,
+        "int32" => VariantTypeId::Int32,
+        "uint32" => VariantTypeId::UInt32,
+        "int64" => VariantTypeId::Int64,
+        "uint64" => VariantTypeId::UInt64,
+        "float" => VariantTypeId::Float,
+        "double" => VariantTypeId::Double,
+        "string" => VariantTypeId::String,
+        _ => return Err(format!("Unsupported value type {}", name).into()),
+    };
+    Ok(type_id)
+}
+
+fn parse_value(raw: &str, type_id: VariantTypeId) -> Result<Variant, Box<dyn Error>> {
+    let value = match type_id {
+        VariantTypeId::Boolean => Variant::Boolean(match raw.to_ascii_lowercase().as_str() {
+            "true" | "1" => true,
+            "false" | "0" => false,
+            _ => return Err(format!("{} is not a boolean", raw).into()),
+        }),
+        VariantTypeId::SByte => Variant::SByte(raw.parse()?),
+        VariantTypeId::Byte => Variant::Byte(raw.parse()?),
+        VariantTypeId::Int16 => Variant::Int16(raw.parse()?),
+        VariantTypeId::UInt16 => Variant::UInt16(raw.parse()?),
+        VariantTypeId::Int32 => Variant::Int32(raw.parse()?),
+        VariantTypeId::UInt32 => Variant::UInt32(raw.parse()?),
+        VariantTypeId::Int64 => Variant::Int64(raw.parse()?),
+        VariantTypeId::UInt64 => Variant::UInt64(raw.parse()?),
+        VariantTypeId::Float => Variant::Float(raw.parse()?),
+        VariantTypeId::Double => Variant::Double(raw.parse()?),
+        VariantTypeId::String => Variant::String(raw.into()),
+        other => return Err(format!("Writing {:?} values is not supported", other).into()),
+    };
+    Ok(value)
+}
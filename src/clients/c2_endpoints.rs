@@ -0,0 +1,199 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use log::{info, error};
+use tracing::{info_span, Instrument};
+use crate::clients::ws_client::WebSocketClient;
+use crate::config::configuration::{C2Mode, CONFIG};
+use crate::telemetry::metrics::Exposition;
+
+/// How long an endpoint that failed is skipped before it is tried again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Connection health of one C2 endpoint, shared by the tag synchronizer and every consumer.
+pub struct C2Endpoint {
+    url: String,
+    healthy: AtomicBool,
+    consecutive_failures: AtomicU32,
+    batches_sent: AtomicU64,
+    batches_failed: AtomicU64,
+    connects: AtomicU64,
+    connect_failures: AtomicU64,
+    last_failure: Mutex<Option<Instant>>,
+}
+
+impl C2Endpoint {
+    fn new(url: String) -> Self {
+        C2Endpoint {
+            url,
+            healthy: AtomicBool::new(true),
+            consecutive_failures: AtomicU32::new(0),
+            batches_sent: AtomicU64::new(0),
+            batches_failed: AtomicU64::new(0),
+            connects: AtomicU64::new(0),
+            connect_failures: AtomicU64::new(0),
+            last_failure: Mutex::new(None),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Healthy, or unhealthy for long enough that it is worth another attempt.
+    pub fn is_available(&self) -> bool {
+        self.healthy.load(Ordering::SeqCst)
+            || self.last_failure.lock().unwrap().is_none_or(|failed_at| failed_at.elapsed() >= RETRY_INTERVAL)
+    }
+
+    pub fn record_success(&self) {
+        if !self.healthy.swap(true, Ordering::SeqCst) {
+            info!("C2 endpoint {} is healthy again", self.url);
+        }
+        self.consecutive_failures.store(0, Ordering::SeqCst);
+    }
+
+    pub fn record_sent(&self) {
+        self.batches_sent.fetch_add(1, Ordering::SeqCst);
+        self.record_success();
+    }
+
+    pub fn record_failure(&self, reason: &str) {
+        self.healthy.store(false, Ordering::SeqCst);
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::SeqCst) + 1;
+        *self.last_failure.lock().unwrap() = Some(Instant::now());
+        error!("C2 endpoint {} failed ({} in a row): {}", self.url, failures, reason);
+    }
+
+    pub fn record_batch_failure(&self, reason: &str) {
+        self.batches_failed.fetch_add(1, Ordering::SeqCst);
+        self.record_failure(reason);
+    }
+}
+
+/// Selects one of an endpoint's counters for `render_metrics`.
+type EndpointCounter = fn(&C2Endpoint) -> &AtomicU64;
+
+/// The configured C2 endpoints and the mode that decides which of them a connection uses.
+pub struct C2Endpoints {
+    endpoints: Vec<C2Endpoint>,
+    mode: C2Mode,
+    next: AtomicUsize,
+}
+
+impl C2Endpoints {
+    fn new(urls: Vec<String>, mode: C2Mode) -> Self {
+        C2Endpoints {
+            endpoints: urls.into_iter().map(C2Endpoint::new).collect(),
+            mode,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn mode(&self) -> C2Mode {
+        self.mode
+    }
+
+    pub fn endpoint(&self, index: usize) -> &C2Endpoint {
+        &self.endpoints[index]
+    }
+
+    /// Endpoints in configured order, available ones first, so the primary is preferred while healthy.
+    pub fn failover_order(&self) -> Vec<usize> {
+        let (mut available, unavailable): (Vec<usize>, Vec<usize>) =
+            (0..self.endpoints.len()).partition(|index| self.endpoints[*index].is_available());
+        available.extend(unavailable);
+        available
+    }
+
+    /// Endpoints a new connection should try, in order of preference.
+    pub fn candidates(&self) -> Vec<usize> {
+        match self.mode {
+            C2Mode::Failover | C2Mode::FanOut => self.failover_order(),
+            C2Mode::RoundRobin => {
+                let count = self.endpoints.len().max(1);
+                let start = self.next.fetch_add(1, Ordering::SeqCst) % count;
+                let mut order = self.failover_order();
+                order.sort_by_key(|index| (!self.endpoints[*index].is_available(), (index + count - start) % count));
+                order
+            }
+        }
+    }
+
+    /// Connects to the first candidate that accepts, recording the outcome of each attempt.
+    pub async fn connect_first(&self, candidates: &[usize]) -> Result<(usize, WebSocketClient), String> {
+        for &index in candidates {
+            if let Ok(ws_client) = self.connect(index).await {
+                return Ok((index, ws_client));
+            }
+        }
+        Err("No C2 endpoint accepted a connection".to_string())
+    }
+
+    pub async fn connect(&self, index: usize) -> Result<WebSocketClient, String> {
+        let endpoint = &self.endpoints[index];
+        let config = CONFIG.current();
+        let mut ws_client = WebSocketClient::new(endpoint.url.clone(), config.get_header_key(), config.get_header_value());
+        let attempt = info_span!("c2_connect", endpoint = endpoint.url.as_str());
+        match ws_client.try_connect().instrument(attempt).await.map_err(|e| e.to_string()) {
+            Ok(()) => {
+                endpoint.connects.fetch_add(1, Ordering::SeqCst);
+                endpoint.record_success();
+                Ok(ws_client)
+            }
+            Err(e) => {
+                endpoint.connect_failures.fetch_add(1, Ordering::SeqCst);
+                endpoint.record_failure(&e);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.endpoints.len()
+    }
+
+    pub fn render_metrics(&self, exposition: &mut Exposition) {
+        let counters: [(&str, &str, EndpointCounter); 4] = [
+            ("opc_client_c2_batches_sent_total", "Batches C2 accepted.", |endpoint| &endpoint.batches_sent),
+            ("opc_client_c2_send_errors_total", "Batches that failed to send to C2.", |endpoint| &endpoint.batches_failed),
+            (
+                "opc_client_c2_connects_total",
+                "WebSocket connections opened to C2; growth after start-up means reconnects.",
+                |endpoint| &endpoint.connects,
+            ),
+            ("opc_client_c2_connect_errors_total", "Failed attempts to connect to C2.", |endpoint| &endpoint.connect_failures),
+        ];
+        for (name, help, counter) in counters {
+            exposition.family(name, "counter", help);
+            for endpoint in &self.endpoints {
+                exposition.sample(name, &[("endpoint", &endpoint.url)], counter(endpoint).load(Ordering::SeqCst) as f64);
+            }
+        }
+        exposition.family("opc_client_c2_endpoint_healthy", "gauge", "1 while the last exchange with the C2 endpoint succeeded.");
+        for endpoint in &self.endpoints {
+            let healthy = if endpoint.healthy.load(Ordering::SeqCst) { 1.0 } else { 0.0 };
+            exposition.sample("opc_client_c2_endpoint_healthy", &[("endpoint", &endpoint.url)], healthy);
+        }
+    }
+
+    pub fn log_health(&self) {
+        for endpoint in &self.endpoints {
+            info!(
+                "C2 endpoint {}: healthy: {}, sent: {}, failed: {}",
+                endpoint.url,
+                endpoint.healthy.load(Ordering::SeqCst),
+                endpoint.batches_sent.load(Ordering::SeqCst),
+                endpoint.batches_failed.load(Ordering::SeqCst),
+            );
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref C2_ENDPOINTS: C2Endpoints = {
+        let config = CONFIG.current();
+        C2Endpoints::new(config.get_c2_endpoints(), config.get_c2_mode())
+    };
+}
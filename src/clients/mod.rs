@@ -1,2 +1,4 @@
 pub mod ws_client;
-pub mod opcua_client;
\ No newline at end of file
+pub mod opcua_client;
+pub mod session_pool;
+pub mod c2_endpoints;
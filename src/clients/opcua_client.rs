@@ -1,185 +1,395 @@
 use std::sync::Arc;
-use opcua::sync::RwLock;
+use std::collections::{HashMap, HashSet};
 use tokio;
 use prost::Message;
 use opcua::client::prelude::*;
 use log::{info, error};
-use opcua::types::Variant::Float;
+use tracing::info_span;
 use chrono::{DateTime, Utc};
 
-use crate::config::configuration::CONFIG;
-use crate::message::{Historical, HistoricalValue};
-use crate::system_initializer::data_queue::QUEUE;
+use crate::clients::session_pool::{SessionPool, TagNode, MONITORED_ITEMS_PER_CALL};
+use crate::config::configuration::{SubscriptionConfig, CONFIG};
+use crate::message::{Historical, HistoricalValue, Timing};
+use crate::sinks::HISTORICAL_BATCH_ID;
+use crate::system_initializer::data_queue::{QueuedRecord, QUEUE};
+use crate::system_initializer::producer::TagResults;
+use crate::system_initializer::tag_watchdog::WATCHDOG;
+use crate::system_initializer::sample_filter::{Sample, SAMPLE_FILTER};
+use crate::system_initializer::edge_aggregator::EDGE_AGGREGATOR;
+use crate::system_initializer::calculated_tags::CALCULATED_TAGS;
+use crate::system_initializer::scaling::{EuProperties, SCALING};
+use crate::telemetry::metrics::{latency_seconds, METRICS};
 
+/// A monitored item is tracked by its client handle, which survives the subscription being
+/// recreated after a reconnect or a failover while the server-assigned ids do not.
+#[derive(Clone, Copy)]
+struct MonitoredTag {
+    session: usize,
+    client_handle: u32,
+}
+
+/// Where a client handle currently lives on a session.
+struct ServerItem {
+    subscription_id: u32,
+    monitored_item_id: u32,
+    queue_size: u32,
+    discard_oldest: bool,
+}
+
+/// The subscriptions one producer holds on sessions leased from the shared `SessionPool`.
 pub struct OpcuaClient {
-    pub session: Option<Arc<RwLock<Session>>>,
+    session_pool: Arc<SessionPool>,
+    monitored_items: HashMap<String, Vec<MonitoredTag>>,
 }
 
 impl OpcuaClient {
-    pub fn new() -> Self {
+    pub fn new(session_pool: Arc<SessionPool>) -> Self {
         OpcuaClient {
-            session: None,
+            session_pool,
+            monitored_items: HashMap::new(),
         }
     }
 
-    pub fn connect_to_opcserver(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-    
-        let kep_url = CONFIG.get_opc_url();
-        let kep_user = CONFIG.get_opc_username();
-        let kep_password = CONFIG.get_opc_password();
-
-        let _ = tokio::task::block_in_place(|| -> Result<(), Box<dyn std::error::Error>> {
-            let mut client: Client = ClientBuilder::new()
-                .application_name("Client1")
-                .application_uri("urn:client1")
-                .product_uri("urn:client11")
-                .trust_server_certs(false)
-                .create_sample_keypair(true)
-                .session_retry_limit(3)
-                .client()
-                .unwrap();
-
-            if let Ok(session) = client.connect_to_endpoint(
-                (
-                    kep_url,
-                    SecurityPolicy::None.to_str(),
-                    MessageSecurityMode::None,
-                    UserTokenPolicy::anonymous(),
-                ),
-                IdentityToken::UserName(kep_user, kep_password),
-            ) {
-                self.session = Some(session);
-            }
-            else {
-                error!("Failed to connect to OPC UA endpoint.");
-                return Err("Failed to connect to OPC UA endpoint.".into());
-            }
-            Ok(())
-        })?;
-
-        Ok(())
+    pub fn resolve_tags(&self, tags_vec: &[String]) -> Vec<TagNode> {
+        self.session_pool.resolve(tags_vec)
     }
 
-    pub fn browse_nodes(&self, node_id: NodeId, tags_vec: &[String]) -> Result<Vec<String>, StatusCode> {
-        let session = self.session.as_ref().unwrap().read();
-        let session = &*session;
-        let mut matched_tags = Vec::new();
-        let browse_description = BrowseDescription {
-            node_id: node_id.clone(),
-            browse_direction: BrowseDirection::Forward,
-            reference_type_id: ReferenceTypeId::Organizes.into(),
-            include_subtypes: true,
-            node_class_mask: 0,
-            result_mask: BrowseDescriptionResultMask::all().bits() as u32,
-        };
-        
-        let results = session.browse(&[browse_description]).map_err(|e| {
-            error!("Failed to browse node: {}", e);
-            e
-        })?;
-
-        if let Some(results_vec) = results {
-            for result in results_vec.iter() {
-                if let Some(references) = &result.references {
-                    for reference in references {
-                        let child_node_id = reference.node_id.node_id.clone();
-                        let device_names: Vec<String> = (1..=102).map(|i| format!("Device{}", i)).collect();
-                        // if reference.display_name.text.to_string().contains("Device5000") {
-                        if device_names.iter().any(|device| reference.display_name.text.to_string().contains(device)) {
-                            let device_tags = self.browse_tags(session, child_node_id, tags_vec)?;
-                            matched_tags.extend(device_tags);
-                        } else {
-                            let child_matched_tags = self.browse_nodes(child_node_id, tags_vec)?;
-                            matched_tags.extend(child_matched_tags);
-                        }
+    /// Subscribes to `(node, sampling interval)` pairs; a negative interval uses the publishing interval.
+    pub async fn subscribe_tags(&mut self, tags_vec: Vec<(TagNode, f64)>) -> Result<TagResults, StatusCode> {
+        let mut tag_results = Vec::new();
+        let session_index = self.session_pool.lease_subscription()?;
+        let session = self.session_pool.session(session_index);
+        let session = session.read();
+        let config = CONFIG.current();
+        let settings = config.get_subscription();
+        let subscription_id = session.create_subscription(
+            settings.publishing_interval_ms,
+            settings.lifetime_count,
+            settings.max_keep_alive_count,
+            settings.max_notifications_per_publish,
+            settings.priority,
+            true,
+            DataChangeCallback::new( move |changed_monitored_items| {
+                for item in changed_monitored_items {
+                    for historical_buffer in Self::process_historical(item) {
+                        tokio::spawn(async move {
+                           if QUEUE.enqueue(historical_buffer).is_err() {
+                                error!("Error sending data to DataQueue");
+                           }
+                        });
                     }
                 }
+            }),
+        ).inspect_err(|e| {
+            error!("Failed to create subscription: {}", e);
+            self.session_pool.release_subscription(session_index);
+        })?;
+        let _subscription = info_span!("subscription", subscription_id, session = session_index).entered();
+        info!("Created a subscription with id = {} on session {}", subscription_id, session_index);
+        let mut monitored = 0;
+        for chunk in tags_vec.chunks(MONITORED_ITEMS_PER_CALL) {
+            let items_to_create: Vec<MonitoredItemCreateRequest> = chunk
+                .iter()
+                .map(|(tag, sampling_interval)| {
+                    let mut request: MonitoredItemCreateRequest = tag.node_id.clone().into();
+                    request.requested_parameters.client_handle = self.session_pool.next_client_handle();
+                    request.requested_parameters.sampling_interval = *sampling_interval;
+                    request
+                })
+                .collect();
+            if CONFIG.current().get_read_eu_properties() {
+                Self::read_eu_properties(&session, chunk.iter().map(|(tag, _)| tag));
+            }
+            let results = match session.create_monitored_items(subscription_id, TimestampsToReturn::Both, &items_to_create) {
+                Ok(results) => results,
+                Err(e) => {
+                    error!("Failed to create monitored items: {}", e);
+                    tag_results.extend(chunk.iter().map(|(tag, _)| (tag.name.clone(), e)));
+                    continue;
+                }
+            };
+            for (((tag, _), request), result) in chunk.iter().zip(&items_to_create).zip(results) {
+                let tag_name = tag.name.clone();
+                if result.status_code.is_good() {
+                    self.monitored_items.entry(tag_name.clone()).or_default().push(MonitoredTag {
+                        session: session_index,
+                        client_handle: request.requested_parameters.client_handle,
+                    });
+                    METRICS.monitored_items.add(&[self.session_pool.name()], 1);
+                    monitored += 1;
+                } else {
+                    error!("Failed to monitor {}: {}", tag.node_id, result.status_code);
+                }
+                tag_results.push((tag_name, result.status_code));
             }
         }
-
-      Ok(matched_tags)
+        // A subscription without items would only hold on to its share of the session.
+        if monitored == 0 {
+            if let Err(e) = session.delete_subscription(subscription_id) {
+                error!("Failed to delete empty subscription {}: {}", subscription_id, e);
+            }
+            self.session_pool.release_subscription(session_index);
+        }
+        Ok(tag_results)
     }
-
-    fn browse_tags(&self, session: &Session, channel_node_id: NodeId, tags_vec: &[String]) -> Result<Vec<String>, StatusCode> {
-        let mut matched_tags = Vec::new();
-        let browse_description = BrowseDescription {
-            node_id: channel_node_id.clone(),
-            browse_direction: BrowseDirection::Forward,
-            reference_type_id: ReferenceTypeId::HasComponent.into(),
-            include_subtypes: true,
-            node_class_mask: 0,
-            result_mask: BrowseDescriptionResultMask::all().bits() as u32,
+    
+    /// Reads the EURange and EngineeringUnits properties of the tags' variables for scaling. Tags
+    /// without them, or a server that cannot tell, are scaled without.
+    fn read_eu_properties<'a>(session: &Session, tags: impl Iterator<Item = &'a TagNode>) {
+        const PROPERTIES: [&str; 2] = ["EURange", "EngineeringUnits"];
+        let tags: Vec<&TagNode> = tags.collect();
+        let browse_paths: Vec<BrowsePath> = tags
+            .iter()
+            .flat_map(|tag| {
+                PROPERTIES.iter().map(|property| BrowsePath {
+                    starting_node: tag.node_id.clone(),
+                    relative_path: RelativePath {
+                        elements: Some(vec![RelativePathElement {
+                            reference_type_id: ReferenceTypeId::HasProperty.into(),
+                            is_inverse: false,
+                            include_subtypes: true,
+                            target_name: QualifiedName::new(0, *property),
+                        }]),
+                    },
+                })
+            })
+            .collect();
+        let targets = match session.translate_browse_paths_to_node_ids(&browse_paths) {
+            Ok(targets) => targets,
+            Err(e) => {
+                error!("Failed to look up EURange and EngineeringUnits: {}", e);
+                return;
+            }
         };
+        // One (tag, property) per node found.
+        let (found, nodes_to_read): (Vec<(usize, usize)>, Vec<ReadValueId>) = targets
+            .iter()
+            .enumerate()
+            .filter_map(|(index, result)| {
+                let target = result.targets.as_ref()?.first().filter(|_| result.status_code.is_good())?;
+                Some(((index / PROPERTIES.len(), index % PROPERTIES.len()), target.target_id.node_id.clone().into()))
+            })
+            .unzip();
+        if nodes_to_read.is_empty() {
+            return;
+        }
+        let values = match session.read(&nodes_to_read, TimestampsToReturn::Neither, 0.0) {
+            Ok(values) => values,
+            Err(e) => {
+                error!("Failed to read EURange and EngineeringUnits: {}", e);
+                return;
+            }
+        };
+        let mut properties: HashMap<usize, EuProperties> = HashMap::new();
+        let decoding_options = opcua::types::DecodingOptions::default();
+        for ((tag, property), value) in found.into_iter().zip(values) {
+            let Some(Variant::ExtensionObject(object)) = value.value else {
+                continue;
+            };
+            let properties = properties.entry(tag).or_default();
+            if property == 0 {
+                properties.range = object.decode_inner::<Range>(&decoding_options).ok().map(|range| (range.low, range.high));
+            } else {
+                properties.units = object
+                    .decode_inner::<EUInformation>(&decoding_options)
+                    .ok()
+                    .map(|information| information.display_name.text.to_string())
+                    .filter(|units| !units.is_empty());
+            }
+        }
+        for (tag, properties) in properties {
+            SCALING.set_server_properties(&tags[tag].name, properties);
+        }
+    }
 
-        let results = session.browse(&[browse_description])?;
-        if let Some(results_vec) = results {
-            for result in results_vec.iter() {
-                if let Some(references) = &result.references {
-                    for reference in references {
-                        let tag_name = reference.display_name.text.clone();
-                        let node_id = reference.node_id.clone().to_string();                   
-                        let result = &node_id[13..];
-                        if tags_vec.contains(&tag_name.to_string()) {
-                            matched_tags.push(result.to_string());
-                        }
+    pub fn unsubscribe_tags(&mut self, tags_vec: &[String]) -> Result<TagResults, StatusCode> {
+        let mut tag_results = Vec::new();
+        let mut items_by_session: HashMap<usize, Vec<(String, u32)>> = HashMap::new();
+        for tag in tags_vec {
+            match self.monitored_items.remove(tag) {
+                Some(items) => {
+                    METRICS.monitored_items.add(&[self.session_pool.name()], -(items.len() as i64));
+                    for item in items {
+                        items_by_session.entry(item.session).or_default().push((tag.clone(), item.client_handle));
                     }
                 }
+                None => tag_results.push((tag.clone(), StatusCode::BadMonitoredItemIdInvalid)),
+            }
+        }
+        for (session_index, items) in items_by_session {
+            let session = self.session_pool.session(session_index);
+            let session = session.read();
+            let server_items = Self::server_items(&session);
+            let mut items_by_subscription: HashMap<u32, Vec<(String, u32)>> = HashMap::new();
+            for (tag, client_handle) in items {
+                match server_items.get(&client_handle) {
+                    Some(item) => items_by_subscription
+                        .entry(item.subscription_id)
+                        .or_default()
+                        .push((tag, item.monitored_item_id)),
+                    None => tag_results.push((tag, StatusCode::BadMonitoredItemIdInvalid)),
+                }
+            }
+            for (subscription_id, items) in items_by_subscription {
+                let items_to_delete: Vec<u32> = items.iter().map(|(_, id)| *id).collect();
+                let results = session.delete_monitored_items(subscription_id, &items_to_delete)?;
+                info!("Removed {} monitored items from subscription {}", items_to_delete.len(), subscription_id);
+                tag_results.extend(items.into_iter().map(|(tag, _)| tag).zip(results));
             }
         }
-      Ok(matched_tags)
+        Ok(tag_results)
     }
 
-    pub async fn subscribe_tags(&self, tags_vec: Vec<String>, ns: u16) -> Result<(), StatusCode> {
-        if let Some(session) = self.session.as_ref() {
+    pub fn set_sampling_interval(&self, tags_vec: &[String], sampling_interval: f64) -> Result<TagResults, StatusCode> {
+        let mut tag_results = Vec::new();
+        let mut items_by_session: HashMap<usize, Vec<(String, u32)>> = HashMap::new();
+        for tag in tags_vec {
+            let Some(items) = self.monitored_items.get(tag) else {
+                tag_results.push((tag.clone(), StatusCode::BadMonitoredItemIdInvalid));
+                continue;
+            };
+            for item in items {
+                items_by_session.entry(item.session).or_default().push((tag.clone(), item.client_handle));
+            }
+        }
+
+        for (session_index, items) in items_by_session {
+            let session = self.session_pool.session(session_index);
             let session = session.read();
-            let subscription_id = session.create_subscription(
-                1000.0,
-                30*3,
-                30,
-                0,
-                0,
-                true,
-                DataChangeCallback::new( move |changed_monitored_items| {
-                for item in changed_monitored_items {
-                    let historical_buffer = Self::process_historical(item);
-                    tokio::spawn(async move {
-                       if let Err(_) =  QUEUE.enqueue(historical_buffer){
-                            error!("Error sending data to DataQueue");
-                       }
-                    }); 
-                }
-            }),
-        ).map_err(|e| {
-            error!("Failed to create subscription: {}", e);
-            e
-        })?;
-            info!("Created a subscription with id = {}", subscription_id);
-            const BATCH_SIZE: usize = 809;
-            for chunk in tags_vec.chunks(BATCH_SIZE) {
-                let items_to_create: Vec<MonitoredItemCreateRequest> = chunk
-                    .iter()
-                    .map(|v| NodeId::new(ns, v.clone()).into())
-                    .collect();
-                let _ = session.create_monitored_items(subscription_id, TimestampsToReturn::Both, &items_to_create)?;
+            let server_items = Self::server_items(&session);
+            let mut items_by_subscription: HashMap<u32, (Vec<String>, Vec<MonitoredItemModifyRequest>)> = HashMap::new();
+            for (tag, client_handle) in items {
+                let Some(item) = server_items.get(&client_handle) else {
+                    tag_results.push((tag, StatusCode::BadMonitoredItemIdInvalid));
+                    continue;
+                };
+                // The server echoes the client handle back in notifications, so it must be kept unchanged.
+                let request = MonitoredItemModifyRequest {
+                    monitored_item_id: item.monitored_item_id,
+                    requested_parameters: MonitoringParameters {
+                        client_handle,
+                        sampling_interval,
+                        filter: ExtensionObject::null(),
+                        queue_size: item.queue_size,
+                        discard_oldest: item.discard_oldest,
+                    },
+                };
+                let entry = items_by_subscription.entry(item.subscription_id).or_default();
+                entry.0.push(tag);
+                entry.1.push(request);
+            }
+            for (subscription_id, (tags, items_to_modify)) in items_by_subscription {
+                let results = session.modify_monitored_items(subscription_id, TimestampsToReturn::Both, &items_to_modify)?;
+                tag_results.extend(tags.into_iter().zip(results.into_iter().map(|result| result.status_code)));
             }
         }
-        else {
-            error!("Session is not available for subscribing to variables.");
-            return Err(StatusCode::BadSessionIdInvalid);
+        Ok(tag_results)
+    }
+
+    /// Applies new subscription parameters to every subscription holding this client's items.
+    pub fn modify_subscriptions(&self, settings: &SubscriptionConfig) -> Result<(), StatusCode> {
+        for (session_index, subscription_ids) in self.subscription_ids() {
+            let session = self.session_pool.session(session_index);
+            let session = session.read();
+            for subscription_id in subscription_ids {
+                session.modify_subscription(
+                    subscription_id,
+                    settings.publishing_interval_ms,
+                    settings.lifetime_count,
+                    settings.max_keep_alive_count,
+                    settings.max_notifications_per_publish,
+                    settings.priority,
+                )?;
+            }
         }
         Ok(())
     }
-    
-    fn process_historical(item: &MonitoredItem)-> Vec<u8> {        
+
+    /// Deletes every subscription holding this client's items, so the server stops sampling them
+    /// now rather than when the subscriptions' lifetime runs out.
+    pub fn delete_subscriptions(&mut self) -> Result<(), StatusCode> {
+        for (session_index, subscription_ids) in self.subscription_ids() {
+            let subscription_ids: Vec<u32> = subscription_ids.into_iter().collect();
+            let session = self.session_pool.session(session_index);
+            session.read().delete_subscriptions(&subscription_ids)?;
+            for _ in &subscription_ids {
+                self.session_pool.release_subscription(session_index);
+            }
+            info!("Deleted {} subscriptions on session {}", subscription_ids.len(), session_index);
+        }
+        let items: usize = self.monitored_items.values().map(Vec::len).sum();
+        METRICS.monitored_items.add(&[self.session_pool.name()], -(items as i64));
+        self.monitored_items.clear();
+        Ok(())
+    }
+
+    /// The subscriptions holding this client's items, by session.
+    fn subscription_ids(&self) -> HashMap<usize, HashSet<u32>> {
+        let mut handles_by_session: HashMap<usize, HashSet<u32>> = HashMap::new();
+        for item in self.monitored_items.values().flatten() {
+            handles_by_session.entry(item.session).or_default().insert(item.client_handle);
+        }
+        handles_by_session
+            .into_iter()
+            .map(|(session_index, client_handles)| {
+                let session = self.session_pool.session(session_index);
+                let subscription_ids = Self::server_items(&session.read())
+                    .into_iter()
+                    .filter(|(client_handle, _)| client_handles.contains(client_handle))
+                    .map(|(_, item)| item.subscription_id)
+                    .collect();
+                (session_index, subscription_ids)
+            })
+            .collect()
+    }
+
+    fn server_items(session: &Session) -> HashMap<u32, ServerItem> {
+        let subscription_state = session.subscription_state();
+        let subscription_state = subscription_state.read();
+        let mut server_items = HashMap::new();
+        for subscription_id in subscription_state.subscription_ids().unwrap_or_default() {
+            let Some(subscription) = subscription_state.get(subscription_id) else {
+                continue;
+            };
+            for item in subscription.monitored_items().values() {
+                server_items.insert(item.client_handle(), ServerItem {
+                    subscription_id,
+                    monitored_item_id: item.id(),
+                    queue_size: item.queue_size() as u32,
+                    discard_oldest: item.discard_oldest(),
+                });
+            }
+        }
+        server_items
+    }
+
+    /// Whether the tag has a monitored item in one of this client's subscriptions.
+    pub fn is_monitored(&self, tag: &str) -> bool {
+        self.monitored_items.contains_key(tag)
+    }
+
+    /// Encodes the item's latest value with its source, server and receive times for the consumer,
+    /// along with the calculated tags it changed. Empty when there is nothing to queue.
+    fn process_historical(item: &MonitoredItem)-> Vec<QueuedRecord> {        
+        let received_millis = Utc::now().timestamp_millis();
         let node_id = &item.item_to_monitor().node_id;
         let data_value = item.last_value();
 
+        let mut tag_name = "".to_string();
+        match Self::extract_tagname(node_id.to_string()) {
+            Some(tagname) => tag_name = tagname,
+            None => error!("No tag name in node id {}", node_id),
+        }
+        // Bad values often come without a source timestamp, so the watchdog hears of every value.
+        WATCHDOG.record_update(&tag_name, data_value.status());
+
         let mut value: f64 = 0.0; 
         let mut timestamp_millis: Option<i64> = None;
 
         if let Some(ref value_) = data_value.value {
-            if let Float(value_float) = value_ {
-                value = *value_float as f64; 
+            // PLCs often report raw counts as integers; scaling turns them into engineering values.
+            if let Some(raw) = value_.as_f64() {
+                value = SCALING.scale(&tag_name, raw);
             }
 
             if let Some(timestamp) = &data_value.source_timestamp {
@@ -189,29 +399,59 @@ impl OpcuaClient {
             }
         }
         if let (value, Some(timestamp_millis)) = (value, timestamp_millis) {
-
-            let mut tag_name = "".to_string();
-            match Self::extract_tagname(node_id.to_string()) {
-                Some(tagname) => tag_name = tagname,
-                None => println!("No tag found."),
-            }
             //println!("{}",tag_name);
-            let historical_data = Historical {
-                batchid: 1000,
-                sensor: tag_name,
-                values: vec![HistoricalValue { t: timestamp_millis, v: value }],
+            let sample = Sample {
+                t: timestamp_millis,
+                v: value,
+                server_t: data_value
+                    .server_timestamp
+                    .as_ref()
+                    .and_then(|timestamp| Self::datetime_to_timestamp_millis(&timestamp.to_string()))
+                    .unwrap_or_default(),
+                received_t: received_millis,
             };
-            let mut historical_buffer = Vec::new();
-            if historical_data.encode(&mut historical_buffer).is_err() {
-                error!("Error encoding historical data");
-                return historical_buffer;
-            }
-        return historical_buffer;
+            METRICS.source_to_receive_seconds.observe(&[], latency_seconds(sample.t, received_millis));
+            let good = data_value.status().is_good();
+            let calculated = CALCULATED_TAGS.update(&tag_name, &sample, good);
+            let mut historical_buffers: Vec<QueuedRecord> = Self::encode_historical(tag_name, sample, good).into_iter().collect();
+            historical_buffers.extend(calculated.into_iter().filter_map(|(tag, sample)| Self::encode_historical(tag, sample, true)));
+            historical_buffers
         } else {
             info!("No valid value or timestamp available for historical data.");
-            return vec![1];
-        }  
-    }        
+            METRICS.dropped_samples.inc(&["no_timestamp"]);
+            Vec::new()
+        }
+    }
+
+    /// Passes a value through the aggregations and filters, and encodes it or a value the filters
+    /// held back earlier. `None` when there is nothing to queue.
+    ///
+    /// The record's timing is that of its earliest received value, queued beside the record.
+    fn encode_historical(tag_name: String, sample: Sample, good: bool) -> Option<QueuedRecord> {
+        // Aggregates are taken from every value, before the filters thin them out.
+        if !EDGE_AGGREGATOR.record(&tag_name, &sample, good) {
+            return None;
+        }
+        let samples = SAMPLE_FILTER.filter(&tag_name, sample, good);
+        let timing = samples
+            .iter()
+            .filter(|sample| sample.received_t != 0)
+            .min_by_key(|sample| sample.received_t)
+            .map(|sample| Timing { source_t: sample.t, server_t: sample.server_t, received_t: sample.received_t, dequeued_t: 0 })?;
+        let values = samples.into_iter().map(|sample| HistoricalValue { t: sample.t, v: sample.v }).collect();
+        let historical_data = Historical {
+            batchid: HISTORICAL_BATCH_ID,
+            units: SCALING.units(&tag_name).unwrap_or_default(),
+            sensor: tag_name,
+            values,
+        };
+        let mut historical_buffer = Vec::new();
+        if historical_data.encode(&mut historical_buffer).is_err() {
+            error!("Error encoding historical data");
+            return None;
+        }
+        Some(QueuedRecord { record: historical_buffer, timing })
+    }
 
     fn datetime_to_timestamp_millis(datetime_str: &str) -> Option<i64> {
         if let Ok(datetime) = DateTime::parse_from_rfc3339(datetime_str) {
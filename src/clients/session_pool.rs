@@ -0,0 +1,556 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use opcua::sync::{Mutex, RwLock};
+use opcua::client::prelude::*;
+use tokio::sync::oneshot;
+use log::{info, error};
+use tracing::info_span;
+
+use std::str::FromStr;
+use crate::config::configuration::OpcConfig;
+
+/// ServerCapabilities variables added in OPC UA 1.05, not yet in the `opcua` crate's `VariableId`.
+const MAX_SESSIONS_NODE: u32 = 24095;
+const MAX_SUBSCRIPTIONS_PER_SESSION_NODE: u32 = 24098;
+
+/// Minimum time between two full address space browses triggered by unknown tags.
+const REDISCOVERY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Monitored items per CreateMonitoredItems call, both when subscribing and when moving subscriptions.
+///
+/// Servers limit the operations of one call (`MaxMonitoredItemsPerCall`) and the size of a request;
+/// 809 items of about 80 bytes keep a request under the 64 KiB many servers accept, and under their
+/// per-call limits.
+pub const MONITORED_ITEMS_PER_CALL: usize = 809;
+
+/// ServiceLevel at or above which a server counts as fully healthy (OPC UA Part 4, 6.6.2.4.2).
+const HEALTHY_SERVICE_LEVEL: u8 = 200;
+
+struct PooledSession {
+    session: std::sync::RwLock<Arc<RwLock<Session>>>,
+    // Dropping the sender stops the session's run loop.
+    run_loop: std::sync::Mutex<Option<oneshot::Sender<SessionCommand>>>,
+    subscriptions: AtomicUsize,
+}
+
+/// Passes notifications of a subscription recreated on another server to its original callback.
+struct SharedCallback(Arc<Mutex<dyn OnSubscriptionNotification + Send + Sync>>);
+
+impl OnSubscriptionNotification for SharedCallback {
+    fn on_data_change(&mut self, data_change_items: &[&MonitoredItem]) {
+        self.0.lock().on_data_change(data_change_items);
+    }
+
+    fn on_event(&mut self, events: &EventNotificationList) {
+        self.0.lock().on_event(events);
+    }
+}
+
+#[derive(Clone)]
+pub struct TagNode {
+    pub name: String,
+    pub node_id: NodeId,
+}
+
+struct Catalog {
+    nodes: HashMap<String, Vec<NodeId>>,
+    discovered_at: Instant,
+}
+
+/// OPC UA sessions to one source shared by its producers, plus the tag catalog discovered once through them.
+/// A source may be a redundant server set, in which case the sessions follow the healthiest server.
+pub struct SessionPool {
+    config: OpcConfig,
+    endpoints: Vec<String>,
+    active_endpoint: AtomicUsize,
+    redundancy: RedundancySupport,
+    sessions: Vec<PooledSession>,
+    // Backup sessions kept open for warm and hot redundancy so a failover does not wait on a connect.
+    standby: std::sync::Mutex<HashMap<usize, Arc<RwLock<Session>>>>,
+    max_subscriptions_per_session: usize,
+    next_client_handle: AtomicU32,
+    catalog: std::sync::RwLock<Catalog>,
+    // Set by `disconnect` so the health check does not fail over to reconnect the sessions.
+    disconnected: AtomicBool,
+}
+
+impl SessionPool {
+    pub fn connect(source: &OpcConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let endpoints: Vec<String> = std::iter::once(source.url.clone())
+            .chain(source.redundant_urls.iter().cloned())
+            .collect();
+
+        tokio::task::block_in_place(|| -> Result<Self, Box<dyn std::error::Error>> {
+            // Start on the first server of the set that accepts a session.
+            let (active_endpoint, first_session) = endpoints
+                .iter()
+                .enumerate()
+                .find_map(|(index, url)| Self::connect_session(source, url).ok().map(|session| (index, session)))
+                .ok_or_else(|| format!("No server of {} accepted a session", source.name))?;
+            let opc_url = endpoints[active_endpoint].as_str();
+            let (max_sessions, server_max_subscriptions) = Self::read_server_limits(&first_session.read());
+            let mut pool_size = source.session_pool_size.max(1);
+            if max_sessions > 0 && pool_size > max_sessions {
+                info!("{} allows {} sessions, shrinking session pool from {}", source.name, max_sessions, pool_size);
+                pool_size = max_sessions;
+            }
+            let max_subscriptions_per_session = match (source.max_subscriptions_per_session, server_max_subscriptions) {
+                (0, server) => server,
+                (configured, 0) => configured,
+                (configured, server) => configured.min(server),
+            };
+
+            let redundancy = Self::read_redundancy_support(&first_session.read());
+            if endpoints.len() > 1 || redundancy != RedundancySupport::None {
+                info!("{} is a {:?} redundant server set with {} configured servers", source.name, redundancy, endpoints.len());
+            }
+
+            let mut sessions = vec![first_session];
+            while sessions.len() < pool_size {
+                sessions.push(Self::connect_session(source, opc_url)?);
+            }
+            info!("Connected {} OPC UA sessions to {} ({})", sessions.len(), source.name, opc_url);
+
+            let nodes = Self::discover(&sessions[0].read())?;
+            info!("Discovered {} tags in the address space of {}", nodes.len(), source.name);
+
+            Ok(SessionPool {
+                config: source.clone(),
+                endpoints: endpoints.clone(),
+                active_endpoint: AtomicUsize::new(active_endpoint),
+                redundancy,
+                sessions: sessions
+                    .into_iter()
+                    .map(|session| PooledSession {
+                        run_loop: std::sync::Mutex::new(Some(Session::run_async(session.clone()))),
+                        session: std::sync::RwLock::new(session),
+                        subscriptions: AtomicUsize::new(0),
+                    })
+                    .collect(),
+                standby: std::sync::Mutex::new(HashMap::new()),
+                max_subscriptions_per_session,
+                next_client_handle: AtomicU32::new(1),
+                catalog: std::sync::RwLock::new(Catalog { nodes, discovered_at: Instant::now() }),
+                disconnected: AtomicBool::new(false),
+            })
+        })
+    }
+
+    /// A single session to `url` with the source's security and identity, outside any pool.
+    pub fn connect_session(source: &OpcConfig, url: &str) -> Result<Arc<RwLock<Session>>, Box<dyn std::error::Error>> {
+        let security_policy = SecurityPolicy::from_str(&source.security_policy)
+            .map_err(|_| format!("Unknown security policy {} for {}", source.security_policy, source.name))?;
+        let security_mode = MessageSecurityMode::from(source.security_mode.as_str());
+        let identity_token = if source.username.trim().is_empty() {
+            IdentityToken::Anonymous
+        } else {
+            IdentityToken::UserName(source.username.clone(), source.password.clone())
+        };
+
+        let _attempt = info_span!("opc_connect", source = source.name.as_str(), endpoint = url).entered();
+        let mut client = Self::client()?;
+        let session = client.connect_to_endpoint(
+            (
+                url,
+                security_policy.to_str(),
+                security_mode,
+                UserTokenPolicy::anonymous(),
+            ),
+            identity_token,
+        ).inspect_err(|e| {
+            error!("Failed to connect to OPC UA endpoint {} of {}: {}", url, source.name, e);
+        })?;
+        Ok(session)
+    }
+
+    /// The endpoints a server offers, with their security policies and identity tokens.
+    pub fn server_endpoints(url: &str) -> Result<Vec<EndpointDescription>, Box<dyn std::error::Error>> {
+        let client = Self::client()?;
+        let endpoints = client.get_server_endpoints_from_url(url)?;
+        Ok(endpoints)
+    }
+
+    fn client() -> Result<Client, Box<dyn std::error::Error>> {
+        let client = ClientBuilder::new()
+            .application_name("Client1")
+            .application_uri("urn:client1")
+            .product_uri("urn:client11")
+            .trust_server_certs(false)
+            .create_sample_keypair(true)
+            .session_retry_limit(3)
+            .client()
+            .ok_or("Invalid OPC UA client configuration")?;
+        Ok(client)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// Client handles are unique across the pool so monitored items keep them when moved between servers.
+    pub fn next_client_handle(&self) -> u32 {
+        self.next_client_handle.fetch_add(1, Ordering::SeqCst)
+    }
+
+    pub fn contains(&self, tag: &str) -> bool {
+        self.catalog.read().unwrap().nodes.contains_key(tag)
+    }
+
+    pub fn session(&self, index: usize) -> Arc<RwLock<Session>> {
+        self.sessions[index].session.read().unwrap().clone()
+    }
+
+    /// Picks the session carrying the fewest subscriptions for a new subscription.
+    pub fn lease_subscription(&self) -> Result<usize, StatusCode> {
+        loop {
+            let (index, pooled) = self
+                .sessions
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, pooled)| pooled.subscriptions.load(Ordering::SeqCst))
+                .ok_or(StatusCode::BadSessionIdInvalid)?;
+            let current = pooled.subscriptions.load(Ordering::SeqCst);
+            if self.max_subscriptions_per_session > 0 && current >= self.max_subscriptions_per_session {
+                error!("All {} OPC UA sessions are at their subscription limit", self.sessions.len());
+                return Err(StatusCode::BadTooManySubscriptions);
+            }
+            if pooled.subscriptions.compare_exchange(current, current + 1, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                return Ok(index);
+            }
+        }
+    }
+
+    pub fn release_subscription(&self, index: usize) {
+        self.sessions[index].subscriptions.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Maps tag names to their nodes, browsing the server again if some are unknown.
+    pub fn resolve(&self, tags: &[String]) -> Vec<TagNode> {
+        let (mut matched, unresolved) = self.lookup(tags);
+        let stale = self.catalog.read().unwrap().discovered_at.elapsed() >= REDISCOVERY_INTERVAL;
+        if !unresolved.is_empty() && stale {
+            info!("{} tags are not in the tag catalog of {}, browsing it again", unresolved.len(), self.config.name);
+            let session = self.session(0);
+            match tokio::task::block_in_place(|| Self::discover(&session.read())) {
+                Ok(nodes) => {
+                    *self.catalog.write().unwrap() = Catalog { nodes, discovered_at: Instant::now() };
+                    matched.extend(self.lookup(&unresolved).0);
+                }
+                Err(e) => error!("Failed to browse the server: {}", e),
+            }
+        }
+        matched
+    }
+
+    fn lookup(&self, tags: &[String]) -> (Vec<TagNode>, Vec<String>) {
+        let catalog = self.catalog.read().unwrap();
+        let mut matched = Vec::new();
+        let mut unresolved = Vec::new();
+        for tag in tags {
+            match catalog.nodes.get(tag) {
+                Some(nodes) => matched.extend(nodes.iter().map(|node_id| TagNode { name: tag.clone(), node_id: node_id.clone() })),
+                None => unresolved.push(tag.clone()),
+            }
+        }
+        (matched, unresolved)
+    }
+
+    /// Checks the active server's health on an interval and moves the sessions to the healthiest
+    /// server of the set when it degrades. Transparent sets fail over on the server side, where the
+    /// session's own reconnect transfers subscriptions with TransferSubscriptions.
+    pub fn watch(session_pool: &Arc<SessionPool>) {
+        if session_pool.endpoints.len() < 2 || session_pool.redundancy == RedundancySupport::Transparent {
+            return;
+        }
+        let session_pool = session_pool.clone();
+        tokio::spawn(async move {
+            let period = Duration::from_secs(session_pool.config.health_check_secs.max(1));
+            let mut health_timer = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                health_timer.tick().await;
+                if session_pool.disconnected.load(Ordering::SeqCst) {
+                    break;
+                }
+                let session_pool = session_pool.clone();
+                let _ = tokio::task::spawn_blocking(move || session_pool.check_health()).await;
+            }
+        });
+    }
+
+    /// Whether each pooled session is connected, in pool order.
+    pub fn session_states(&self) -> Vec<bool> {
+        (0..self.sessions.len()).map(|index| self.session(index).read().is_connected()).collect()
+    }
+
+    pub fn active_endpoint(&self) -> &str {
+        &self.endpoints[self.active_endpoint.load(Ordering::SeqCst)]
+    }
+
+    /// Closes every session of the pool, standby ones included, so the servers free them at once
+    /// rather than when they time out. Blocks; the pool cannot be used afterwards.
+    pub fn disconnect(&self) {
+        self.disconnected.store(true, Ordering::SeqCst);
+        for pooled in &self.sessions {
+            pooled.run_loop.lock().unwrap().take();
+            pooled.session.read().unwrap().read().disconnect();
+        }
+        for (_, session) in self.standby.lock().unwrap().drain() {
+            session.read().disconnect();
+        }
+        info!("Closed the OPC UA sessions to {}", self.config.name);
+    }
+
+    fn check_health(&self) {
+        let active = self.active_endpoint.load(Ordering::SeqCst);
+        let session = self.session(0);
+        let active_level = {
+            let session = session.read();
+            if session.is_connected() { Self::read_service_level(&session) } else { 0 }
+        };
+        if active_level >= self.config.min_service_level {
+            // Only warm and hot sets keep backup sessions to look after; a cold backup is not worth a
+            // session every check while the active server is healthy.
+            if self.keeps_standby() {
+                self.probe_standby(active);
+            }
+            return;
+        }
+
+        info!("{} at {} reports service level {}, probing the redundant servers", self.config.name, self.endpoints[active], active_level);
+        let best = self.probe_standby(active)
+            .into_iter()
+            .filter(|(_, level)| *level > active_level)
+            .max_by_key(|(_, level)| *level);
+        let Some((endpoint, level)) = best else {
+            error!("No server of {} is healthier than the active one", self.config.name);
+            return;
+        };
+        info!("Failing {} over to {} (service level {})", self.config.name, self.endpoints[endpoint], level);
+        match self.fail_over(endpoint) {
+            Ok(()) => info!("{} now collects from {}", self.config.name, self.endpoints[endpoint]),
+            Err(e) => error!("Failover of {} to {} failed: {}", self.config.name, self.endpoints[endpoint], e),
+        }
+    }
+
+    /// Reads the service level of every backup server. Warm and hot sets keep the backup sessions
+    /// open between checks; cold ones are only connected for the probe.
+    fn probe_standby(&self, active: usize) -> Vec<(usize, u8)> {
+        let keep_open = self.keeps_standby();
+        let mut standby = self.standby.lock().unwrap();
+        let mut levels = Vec::new();
+        for (endpoint, url) in self.endpoints.iter().enumerate() {
+            if endpoint == active {
+                continue;
+            }
+            let session = match standby.get(&endpoint) {
+                Some(session) => session.clone(),
+                None => match Self::connect_session(&self.config, url) {
+                    Ok(session) => session,
+                    Err(_) => continue,
+                },
+            };
+            let level = Self::read_service_level(&session.read());
+            if keep_open && level > 0 {
+                standby.insert(endpoint, session);
+            } else {
+                standby.remove(&endpoint);
+                session.read().disconnect();
+            }
+            levels.push((endpoint, level));
+        }
+        levels
+    }
+
+    fn keeps_standby(&self) -> bool {
+        matches!(
+            self.redundancy,
+            RedundancySupport::Warm | RedundancySupport::Hot | RedundancySupport::HotAndMirrored
+        )
+    }
+
+    /// Moves every pooled session to `endpoint`, recreating its subscriptions there. Every session is
+    /// connected before any is moved, so a server that refuses one leaves the pool where it was.
+    ///
+    /// Subscriptions are rebuilt from the client-side state even in hot sets, whose servers could
+    /// TransferSubscriptions: the `opcua` client only delivers notifications of subscriptions it knows on
+    /// that session object, and it can only transfer into one when reconnecting it to the same server.
+    fn fail_over(&self, endpoint: usize) -> Result<(), Box<dyn std::error::Error>> {
+        let url = &self.endpoints[endpoint];
+        let mut new_sessions: Vec<Arc<RwLock<Session>>> = self.standby.lock().unwrap().remove(&endpoint).into_iter().collect();
+        while new_sessions.len() < self.sessions.len() {
+            match Self::connect_session(&self.config, url) {
+                Ok(session) => new_sessions.push(session),
+                Err(e) => {
+                    for session in new_sessions {
+                        session.read().disconnect();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        for (pooled, new_session) in self.sessions.iter().zip(new_sessions) {
+            let old_session = pooled.session.read().unwrap().clone();
+            // Stop the old run loop first so it does not keep retrying the failed server.
+            pooled.run_loop.lock().unwrap().take();
+            Self::recreate_subscriptions(&old_session.read(), &new_session.read());
+            old_session.read().disconnect();
+            *pooled.session.write().unwrap() = new_session.clone();
+            *pooled.run_loop.lock().unwrap() = Some(Session::run_async(new_session));
+        }
+        self.active_endpoint.store(endpoint, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn recreate_subscriptions(old_session: &Session, new_session: &Session) {
+        let subscription_state = old_session.subscription_state();
+        let subscription_state = subscription_state.read();
+        for subscription_id in subscription_state.subscription_ids().unwrap_or_default() {
+            let Some(subscription) = subscription_state.get(subscription_id) else {
+                continue;
+            };
+            let new_subscription_id = match new_session.create_subscription(
+                subscription.publishing_interval(),
+                subscription.lifetime_count(),
+                subscription.max_keep_alive_count(),
+                subscription.max_notifications_per_publish(),
+                subscription.priority(),
+                subscription.publishing_enabled(),
+                SharedCallback(subscription.notification_callback()),
+            ) {
+                Ok(new_subscription_id) => new_subscription_id,
+                Err(e) => {
+                    error!("Failed to recreate subscription {}: {}", subscription_id, e);
+                    continue;
+                }
+            };
+            let items_to_create: Vec<MonitoredItemCreateRequest> = subscription
+                .monitored_items()
+                .values()
+                .map(|item| MonitoredItemCreateRequest {
+                    item_to_monitor: item.item_to_monitor().clone(),
+                    monitoring_mode: item.monitoring_mode(),
+                    requested_parameters: MonitoringParameters {
+                        client_handle: item.client_handle(),
+                        sampling_interval: item.sampling_interval(),
+                        filter: ExtensionObject::null(),
+                        queue_size: item.queue_size() as u32,
+                        discard_oldest: item.discard_oldest(),
+                    },
+                })
+                .collect();
+            for chunk in items_to_create.chunks(MONITORED_ITEMS_PER_CALL) {
+                if let Err(e) = new_session.create_monitored_items(new_subscription_id, TimestampsToReturn::Both, chunk) {
+                    error!("Failed to recreate monitored items of subscription {}: {}", subscription_id, e);
+                }
+            }
+            info!("Recreated subscription {} as {} with {} monitored items", subscription_id, new_subscription_id, items_to_create.len());
+        }
+    }
+
+    fn read_service_level(session: &Session) -> u8 {
+        let node: ReadValueId = NodeId::from(&VariableId::Server_ServiceLevel).into();
+        match session.read(&[node], TimestampsToReturn::Neither, 0.0) {
+            Ok(values) => match values.first().and_then(|value| value.value.as_ref()) {
+                Some(Variant::Byte(level)) => *level,
+                // Servers without redundancy support may not expose a service level.
+                _ => HEALTHY_SERVICE_LEVEL,
+            },
+            Err(_) => 0,
+        }
+    }
+
+    fn read_redundancy_support(session: &Session) -> RedundancySupport {
+        let node: ReadValueId = NodeId::from(&VariableId::Server_ServerRedundancy_RedundancySupport).into();
+        let values = session.read(&[node], TimestampsToReturn::Neither, 0.0).unwrap_or_default();
+        match values.first().and_then(|value| value.value.as_ref()) {
+            Some(Variant::Int32(1)) => RedundancySupport::Cold,
+            Some(Variant::Int32(2)) => RedundancySupport::Warm,
+            Some(Variant::Int32(3)) => RedundancySupport::Hot,
+            Some(Variant::Int32(4)) => RedundancySupport::Transparent,
+            Some(Variant::Int32(5)) => RedundancySupport::HotAndMirrored,
+            _ => RedundancySupport::None,
+        }
+    }
+
+    fn read_server_limits(session: &Session) -> (usize, usize) {
+        let nodes_to_read: Vec<ReadValueId> = [MAX_SESSIONS_NODE, MAX_SUBSCRIPTIONS_PER_SESSION_NODE]
+            .iter()
+            .map(|id| NodeId::new(0, *id).into())
+            .collect();
+        let limit = |value: &DataValue| match &value.value {
+            Some(Variant::UInt32(limit)) => *limit as usize,
+            _ => 0,
+        };
+        match session.read(&nodes_to_read, TimestampsToReturn::Neither, 0.0) {
+            Ok(values) if values.len() == 2 => (limit(&values[0]), limit(&values[1])),
+            _ => (0, 0),
+        }
+    }
+
+    fn discover(session: &Session) -> Result<HashMap<String, Vec<NodeId>>, StatusCode> {
+        let mut nodes = HashMap::new();
+        Self::browse_nodes(session, ObjectId::RootFolder.into(), &mut nodes)?;
+        Ok(nodes)
+    }
+
+    fn browse_nodes(session: &Session, node_id: NodeId, nodes: &mut HashMap<String, Vec<NodeId>>) -> Result<(), StatusCode> {
+        let browse_description = BrowseDescription {
+            node_id: node_id.clone(),
+            browse_direction: BrowseDirection::Forward,
+            reference_type_id: ReferenceTypeId::Organizes.into(),
+            include_subtypes: true,
+            node_class_mask: 0,
+            result_mask: BrowseDescriptionResultMask::all().bits(),
+        };
+
+        let results = session.browse(&[browse_description]).inspect_err(|e| {
+            error!("Failed to browse node: {}", e);
+        })?;
+
+        if let Some(results_vec) = results {
+            for result in results_vec.iter() {
+                if let Some(references) = &result.references {
+                    for reference in references {
+                        let child_node_id = reference.node_id.node_id.clone();
+                        let device_names: Vec<String> = (1..=102).map(|i| format!("Device{}", i)).collect();
+                        // if reference.display_name.text.to_string().contains("Device5000") {
+                        if device_names.iter().any(|device| reference.display_name.text.to_string().contains(device)) {
+                            Self::browse_tags(session, child_node_id, nodes)?;
+                        } else {
+                            Self::browse_nodes(session, child_node_id, nodes)?;
+                        }
+                    }
+                }
+            }
+        }
+
+      Ok(())
+    }
+
+    fn browse_tags(session: &Session, channel_node_id: NodeId, nodes: &mut HashMap<String, Vec<NodeId>>) -> Result<(), StatusCode> {
+        let browse_description = BrowseDescription {
+            node_id: channel_node_id.clone(),
+            browse_direction: BrowseDirection::Forward,
+            reference_type_id: ReferenceTypeId::HasComponent.into(),
+            include_subtypes: true,
+            node_class_mask: 0,
+            result_mask: BrowseDescriptionResultMask::all().bits(),
+        };
+
+        let results = session.browse(&[browse_description])?;
+        if let Some(results_vec) = results {
+            for result in results_vec.iter() {
+                if let Some(references) = &result.references {
+                    for reference in references {
+                        let tag_name = reference.display_name.text.to_string();
+                        nodes.entry(tag_name).or_default().push(reference.node_id.node_id.clone());
+                    }
+                }
+            }
+        }
+      Ok(())
+    }
+}
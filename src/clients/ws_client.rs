@@ -1,6 +1,7 @@
 use log::error;
 use log::info;
 use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderName;
 use tokio_tungstenite::client_async;
 use tokio_native_tls::TlsStream;
 use std::error::Error;
@@ -9,47 +10,39 @@ use futures_util::StreamExt;
 use futures_util::SinkExt;
 use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
 use serde_json::Value;
-use std::thread::sleep;
-use std::time::Duration;
+use std::collections::VecDeque;
+
+pub const TAG_CONTROL_MSG_TYPE: &str = "TAG_CONTROL";
 
 pub struct WebSocketClient {
     backend_url: String,
-    header_key: &'static str,
+    header_key: String,
     header_value: String,
     ws_client: Option<tokio_tungstenite::WebSocketStream<TlsStream<tokio::net::TcpStream>>>,
+    pending_control: VecDeque<Value>,
 }
 
 impl WebSocketClient {
-    pub fn new(backend_url: String, header_key: &'static str, header_value: String) -> Self {
+    pub fn new(backend_url: String, header_key: String, header_value: String) -> Self {
         WebSocketClient {
             backend_url,
             header_key,
             header_value,
             ws_client: None,
+            pending_control: VecDeque::new(),
         }
     }
 
-    pub async fn connect_to_c2_server(&mut self) -> Result<(), Box<dyn Error>> {
-        loop{
-        let  ws_url: Url = self.backend_url.parse()?;
+    /// A single connection attempt, leaving retries and endpoint choice to the caller.
+    pub async fn try_connect(&mut self) -> Result<(), Box<dyn Error>> {
+        let ws_url: Url = self.backend_url.parse()?;
         let tls_stream = self.open_tls_stream(&ws_url).await?;
         let mut request = ws_url.into_client_request()?;
+        request.headers_mut().insert(self.header_key.parse::<HeaderName>()?, self.header_value.parse()?);
 
-        let headers = request.headers_mut();
-        headers.insert(self.header_key, self.header_value.parse().unwrap());
-
-        match client_async(request, tls_stream).await {
-            Ok((ws_client, _)) => {
-                self.ws_client = Some(ws_client);
-                break;
-            }
-            Err(err) => {
-                error!("Failed to connect to C2 Server: {}", err);
-                sleep(Duration::from_secs(5));
-            }
-        }
-    }
-        info!("Connected to C2 Server");
+        let (ws_client, _) = client_async(request, tls_stream).await?;
+        self.ws_client = Some(ws_client);
+        info!("Connected to C2 Server {}", self.backend_url);
         Ok(())
     }
 
@@ -69,46 +62,111 @@ impl WebSocketClient {
         info!("Message sent");
         Ok(())
     }
-    pub async fn receive_response(&mut self) -> Result<Vec<String>, Box<dyn Error>> {
+    /// Reads C2's batches for the last tag request up to the one marked final; control messages
+    /// arriving in between are kept for `receive_control`.
+    pub async fn receive_response(&mut self) -> Result<TagResponse, Box<dyn Error>> {
         let ws_client = self.ws_client.as_mut().ok_or("WebSocket client not initialized")?;
-    
-        let mut tags_vec = Vec::new();
-        while let Some(Ok(tokio_tungstenite::tungstenite::Message::Text(response))) = ws_client.next().await {
-            let without_32nd = format!("{}{}", &response[..31], &response[32..]);
-            let len = without_32nd.len();
-            let modified_string = format!("{}{}", &without_32nd[..len - 2], &without_32nd[len - 1..]);
-    
-            let outer_response: Value = serde_json::from_str(&modified_string)?;
-            if let Some(inner_msg) = outer_response["msg"].as_object() {
-                if let Some(data_list) = inner_msg["data"].as_array() {
-                    for item in data_list {
-                        if let Some(tag_name) = item["tagName"].as_str() {
-                            tags_vec.push(tag_name.to_string());
-                        }
+
+        let mut response = TagResponse::default();
+        while let Some(message) = ws_client.next().await {
+            match message? {
+                WsMessage::Text(text) => {
+                    if let Some(control) = Self::parse_control(&text) {
+                        self.pending_control.push_back(control);
+                        continue;
                     }
-                }
-                if let Some(final_batch) = inner_msg.get("finalBatch").and_then(Value::as_bool) {
-                    if final_batch {
-                        println!("Final Batch: {}", final_batch.to_string());
-                        break;
+                    response.push(&text)?;
+                    if response.final_batch {
+                        info!("Final batch received, {} tag records in total", response.records.len());
+                        return Ok(response);
                     }
                 }
+                WsMessage::Close(_) => break,
+                _ => {}
             }
         }
-    
-        if tags_vec.is_empty() {
-            Ok(vec!["Server didn't respond".to_string()])
+        Err(format!("C2 connection closed after {} tag records, before the final batch", response.records.len()).into())
+    }
+
+    /// Waits for the next control message from C2, including any that arrived during a tag request.
+    pub async fn receive_control(&mut self) -> Result<Value, Box<dyn Error>> {
+        if let Some(control) = self.pending_control.pop_front() {
+            return Ok(control);
+        }
+        let ws_client = self.ws_client.as_mut().ok_or("WebSocket client not initialized")?;
+        while let Some(message) = ws_client.next().await {
+            if let WsMessage::Text(text) = message? {
+                if let Some(control) = Self::parse_control(&text) {
+                    return Ok(control);
+                }
+                error!("Ignoring unexpected message from C2");
+            }
+        }
+        Err("C2 connection closed".into())
+    }
+
+    pub async fn send_control_reply(&mut self, reply: String) -> Result<(), Box<dyn Error>> {
+        let ws_client = self.ws_client.as_mut().ok_or("WebSocket client not initialized")?;
+        ws_client.send(WsMessage::Text(reply)).await?;
+        Ok(())
+    }
+
+    fn parse_control(text: &str) -> Option<Value> {
+        let message: Value = serde_json::from_str(text).ok()?;
+        if message["msg_type"].as_str() == Some(TAG_CONTROL_MSG_TYPE) {
+            Some(message)
         } else {
-            Ok(tags_vec)
+            None
         }
     }
 
     pub async fn push_to_c2(&mut self,universal_buffer : Vec<u8>)-> Result<(), Box<dyn Error>>{
         //info!("{}",universal_buffer.len());
         let ws_client = self.ws_client.as_mut().ok_or("WebSocket client not initialized")?;
-        if let Err(e) = ws_client.send(WsMessage::Binary(universal_buffer)).await {
-            error!("Failed to send Universal data: {:?}", e);
+        ws_client.send(WsMessage::Binary(universal_buffer)).await?;
+        Ok(())
+    }
+}
+
+/// The tag records C2 sent for one request, gathered from its batches.
+#[derive(Debug, Default)]
+pub struct TagResponse {
+    pub records: Vec<Value>,
+    /// Whether C2 marked the last batch as final, i.e. the records are the whole answer.
+    pub final_batch: bool,
+}
+
+impl TagResponse {
+    /// Adds one batch as C2 sends it.
+    pub fn push(&mut self, response: &str) -> Result<(), Box<dyn Error>> {
+        // C2 pads each batch with a stray 32nd and second to last character.
+        let unexpected = || format!("Unexpected tag response from C2: {}", response);
+        let without_32nd = format!("{}{}", response.get(..31).ok_or_else(unexpected)?, response.get(32..).ok_or_else(unexpected)?);
+        let len = without_32nd.len();
+        let modified_string = format!(
+            "{}{}",
+            without_32nd.get(..len.saturating_sub(2)).ok_or_else(unexpected)?,
+            without_32nd.get(len.saturating_sub(1)..).ok_or_else(unexpected)?
+        );
+
+        let outer_response: Value = serde_json::from_str(&modified_string)?;
+        if let Some(inner_msg) = outer_response["msg"].as_object() {
+            if let Some(data_list) = inner_msg.get("data").and_then(Value::as_array) {
+                self.records.extend(data_list.iter().cloned());
+            }
+            self.final_batch = inner_msg.get("finalBatch").and_then(Value::as_bool).unwrap_or(false);
         }
         Ok(())
     }
+
+    /// Adds the records of the next page of a paged request.
+    pub fn extend(&mut self, page: TagResponse) {
+        self.records.extend(page.records);
+        self.final_batch = page.final_batch;
+    }
+
+    /// The latest modification time C2 reported among the records.
+    pub fn newest_modified(&self) -> Option<i64> {
+        self.records.iter().filter_map(|record| record["lastModified"].as_i64()).max()
+    }
 }
@@ -1,98 +1,945 @@
-use serde::{Deserialize, Serialize};
-use serde_json::from_reader;
-use std::fs::File;
-use std::error::Error;
+use serde::de::{DeserializeOwned, Error as _};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use log::LevelFilter;
 use base64::encode;
 use serde::Serializer;
 use serde_json::to_string;
-use lazy_static::lazy_static;
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Configuration {
+    #[serde(default)]
     pub base: String,
+    #[serde(default)]
+    pub c2_endpoints: Vec<String>,
+    #[serde(default)]
+    pub c2_mode: C2Mode,
+    #[serde(default)]
     pub header: HeaderConfig,
+    #[serde(default)]
     pub message: Message,
-    pub opc: OpcConfig,
+    #[serde(default)]
+    pub opc: Option<OpcConfig>,
+    #[serde(default)]
+    pub opc_sources: Vec<OpcConfig>,
+    #[serde(default = "default_num_workers", deserialize_with = "from_text")]
     pub num_producers : usize,
-    pub num_consumers : usize
+    #[serde(default = "default_num_workers", deserialize_with = "from_text")]
+    pub num_consumers : usize,
+    #[serde(default)]
+    pub tag_sync: TagSyncConfig,
+    #[serde(default)]
+    pub partition_strategy: PartitionStrategy,
+    /// Where consumers deliver batches; the C2 endpoints when empty.
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+    /// Records per batch handed to the sinks.
+    #[serde(default = "default_batch_size", deserialize_with = "from_text")]
+    pub batch_size: usize,
+    /// Sends a partial batch once this long has passed since the last one; 0 waits for full batches.
+    #[serde(default = "default_flush_interval", deserialize_with = "from_text")]
+    pub flush_interval_ms: u64,
+    /// Most verbose level written to the log; `logging` decides where it goes.
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub subscription: SubscriptionConfig,
+    /// Seconds between checks of the configuration file for changes; 0 reloads only on SIGHUP.
+    #[serde(default = "default_config_watch_secs", deserialize_with = "from_text")]
+    pub config_watch_secs: u64,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    #[serde(default)]
+    pub supervision: SupervisionConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    /// Client-side filters between the subscriptions and the queue; the first group matching a tag applies.
+    #[serde(default)]
+    pub filters: Vec<FilterGroup>,
+    /// Window statistics per tag, sent to C2; the first group matching a tag applies.
+    #[serde(default)]
+    pub aggregations: Vec<AggregationGroup>,
+    #[serde(default)]
+    pub calculated_tags: Vec<CalculatedTag>,
+    /// Scaling and unit conversion of collected tags; the first group matching a tag applies.
+    #[serde(default)]
+    pub scaling: Vec<ScalingGroup>,
+    /// Reads each subscribed tag's EURange and EngineeringUnits properties from the server.
+    #[serde(default = "default_true", deserialize_with = "from_text")]
+    pub read_eu_properties: bool,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+fn default_batch_size() -> usize {
+    200
+}
+
+fn default_flush_interval() -> u64 {
+    1000
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_config_watch_secs() -> u64 {
+    5
+}
+
+/// Where the log goes and in which format.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct LoggingConfig {
+    /// Appended to; empty writes to standard output.
+    #[serde(default = "default_log_file")]
+    pub file: String,
+    #[serde(default)]
+    pub format: LogFormat,
+    /// OTLP gRPC endpoint of an OpenTelemetry collector for the spans, e.g. `http://localhost:4317`;
+    /// empty exports nothing.
+    #[serde(default)]
+    pub otlp_endpoint: String,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            file: default_log_file(),
+            format: LogFormat::default(),
+            otlp_endpoint: String::new(),
+            service_name: default_service_name(),
+        }
+    }
+}
+
+fn default_log_file() -> String {
+    "display.log.txt".to_string()
+}
+
+fn default_service_name() -> String {
+    "opc_client".to_string()
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// One line per record, with the spans it happened in.
+    #[default]
+    Text,
+    /// One JSON object per record, with the current span and the list of spans as fields.
+    Json,
+}
+
+/// How long SIGINT or SIGTERM waits for queued data to be delivered, and where the rest is kept.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ShutdownConfig {
+    /// Seconds consumers get to empty the queue into the sinks.
+    #[serde(default = "default_shutdown_timeout", deserialize_with = "from_text")]
+    pub timeout_secs: u64,
+    /// Undelivered records are written here as a `replay`-able recording.
+    #[serde(default = "default_unsent_directory")]
+    pub unsent_directory: String,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig {
+            timeout_secs: default_shutdown_timeout(),
+            unsent_directory: default_unsent_directory(),
+        }
+    }
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+fn default_unsent_directory() -> String {
+    "unsent".to_string()
+}
+
+/// How failed tasks are restarted: each on its own, at most `max_restarts` times within `window_secs`.
+/// A task that fails more often stops the client.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SupervisionConfig {
+    #[serde(default = "default_max_restarts", deserialize_with = "from_text")]
+    pub max_restarts: usize,
+    #[serde(default = "default_restart_window", deserialize_with = "from_text")]
+    pub window_secs: u64,
+    /// Wait before the first restart within the window; it doubles with every further one.
+    #[serde(default = "default_restart_backoff", deserialize_with = "from_text")]
+    pub backoff_ms: u64,
+}
+
+impl Default for SupervisionConfig {
+    fn default() -> Self {
+        SupervisionConfig {
+            max_restarts: default_max_restarts(),
+            window_secs: default_restart_window(),
+            backoff_ms: default_restart_backoff(),
+        }
+    }
+}
+
+fn default_max_restarts() -> usize {
+    5
+}
+
+fn default_restart_window() -> u64 {
+    60
+}
+
+fn default_restart_backoff() -> u64 {
+    1000
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TelemetryConfig {
+    /// Address of the HTTP server for `/metrics`, e.g. `0.0.0.0:9464`; empty turns it off.
+    #[serde(default)]
+    pub listen: String,
+    /// Reports the age of every tag's last value, one series per tag.
+    #[serde(default = "default_true", deserialize_with = "from_text")]
+    pub per_tag_metrics: bool,
+    /// Sends the source, server, receive and dequeue times of every message to C2 in `Universal.timings`.
+    #[serde(default, deserialize_with = "from_text")]
+    pub universal_latency: bool,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig { listen: String::new(), per_tag_metrics: true, universal_latency: false }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Thresholds past which `/healthz` reports the client as unhealthy; 0 turns an age check off.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct HealthConfig {
+    /// Share of the queue capacity in use, from 0 to 1.
+    #[serde(default = "default_max_queue_fill", deserialize_with = "from_text")]
+    pub max_queue_fill: f64,
+    /// Seconds without a delivered batch while records are waiting in the queue.
+    #[serde(default = "default_max_push_age", deserialize_with = "from_text")]
+    pub max_push_age_secs: u64,
+    /// Seconds since tag sync last reached C2.
+    #[serde(default, deserialize_with = "from_text")]
+    pub max_tag_sync_age_secs: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            max_queue_fill: default_max_queue_fill(),
+            max_push_age_secs: default_max_push_age(),
+            max_tag_sync_age_secs: 0,
+        }
+    }
+}
+
+fn default_max_queue_fill() -> f64 {
+    0.9
+}
+
+fn default_max_push_age() -> u64 {
+    120
+}
+
+/// When a tag counts as stale: no value for `stale_factor` times its expected update period, which is
+/// the tag's `expectedUpdatePeriod` from C2 or else `default_period_secs`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct WatchdogConfig {
+    /// Seconds between checks of every tag; 0 turns the watchdog off.
+    #[serde(default = "default_watchdog_interval", deserialize_with = "from_text")]
+    pub check_interval_secs: u64,
+    /// Expected update period of tags C2 gives none; 0 checks only those it does, for staleness.
+    #[serde(default, deserialize_with = "from_text")]
+    pub default_period_secs: u64,
+    #[serde(default = "default_stale_factor", deserialize_with = "from_text")]
+    pub stale_factor: f64,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            check_interval_secs: default_watchdog_interval(),
+            default_period_secs: 0,
+            stale_factor: default_stale_factor(),
+        }
+    }
+}
+
+fn default_watchdog_interval() -> u64 {
+    10
+}
+
+fn default_stale_factor() -> f64 {
+    3.0
+}
+
+/// Filtering for a group of tags, for servers that ignore or lack deadband filters.
+///
+/// Values first pass `min_interval_ms` and `deadband` against the last value that passed, then
+/// `compression`. Values with a Bad status code are never filtered.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FilterGroup {
+    pub name: String,
+    /// Tag name patterns, where `*` matches any text and `?` one character.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Asset name patterns; a tag matches when its name or its asset matches. Both empty match every tag.
+    #[serde(default)]
+    pub assets: Vec<String>,
+    #[serde(default)]
+    pub deadband: Option<Deadband>,
+    /// Least time between two values of a tag by source timestamp; 0 lets every value through.
+    #[serde(default, deserialize_with = "from_text")]
+    pub min_interval_ms: u64,
+    #[serde(default)]
+    pub compression: Option<Compression>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Deadband {
+    /// Drops values that changed by at most `value` in engineering units.
+    Absolute {
+        #[serde(deserialize_with = "from_text")]
+        value: f64,
+    },
+    /// Drops values that changed by at most `value` percent of the last value that passed.
+    Percent {
+        #[serde(deserialize_with = "from_text")]
+        value: f64,
+    },
+}
+
+/// Keeps only the values needed to redraw the signal within `deviation`. A value is held until a
+/// later one shows whether it is needed, so each tag's latest value reaches the sinks late.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub struct Compression {
+    pub algorithm: CompressionAlgorithm,
+    #[serde(deserialize_with = "from_text")]
+    pub deviation: f64,
+    /// Sends the held value once this long has passed since the last one sent; 0 waits for a change.
+    #[serde(default, deserialize_with = "from_text")]
+    pub max_interval_ms: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionAlgorithm {
+    SwingingDoor,
+    BoxcarBackslope,
+}
+
+/// Statistics over windows of a group of tags' values, taken before the filters and sent to C2 as
+/// `Aggregate` messages.
+///
+/// Windows are aligned to the epoch by source timestamp: tumbling when `step_secs` is 0, otherwise a
+/// window of `window_secs` starts every `step_secs`. Only values with a Good status code count.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AggregationGroup {
+    pub name: String,
+    /// Tag name patterns, where `*` matches any text and `?` one character.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Asset name patterns; a tag matches when its name or its asset matches. Both empty match every tag.
+    #[serde(default)]
+    pub assets: Vec<String>,
+    #[serde(deserialize_with = "from_text")]
+    pub window_secs: u64,
+    #[serde(default, deserialize_with = "from_text")]
+    pub step_secs: u64,
+    #[serde(default = "default_aggregate_functions")]
+    pub functions: Vec<AggregateFunction>,
+    /// How long past a window's end, by the client's clock, values for it are still taken.
+    #[serde(default = "default_aggregation_lateness", deserialize_with = "from_text")]
+    pub lateness_ms: u64,
+    /// Sends the raw values too; without them only the aggregates of the group's tags leave the client.
+    #[serde(default = "default_true", deserialize_with = "from_text")]
+    pub raw: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AggregateFunction {
+    Min,
+    Max,
+    Mean,
+    /// Mean with each value weighted by how long it held within the window.
+    TimeWeightedAverage,
+    Count,
+    First,
+    Last,
+    /// Population standard deviation.
+    Stddev,
+}
+
+impl AggregateFunction {
+    pub const ALL: [AggregateFunction; 8] = [
+        AggregateFunction::Min,
+        AggregateFunction::Max,
+        AggregateFunction::Mean,
+        AggregateFunction::TimeWeightedAverage,
+        AggregateFunction::Count,
+        AggregateFunction::First,
+        AggregateFunction::Last,
+        AggregateFunction::Stddev,
+    ];
+
+    /// The name in the configuration and in `AggregateValue.function`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AggregateFunction::Min => "min",
+            AggregateFunction::Max => "max",
+            AggregateFunction::Mean => "mean",
+            AggregateFunction::TimeWeightedAverage => "time_weighted_average",
+            AggregateFunction::Count => "count",
+            AggregateFunction::First => "first",
+            AggregateFunction::Last => "last",
+            AggregateFunction::Stddev => "stddev",
+        }
+    }
+}
+
+fn default_aggregate_functions() -> Vec<AggregateFunction> {
+    AggregateFunction::ALL.to_vec()
+}
+
+fn default_aggregation_lateness() -> u64 {
+    2000
+}
+
+/// A tag computed at the edge from collected tags, or from other calculated tags, and sent as a
+/// `Historical` sensor of its own.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CalculatedTag {
+    pub name: String,
+    /// For example `(Flow1 + Flow2) * 3.6`, `if(Level > 90, 1, 0)` or `rate(Counter)`.
+    pub expression: String,
+}
+
+/// Turns the raw values of a group of tags into engineering values. Tags no group matches are scaled
+/// by the `scaleFactor` and `scaleOffset` C2 gives them, if any.
+///
+/// With `raw_low` and `raw_high` the raw range is mapped onto the engineering range, taken from
+/// `eu_low` and `eu_high` or else the tag's EURange on the server. Otherwise values are multiplied by
+/// `factor` and `offset` is added, falling back to C2's scale. `square_root` takes the root of the
+/// raw value's share of its range, or of the raw value, first, as for flow from differential pressure.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ScalingGroup {
+    pub name: String,
+    /// Tag name patterns, where `*` matches any text and `?` one character.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Asset name patterns; a tag matches when its name or its asset matches. Both empty match every tag.
+    #[serde(default)]
+    pub assets: Vec<String>,
+    #[serde(default)]
+    pub function: ScalingFunction,
+    #[serde(default, deserialize_with = "option_from_text")]
+    pub raw_low: Option<f64>,
+    #[serde(default, deserialize_with = "option_from_text")]
+    pub raw_high: Option<f64>,
+    #[serde(default, deserialize_with = "option_from_text")]
+    pub eu_low: Option<f64>,
+    #[serde(default, deserialize_with = "option_from_text")]
+    pub eu_high: Option<f64>,
+    #[serde(default, deserialize_with = "option_from_text")]
+    pub factor: Option<f64>,
+    #[serde(default, deserialize_with = "option_from_text")]
+    pub offset: Option<f64>,
+    /// Limits scaled values to the engineering range.
+    #[serde(default, deserialize_with = "from_text")]
+    pub clamp: bool,
+    /// Units of the scaled values, ahead of those from C2 and the server.
+    #[serde(default)]
+    pub units: Option<String>,
+    /// Converts the scaled values to these units.
+    #[serde(default)]
+    pub convert_to: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ScalingFunction {
+    #[default]
+    Linear,
+    SquareRoot,
+}
+
+/// Parameters of the OPC UA subscriptions producers create.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SubscriptionConfig {
+    #[serde(default = "default_publishing_interval", deserialize_with = "from_text")]
+    pub publishing_interval_ms: f64,
+    #[serde(default = "default_lifetime_count", deserialize_with = "from_text")]
+    pub lifetime_count: u32,
+    #[serde(default = "default_max_keep_alive_count", deserialize_with = "from_text")]
+    pub max_keep_alive_count: u32,
+    /// 0 leaves the number of notifications per publish unlimited.
+    #[serde(default, deserialize_with = "from_text")]
+    pub max_notifications_per_publish: u32,
+    #[serde(default, deserialize_with = "from_text")]
+    pub priority: u8,
+    /// Sampling interval of tags C2 gives none for; negative uses the publishing interval.
+    #[serde(default = "default_sampling_interval", deserialize_with = "from_text")]
+    pub default_sampling_interval_ms: f64,
+}
+
+impl Default for SubscriptionConfig {
+    fn default() -> Self {
+        SubscriptionConfig {
+            publishing_interval_ms: default_publishing_interval(),
+            lifetime_count: default_lifetime_count(),
+            max_keep_alive_count: default_max_keep_alive_count(),
+            max_notifications_per_publish: 0,
+            priority: 0,
+            default_sampling_interval_ms: default_sampling_interval(),
+        }
+    }
+}
+
+fn default_publishing_interval() -> f64 {
+    1000.0
+}
+
+fn default_lifetime_count() -> u32 {
+    90
+}
+
+fn default_max_keep_alive_count() -> u32 {
+    30
+}
+
+fn default_sampling_interval() -> f64 {
+    -1.0
+}
+
+fn default_num_workers() -> usize {
+    5
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct HeaderConfig {
+    #[serde(default = "default_header_key")]
     pub key: String,
+    #[serde(default)]
     pub username: String,
+    #[serde(default)]
     pub password: String,
 }
-#[derive(Deserialize, Serialize, Debug)]
+
+impl Default for HeaderConfig {
+    fn default() -> Self {
+        HeaderConfig { key: default_header_key(), username: String::new(), password: String::new() }
+    }
+}
+
+fn default_header_key() -> String {
+    "Authorization".to_string()
+}
+
+/// The tag request C2 expects when none is configured: every tag definition, by name.
+impl Default for Message {
+    fn default() -> Self {
+        Message { msg_type: "GET_TAG_DATA".to_string(), filter: Filter::default() }
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter {
+            date_range: DateRange::default(),
+            id: 0,
+            r#type: TagType::default(),
+            last_modified: 0,
+            asset_name: String::new(),
+            starting_row: -1,
+            max_record_count: -1,
+            order_by_property: "tagName".to_string(),
+            descending: false,
+            filter_deleted: false,
+        }
+    }
+}
+
+impl Default for TagType {
+    fn default() -> Self {
+        TagType { id: 4302 }
+    }
+}
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
 pub struct Message {
     pub msg_type: String,
     #[serde(serialize_with = "serialize_filter_as_string")]
     pub filter: Filter,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
 pub struct Filter {
     #[serde(rename = "dateRange")]
     pub date_range: DateRange,
+    #[serde(deserialize_with = "from_text")]
     pub id: i32,
     #[serde(rename = "type")]
     pub r#type: TagType,
-    #[serde(rename = "lastModified")]
+    #[serde(rename = "lastModified", deserialize_with = "from_text")]
     pub last_modified: i64,
     #[serde(rename = "assetName")]
     pub asset_name: String,
-    #[serde(rename = "startingRow")]
+    #[serde(rename = "startingRow", deserialize_with = "from_text")]
     pub starting_row: i32,
-    #[serde(rename = "maxRecordCount")]
+    #[serde(rename = "maxRecordCount", deserialize_with = "from_text")]
     pub max_record_count: i32,
     #[serde(rename = "orderByProperty")]
     pub order_by_property: String,
+    #[serde(deserialize_with = "from_text")]
     pub descending: bool,
-    #[serde(rename = "filterDeleted")]
+    #[serde(rename = "filterDeleted", deserialize_with = "from_text")]
     pub filter_deleted: bool,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
 pub struct DateRange {
+    #[serde(deserialize_with = "from_text")]
     pub id: i32,
+    #[serde(deserialize_with = "from_text")]
     pub duration: i32,
+    #[serde(deserialize_with = "from_text")]
     pub selection: i32,
-    #[serde(rename = "fromDate")]
+    #[serde(rename = "fromDate", deserialize_with = "from_text")]
     pub from_date: i64,
-    #[serde(rename = "toDate")]
+    #[serde(rename = "toDate", deserialize_with = "from_text")]
     pub to_date: i64,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct TagType {
+    #[serde(deserialize_with = "from_text")]
     pub id: i32,
 }
 
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct OpcConfig {
+    #[serde(default = "default_opc_source_name")]
+    pub name: String,
     pub url: String,
+    /// Endpoint URLs of the other servers in this source's redundant server set.
+    #[serde(default)]
+    pub redundant_urls: Vec<String>,
+    /// Seconds between checks of the active server's ServiceLevel when redundant servers are configured.
+    #[serde(default = "default_health_check_secs", deserialize_with = "from_text")]
+    pub health_check_secs: u64,
+    /// ServiceLevel below which the sessions move to a healthier server of the set.
+    #[serde(default = "default_min_service_level", deserialize_with = "from_text")]
+    pub min_service_level: u8,
+    /// Blank for an anonymous session.
+    #[serde(default)]
     pub username: String,
+    #[serde(default)]
     pub password: String,
+    #[serde(default = "default_security_setting")]
+    pub security_policy: String,
+    #[serde(default = "default_security_setting")]
+    pub security_mode: String,
+    /// Producers for this source; `num_producers` when absent.
+    #[serde(default, deserialize_with = "option_from_text")]
+    pub num_producers: Option<usize>,
+    #[serde(default = "default_session_pool_size", deserialize_with = "from_text")]
+    pub session_pool_size: usize,
+    /// Client-side cap on subscriptions per session; 0 defers to the server's limit.
+    #[serde(default, deserialize_with = "from_text")]
+    pub max_subscriptions_per_session: usize,
+}
+
+fn default_session_pool_size() -> usize {
+    1
+}
+
+fn default_health_check_secs() -> u64 {
+    10
+}
+
+fn default_min_service_level() -> u8 {
+    200
+}
+
+fn default_opc_source_name() -> String {
+    "default".to_string()
+}
+
+fn default_security_setting() -> String {
+    "None".to_string()
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TagSyncConfig {
+    #[serde(default = "default_tag_sync_interval", deserialize_with = "from_text")]
+    pub interval_secs: u64,
+    #[serde(default = "default_tag_sync_page_size", deserialize_with = "from_text")]
+    pub page_size: i32,
+}
+
+impl Default for TagSyncConfig {
+    fn default() -> Self {
+        TagSyncConfig {
+            interval_secs: default_tag_sync_interval(),
+            page_size: default_tag_sync_page_size(),
+        }
+    }
+}
+
+fn default_tag_sync_interval() -> u64 {
+    300
+}
+
+fn default_tag_sync_page_size() -> i32 {
+    1000
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PartitionStrategy {
+    #[default]
+    RoundRobin,
+    ByAsset,
+    ByChangeRate,
+    ConsistentHash,
+}
+
+/// How consumers spread batches over the C2 endpoints.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum C2Mode {
+    /// Everything goes to the first healthy endpoint in configured order.
+    #[default]
+    Failover,
+    /// Each consumer connects to the next healthy endpoint in turn.
+    RoundRobin,
+    /// Every batch goes to every endpoint.
+    FanOut,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkConfig {
+    C2,
+    Mqtt(MqttSinkConfig),
+    SparkplugB(SparkplugSinkConfig),
+    Kafka(KafkaSinkConfig),
+    Http(HttpSinkConfig),
+    File(FileSinkConfig),
+    Recorder(RecorderSinkConfig),
+    Stdout,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MqttSinkConfig {
+    pub host: String,
+    #[serde(default = "default_mqtt_port", deserialize_with = "from_text")]
+    pub port: u16,
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    /// `{tag}` in the topic publishes each sample on its own topic; otherwise a batch is one message.
+    #[serde(default = "default_mqtt_topic")]
+    pub topic: String,
+    #[serde(default = "default_mqtt_qos", deserialize_with = "from_text")]
+    pub qos: u8,
+    #[serde(default = "default_mqtt_keep_alive", deserialize_with = "from_text")]
+    pub keep_alive_secs: u64,
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_client_id() -> String {
+    "opc_client".to_string()
+}
+
+fn default_mqtt_topic() -> String {
+    "opc/{tag}".to_string()
+}
+
+fn default_mqtt_qos() -> u8 {
+    1
+}
+
+fn default_mqtt_keep_alive() -> u64 {
+    30
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SparkplugSinkConfig {
+    pub host: String,
+    #[serde(default = "default_mqtt_port", deserialize_with = "from_text")]
+    pub port: u16,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    #[serde(default = "default_mqtt_keep_alive", deserialize_with = "from_text")]
+    pub keep_alive_secs: u64,
+    #[serde(default = "default_sparkplug_group")]
+    pub group_id: String,
+    /// Also used as the MQTT client id; one edge node is published by one client.
+    #[serde(default = "default_mqtt_client_id")]
+    pub edge_node_id: String,
+    /// Device for tags without an asset name; tagged values are published under their asset.
+    #[serde(default = "default_sparkplug_device")]
+    pub default_device: String,
+}
+
+fn default_sparkplug_group() -> String {
+    "opc".to_string()
+}
+
+fn default_sparkplug_device() -> String {
+    "opc".to_string()
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct KafkaSinkConfig {
+    pub brokers: Vec<String>,
+    pub topic: String,
+    #[serde(default, deserialize_with = "from_text")]
+    pub partition: i32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct HttpSinkConfig {
+    pub url: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default = "default_http_timeout", deserialize_with = "from_text")]
+    pub timeout_secs: u64,
+}
+
+fn default_http_timeout() -> u64 {
+    10
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FileSinkConfig {
+    pub directory: String,
+    #[serde(default = "default_file_prefix")]
+    pub prefix: String,
+    #[serde(default)]
+    pub format: FileFormat,
+    /// Starts a new file after this many seconds; 0 never rotates on time.
+    #[serde(default = "default_rotate_secs", deserialize_with = "from_text")]
+    pub rotate_secs: u64,
+    /// Starts a new file once this many bytes are written; 0 never rotates on size.
+    #[serde(default = "default_rotate_bytes", deserialize_with = "from_text")]
+    pub rotate_bytes: u64,
+}
+
+fn default_file_prefix() -> String {
+    "samples".to_string()
+}
+
+fn default_rotate_secs() -> u64 {
+    3600
+}
+
+fn default_rotate_bytes() -> u64 {
+    100 * 1024 * 1024
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RecorderSinkConfig {
+    pub directory: String,
+    #[serde(default = "default_recorder_prefix")]
+    pub prefix: String,
+    #[serde(default)]
+    pub format: RecordFormat,
+    #[serde(default = "default_rotate_secs", deserialize_with = "from_text")]
+    pub rotate_secs: u64,
+    #[serde(default = "default_rotate_bytes", deserialize_with = "from_text")]
+    pub rotate_bytes: u64,
+}
+
+fn default_recorder_prefix() -> String {
+    "c2-capture".to_string()
+}
+
+/// What the recorder captures; both can be replayed.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordFormat {
+    /// The exact `Universal` frames that would be pushed to C2.
+    #[default]
+    Universal,
+    /// The decoded `Historical` samples as JSON lines, for reading by eye.
+    Historical,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FileFormat {
+    #[default]
+    JsonLines,
+    Csv,
+    Parquet,
 }
 
 impl Configuration {
-    pub fn get_base(&self) -> String {
-        self.base.to_string()
+    /// All configured C2 endpoints, the legacy `base` URL first.
+    pub fn get_c2_endpoints(&self) -> Vec<String> {
+        std::iter::once(&self.base)
+            .filter(|base| !base.trim().is_empty())
+            .chain(self.c2_endpoints.iter())
+            .cloned()
+            .collect()
+    }
+
+    pub fn get_c2_mode(&self) -> C2Mode {
+        self.c2_mode
+    }
+
+    pub fn get_sinks(&self) -> Vec<SinkConfig> {
+        if self.sinks.is_empty() {
+            vec![SinkConfig::C2]
+        } else {
+            self.sinks.clone()
+        }
     }
 
     pub fn get_message(&self) -> &Message {
         &self.message
     }
 
-    pub fn load_from_file(file_path: &str) -> Result<Self, Box<dyn Error>> {
-        let file = File::open(file_path)?;
-        let config:Configuration = from_reader(file)?;
-        Ok(config)
+    /// Makes this the configuration behind `CONFIG`, replacing any loaded before. A replaced configuration
+    /// is freed once the last caller still holding it lets go.
+    pub fn install(self) {
+        *LOADED.write().unwrap() = Some(Arc::new(self));
     }
 
-    pub fn get_header_key(&self) -> &'static str {
-        Box::leak(self.header.key.clone().into_boxed_str())
+    pub fn get_header_key(&self) -> String {
+        self.header.key.clone()
     }
 
     pub fn get_header_value(&self) -> String {
@@ -100,16 +947,9 @@ impl Configuration {
         format!("Basic {}", encoded_credentials)
     }
 
-    pub fn get_opc_url(&self) -> &str {
-        &self.opc.url
-    }
-
-    pub fn get_opc_username(&self) -> String {
-        self.opc.username.to_string()
-    }
-
-    pub fn get_opc_password(&self) -> String {
-        self.opc.password.to_string()
+    /// All configured OPC UA servers, the single legacy `opc` entry first.
+    pub fn get_opc_sources(&self) -> Vec<&OpcConfig> {
+        self.opc.iter().chain(self.opc_sources.iter()).collect()
     }
 
     pub fn get_num_producers(&self) -> usize {
@@ -120,7 +960,113 @@ impl Configuration {
         self.num_consumers
     }
 
+    pub fn get_partition_strategy(&self) -> PartitionStrategy {
+        self.partition_strategy
+    }
+
+    pub fn get_tag_sync_interval(&self) -> u64 {
+        self.tag_sync.interval_secs
+    }
+
+    pub fn get_tag_sync_page_size(&self) -> i32 {
+        self.tag_sync.page_size
+    }
+
+    pub fn get_batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// `None` when partial batches are never flushed on time.
+    pub fn get_flush_interval(&self) -> Option<Duration> {
+        (self.flush_interval_ms > 0).then(|| Duration::from_millis(self.flush_interval_ms))
+    }
+
+    pub fn get_log_level(&self) -> LevelFilter {
+        LevelFilter::from_str(&self.log_level).unwrap_or(LevelFilter::Info)
+    }
+
+    pub fn get_logging(&self) -> &LoggingConfig {
+        &self.logging
+    }
+
+    pub fn get_subscription(&self) -> &SubscriptionConfig {
+        &self.subscription
+    }
+
+    pub fn get_config_watch_interval(&self) -> Option<Duration> {
+        (self.config_watch_secs > 0).then(|| Duration::from_secs(self.config_watch_secs))
+    }
+
+    pub fn get_shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown.timeout_secs)
+    }
+
+    pub fn get_unsent_directory(&self) -> &str {
+        &self.shutdown.unsent_directory
+    }
+
+    pub fn get_supervision(&self) -> &SupervisionConfig {
+        &self.supervision
+    }
+
+    pub fn get_telemetry(&self) -> &TelemetryConfig {
+        &self.telemetry
+    }
+
+    pub fn get_health(&self) -> &HealthConfig {
+        &self.health
+    }
+
+    pub fn get_watchdog(&self) -> &WatchdogConfig {
+        &self.watchdog
+    }
+
+    pub fn get_filters(&self) -> &[FilterGroup] {
+        &self.filters
+    }
+
+    pub fn get_aggregations(&self) -> &[AggregationGroup] {
+        &self.aggregations
+    }
+
+    pub fn get_calculated_tags(&self) -> &[CalculatedTag] {
+        &self.calculated_tags
+    }
+
+    pub fn get_scaling(&self) -> &[ScalingGroup] {
+        &self.scaling
+    }
+
+    pub fn get_read_eu_properties(&self) -> bool {
+        self.read_eu_properties
+    }
+
+}
+/// Takes a number or flag as given or as text, since environment overrides set every value as text.
+fn from_text<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned + FromStr,
+    T::Err: fmt::Display,
+{
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(text) => text.trim().parse().map_err(|e| D::Error::custom(format!("{:?}: {}", text, e))),
+        value => T::deserialize(value).map_err(D::Error::custom),
+    }
 }
+
+fn option_from_text<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned + FromStr,
+    T::Err: fmt::Display,
+{
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Null => Ok(None),
+        value => from_text(value).map(Some).map_err(D::Error::custom),
+    }
+}
+
 fn serialize_filter_as_string<S>(filter: &Filter, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -128,6 +1074,17 @@ where
     let json_str = to_string(&filter).map_err(serde::ser::Error::custom)?; 
     serializer.serialize_str(&json_str) 
 }
-lazy_static! {
-   pub static ref CONFIG: Configuration = Configuration::load_from_file("config.json").unwrap();
+static LOADED: RwLock<Option<Arc<Configuration>>> = RwLock::new(None);
+
+/// The configuration `config::loader::init` installed at startup, or the latest reload of it.
+pub struct LoadedConfiguration;
+
+pub static CONFIG: LoadedConfiguration = LoadedConfiguration;
+
+impl LoadedConfiguration {
+    /// The configuration installed now. Callers keep what they read consistent by holding on to it, and
+    /// see reloads by calling again.
+    pub fn current(&self) -> Arc<Configuration> {
+        LOADED.read().unwrap().clone().expect("configuration used before config::loader::init")
+    }
 }
\ No newline at end of file
@@ -0,0 +1,331 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use log::info;
+use serde_json::{Map, Value};
+use crate::config::configuration::Configuration;
+use crate::config::validation::{self, ValidationReport};
+
+/// Used when neither `--config` nor this variable names a file.
+const DEFAULT_CONFIG_PATH: &str = "config.json";
+pub const CONFIG_PATH_VARIABLE: &str = "OPC_CLIENT_CONFIG";
+/// `OPC_CLIENT__NUM_CONSUMERS=8` or `OPC_CLIENT__OPC_SOURCES__0__URL=...` override single values.
+pub const OVERRIDE_PREFIX: &str = "OPC_CLIENT__";
+const OVERRIDE_SEPARATOR: &str = "__";
+
+/// The configuration file: `--config <path>`, then `OPC_CLIENT_CONFIG`, then `config.json`.
+pub fn config_path(from_args: Option<PathBuf>) -> PathBuf {
+    from_args.unwrap_or_else(|| env::var(CONFIG_PATH_VARIABLE).map(PathBuf::from).unwrap_or_else(|_| PathBuf::from(DEFAULT_CONFIG_PATH)))
+}
+
+static CONFIG_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Loads, validates and installs the configuration for `CONFIG`; nothing connects before this succeeds.
+pub fn init(path: &Path) -> Result<(), Box<dyn Error>> {
+    let (config, report) = load(path)?;
+    report.log();
+    if report.has_errors() {
+        return Err(format!("Invalid configuration {}:\n{}", path.display(), report).into());
+    }
+    info!("Loaded configuration from {}", path.display());
+    config.install();
+    let _ = CONFIG_FILE.set(path.to_path_buf());
+    Ok(())
+}
+
+/// The file `init` loaded, which reloads read again.
+pub fn config_file() -> Option<&'static Path> {
+    CONFIG_FILE.get().map(PathBuf::as_path)
+}
+
+/// Reads a TOML, YAML or JSON file, applies environment overrides and secret references, then validates.
+///
+/// Layers are applied in that order, so an override may itself be a secret reference. Issues that keep
+/// the file from deserializing at all are returned as an error; everything else lands in the report.
+pub fn load(path: &Path) -> Result<(Configuration, ValidationReport), Box<dyn Error>> {
+    load_with(path, env::vars())
+}
+
+fn load_with(path: &Path, variables: impl Iterator<Item = (String, String)>) -> Result<(Configuration, ValidationReport), Box<dyn Error>> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Cannot read configuration {}: {}", path.display(), e))?;
+    let mut value = parse(path, &text)?;
+    apply_overrides(&mut value, variables)?;
+
+    let mut report = ValidationReport::default();
+    resolve_secrets(&mut value, "", &mut report);
+    let config: Configuration = serde_json::from_value(value)
+        .map_err(|e| format!("Invalid configuration {}: {}", path.display(), e))?;
+    validation::validate(&config, &mut report);
+    Ok((config, report))
+}
+
+fn parse(path: &Path, text: &str) -> Result<Value, Box<dyn Error>> {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+    let value = match extension.to_ascii_lowercase().as_str() {
+        "toml" => toml::from_str(text).map_err(|e| format!("Invalid TOML in {}: {}", path.display(), e))?,
+        "yaml" | "yml" => serde_yaml::from_str(text).map_err(|e| format!("Invalid YAML in {}: {}", path.display(), e))?,
+        _ => serde_json::from_str(text).map_err(|e| format!("Invalid JSON in {}: {}", path.display(), e))?,
+    };
+    Ok(value)
+}
+
+/// Applies every `OPC_CLIENT__`-prefixed variable; segments are matched to keys ignoring case and underscores,
+/// and numeric segments index lists.
+fn apply_overrides(root: &mut Value, variables: impl Iterator<Item = (String, String)>) -> Result<(), String> {
+    let mut overrides: Vec<(String, String)> = variables
+        .filter_map(|(name, value)| name.strip_prefix(OVERRIDE_PREFIX).map(|path| (path.to_string(), value)))
+        .collect();
+    // Sorted by index so that list entries added by overrides are created in order, `__2` before `__10`.
+    overrides.sort_by_cached_key(|(path, _)| {
+        path.split(OVERRIDE_SEPARATOR).map(|segment| (segment.parse::<usize>().ok(), segment.to_string())).collect::<Vec<_>>()
+    });
+    for (path, value) in overrides {
+        let segments: Vec<&str> = path.split(OVERRIDE_SEPARATOR).filter(|segment| !segment.is_empty()).collect();
+        if segments.is_empty() {
+            continue;
+        }
+        info!("Overriding configuration {} from the environment", segments.join(".").to_lowercase());
+        set_path(root, &segments, value).map_err(|e| format!("Cannot apply {}{}: {}", OVERRIDE_PREFIX, path, e))?;
+    }
+    Ok(())
+}
+
+fn set_path(node: &mut Value, segments: &[&str], raw: String) -> Result<(), String> {
+    let Some((segment, rest)) = segments.split_first() else {
+        // Kept as text, so a numeric password stays a string; number and flag fields parse text themselves.
+        *node = Value::String(raw);
+        return Ok(());
+    };
+    if let Ok(index) = segment.parse::<usize>() {
+        // A list the file leaves out is created, so overrides alone can fill it.
+        if node.is_null() {
+            *node = Value::Array(Vec::new());
+        }
+        if let Value::Array(items) = node {
+            if index == items.len() {
+                items.push(Value::Null);
+            }
+            let len = items.len();
+            let item = items
+                .get_mut(index)
+                .ok_or_else(|| format!("entry {} does not follow the {} entries of the list", index, len))?;
+            return set_path(item, rest, raw);
+        }
+    }
+    if !node.is_object() {
+        *node = Value::Object(Map::new());
+    }
+    if let Value::Object(map) = node {
+        let wanted = normalize(segment);
+        let key = map
+            .keys()
+            .find(|key| normalize(key) == wanted)
+            .cloned()
+            .unwrap_or_else(|| segment.to_lowercase());
+        let child = map.entry(key).or_insert(Value::Null);
+        return set_path(child, rest, raw);
+    }
+    Ok(())
+}
+
+fn normalize(key: &str) -> String {
+    key.chars().filter(|c| *c != '_').flat_map(char::to_lowercase).collect()
+}
+
+/// Replaces `${env:NAME}` and `${file:/path}` strings with the variable or file contents.
+fn resolve_secrets(node: &mut Value, field: &str, report: &mut ValidationReport) {
+    match node {
+        Value::String(text) => {
+            let Some(reference) = text.strip_prefix("${").and_then(|text| text.strip_suffix('}')) else {
+                return;
+            };
+            let resolved = if let Some(name) = reference.strip_prefix("env:") {
+                env::var(name).map_err(|_| format!("environment variable {} is not set", name))
+            } else if let Some(file) = reference.strip_prefix("file:") {
+                fs::read_to_string(file)
+                    .map(|contents| contents.trim_end_matches(['\r', '\n']).to_string())
+                    .map_err(|e| format!("cannot read secret file {}: {}", file, e))
+            } else {
+                return;
+            };
+            match resolved {
+                Ok(secret) => *text = secret,
+                Err(message) => report.error(field, message),
+            }
+        }
+        Value::Array(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                resolve_secrets(item, &join(field, &index.to_string()), report);
+            }
+        }
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                resolve_secrets(child, &join(field, key), report);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn join(field: &str, key: &str) -> String {
+    if field.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", field, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::config::configuration::SinkConfig;
+
+    /// Writes `text` to a file of its own under the temporary directory.
+    fn config_file(name: &str, text: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("opc_client_loader_{}_{}", std::process::id(), name));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn variables(pairs: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect::<Vec<_>>().into_iter()
+    }
+
+    fn load_file(path: &Path, pairs: &[(&str, &str)]) -> Configuration {
+        let (config, _) = load_with(path, variables(pairs)).unwrap();
+        fs::remove_file(path).unwrap();
+        config
+    }
+
+    #[test]
+    fn toml_yaml_and_json_load_alike() {
+        let json = config_file("parity.json", r#"{
+            "num_consumers": 3,
+            "log_level": "warn",
+            "opc_sources": [{"name": "line1", "url": "opc.tcp://line1:4840", "redundant_urls": ["opc.tcp://line1b:4840"]}],
+            "sinks": [{"type": "stdout"}]
+        }"#);
+        let yaml = config_file("parity.yaml", "
+num_consumers: 3
+log_level: warn
+opc_sources:
+  - name: line1
+    url: opc.tcp://line1:4840
+    redundant_urls: [opc.tcp://line1b:4840]
+sinks:
+  - type: stdout
+");
+        let toml = config_file("parity.toml", r#"
+num_consumers = 3
+log_level = "warn"
+
+[[opc_sources]]
+name = "line1"
+url = "opc.tcp://line1:4840"
+redundant_urls = ["opc.tcp://line1b:4840"]
+
+[[sinks]]
+type = "stdout"
+"#);
+        let expected = serde_json::to_value(load_file(&json, &[])).unwrap();
+        assert_eq!(serde_json::to_value(load_file(&yaml, &[])).unwrap(), expected);
+        assert_eq!(serde_json::to_value(load_file(&toml, &[])).unwrap(), expected);
+        assert_eq!(expected["num_consumers"], 3);
+        assert_eq!(expected["opc_sources"][0]["redundant_urls"][0], "opc.tcp://line1b:4840");
+    }
+
+    #[test]
+    fn overrides_take_precedence_over_the_file() {
+        let path = config_file("precedence.json", r#"{"num_consumers": 3, "opc_sources": [{"url": "opc.tcp://file:4840", "password": "1234"}]}"#);
+        let config = load_file(&path, &[
+            ("OPC_CLIENT__NUM_CONSUMERS", "8"),
+            ("OPC_CLIENT__OPC_SOURCES__0__URL", "opc.tcp://env:4840"),
+            ("OPC_CLIENT__OPC_SOURCES__0__PASSWORD", "5678"),
+            ("NUM_CONSUMERS", "9"),
+        ]);
+        assert_eq!(config.num_consumers, 8);
+        assert_eq!(config.opc_sources[0].url, "opc.tcp://env:4840");
+        assert_eq!(config.opc_sources[0].password, "5678");
+    }
+
+    #[test]
+    fn numeric_segments_create_lists() {
+        let mut root = json!({});
+        let overrides = [
+            ("OPC_CLIENT__SINKS__0__TYPE", "stdout"),
+            ("OPC_CLIENT__SINKS__1__TYPE", "mqtt"),
+            ("OPC_CLIENT__SINKS__1__HOST", "broker"),
+            ("OPC_CLIENT__SINKS__1__PORT", "1884"),
+        ];
+        apply_overrides(&mut root, variables(&overrides)).unwrap();
+        assert_eq!(root, json!({"sinks": [{"type": "stdout"}, {"type": "mqtt", "host": "broker", "port": "1884"}]}));
+        let config: Configuration = serde_json::from_value(root).unwrap();
+        assert!(matches!(&config.sinks[1], SinkConfig::Mqtt(mqtt) if mqtt.port == 1884));
+
+        let mut root = json!({"filters": []});
+        let overrides: Vec<(String, String)> = (0..12)
+            .map(|index| (format!("OPC_CLIENT__FILTERS__{}__TAGS__0", index), format!("tag{}", index)))
+            .collect();
+        apply_overrides(&mut root, overrides.into_iter()).unwrap();
+        assert_eq!(root["filters"][10]["tags"][0], "tag10");
+    }
+
+    #[test]
+    fn overrides_are_text_until_a_field_reads_them() {
+        let path = config_file("text.json", r#"{"opc_sources": [{"url": "opc.tcp://line1:4840"}]}"#);
+        let config = load_file(&path, &[
+            ("OPC_CLIENT__OPC_SOURCES__0__PASSWORD", "12345"),
+            ("OPC_CLIENT__OPC_SOURCES__0__NUM_PRODUCERS", "4"),
+            ("OPC_CLIENT__BATCH_SIZE", " 50 "),
+            ("OPC_CLIENT__READ_EU_PROPERTIES", "false"),
+            ("OPC_CLIENT__LOG_LEVEL", "true"),
+        ]);
+        assert_eq!(config.opc_sources[0].password, "12345");
+        assert_eq!(config.opc_sources[0].num_producers, Some(4));
+        assert_eq!(config.batch_size, 50);
+        assert!(!config.read_eu_properties);
+        assert_eq!(config.log_level, "true");
+
+        let path = config_file("not_a_number.json", "{}");
+        let error = load_with(&path, variables(&[("OPC_CLIENT__BATCH_SIZE", "many")])).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(error.to_string().contains("\"many\""), "{}", error);
+    }
+
+    #[test]
+    fn skipped_list_entries_are_rejected() {
+        let mut root = json!({"sinks": [{"type": "stdout"}]});
+        let error = apply_overrides(&mut root, variables(&[("OPC_CLIENT__SINKS__2__TYPE", "stdout")])).unwrap_err();
+        assert!(error.contains("OPC_CLIENT__SINKS__2__TYPE"), "{}", error);
+    }
+
+    #[test]
+    fn secret_references_resolve_from_the_environment_and_files() {
+        let secret_file = config_file("secret.txt", "from-file\n");
+        env::set_var("OPC_CLIENT_LOADER_TEST_SECRET", "from-env");
+        let path = config_file("secrets.json", &json!({
+            "opc_sources": [{"url": "opc.tcp://line1:4840", "username": "${env:OPC_CLIENT_LOADER_TEST_SECRET}", "password": format!("${{file:{}}}", secret_file.display())}],
+        }).to_string());
+        let config = load_file(&path, &[]);
+        fs::remove_file(&secret_file).unwrap();
+        assert_eq!(config.opc_sources[0].username, "from-env");
+        assert_eq!(config.opc_sources[0].password, "from-file");
+
+        let mut value = json!({"header": {"password": "${env:OPC_CLIENT_LOADER_TEST_UNSET}"}});
+        let mut report = ValidationReport::default();
+        resolve_secrets(&mut value, "", &mut report);
+        assert!(report.has_errors());
+        assert!(report.to_string().contains("header.password"), "{}", report);
+    }
+
+    #[test]
+    fn overrides_may_be_secret_references() {
+        env::set_var("OPC_CLIENT_LOADER_TEST_PASSWORD", "s3cret");
+        let path = config_file("override_secret.json", r#"{"opc_sources": [{"url": "opc.tcp://line1:4840"}]}"#);
+        let config = load_file(&path, &[("OPC_CLIENT__OPC_SOURCES__0__PASSWORD", "${env:OPC_CLIENT_LOADER_TEST_PASSWORD}")]);
+        assert_eq!(config.opc_sources[0].password, "s3cret");
+    }
+}
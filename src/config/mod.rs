@@ -1 +1,4 @@
 pub mod configuration;
+pub mod loader;
+pub mod reload;
+pub mod validation;
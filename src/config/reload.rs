@@ -0,0 +1,202 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::{info, warn};
+use serde_json::Value;
+use crate::config::configuration::{Configuration, CONFIG};
+use crate::config::loader;
+
+/// A change the running process picks up without a restart.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LiveChange {
+    /// Consumers read the batch size and flush interval for every batch.
+    Batching,
+    LogLevel,
+    /// Existing subscriptions are modified; tags without their own sampling interval are resampled.
+    Subscription,
+    /// The tag request changed, so the tag list is fetched again in full.
+    TagFilter,
+    TagSyncInterval,
+    ConfigWatch,
+    Shutdown,
+    Supervision,
+    Telemetry,
+    Health,
+    Watchdog,
+    /// Tags are matched to filter groups again; values held for compression are dropped.
+    Filters,
+    /// Open windows are dropped and tags matched to the aggregation groups again.
+    Aggregations,
+    /// Expressions are parsed again and the values they read are forgotten.
+    CalculatedTags,
+    /// Tags are matched to the scaling groups again; EURange and units already read are kept.
+    Scaling,
+}
+
+/// What a reload changed, split into what was applied and what waits for a restart.
+#[derive(Debug, Default)]
+pub struct ConfigChanges {
+    pub live: Vec<LiveChange>,
+    pub restart_required: Vec<&'static str>,
+}
+
+impl ConfigChanges {
+    pub fn is_empty(&self) -> bool {
+        self.live.is_empty() && self.restart_required.is_empty()
+    }
+}
+
+/// Fields that shape connections and tasks created at startup. Their old values stay in effect until
+/// a restart, so nothing sees half of a change.
+const RESTART_FIELDS: &[&str] = &[
+    "base",
+    "c2_endpoints",
+    "c2_mode",
+    "header",
+    "opc",
+    "opc_sources",
+    "num_producers",
+    "num_consumers",
+    "partition_strategy",
+    "sinks",
+    "tag_sync.page_size",
+    "telemetry.listen",
+    "logging",
+];
+
+const LIVE_FIELDS: &[(&str, LiveChange)] = &[
+    ("batch_size", LiveChange::Batching),
+    ("flush_interval_ms", LiveChange::Batching),
+    ("log_level", LiveChange::LogLevel),
+    ("subscription", LiveChange::Subscription),
+    ("message", LiveChange::TagFilter),
+    ("tag_sync.interval_secs", LiveChange::TagSyncInterval),
+    ("config_watch_secs", LiveChange::ConfigWatch),
+    ("shutdown", LiveChange::Shutdown),
+    ("supervision", LiveChange::Supervision),
+    ("telemetry.per_tag_metrics", LiveChange::Telemetry),
+    ("telemetry.universal_latency", LiveChange::Telemetry),
+    ("health", LiveChange::Health),
+    ("watchdog", LiveChange::Watchdog),
+    ("filters", LiveChange::Filters),
+    ("aggregations", LiveChange::Aggregations),
+    ("calculated_tags", LiveChange::CalculatedTags),
+    ("scaling", LiveChange::Scaling),
+    ("read_eu_properties", LiveChange::Scaling),
+];
+
+/// Loads the configuration again and installs it with the restart-only fields kept as they are.
+///
+/// An invalid file is reported and the running configuration stays in place.
+pub fn reload(path: &Path) -> Result<ConfigChanges, Box<dyn Error>> {
+    let (mut new_config, report) = loader::load(path)?;
+    report.log();
+    if report.has_errors() {
+        return Err(format!("Not reloading {}, it has {} error(s)", path.display(), report.errors()).into());
+    }
+
+    let old_config = CONFIG.current();
+    let old_value = serde_json::to_value(&*old_config)?;
+    let new_value = serde_json::to_value(&new_config)?;
+
+    let mut changes = ConfigChanges::default();
+    for (field, change) in LIVE_FIELDS {
+        if field_value(&old_value, field) != field_value(&new_value, field) && !changes.live.contains(change) {
+            changes.live.push(*change);
+        }
+    }
+    for field in RESTART_FIELDS {
+        if field_value(&old_value, field) != field_value(&new_value, field) {
+            warn!("Configuration {} changed; it takes effect after a restart", field);
+            changes.restart_required.push(field);
+        }
+    }
+    if changes.is_empty() {
+        return Ok(changes);
+    }
+
+    keep_restart_fields(&mut new_config, &old_config);
+    new_config.install();
+    info!("Reloaded configuration from {}: applying {:?}", path.display(), changes.live);
+    Ok(changes)
+}
+
+fn field_value<'a>(value: &'a Value, field: &str) -> Option<&'a Value> {
+    field.split('.').try_fold(value, |value, key| value.get(key))
+}
+
+fn keep_restart_fields(new_config: &mut Configuration, old_config: &Configuration) {
+    new_config.base = old_config.base.clone();
+    new_config.c2_endpoints = old_config.c2_endpoints.clone();
+    new_config.c2_mode = old_config.c2_mode;
+    new_config.header = old_config.header.clone();
+    new_config.opc = old_config.opc.clone();
+    new_config.opc_sources = old_config.opc_sources.clone();
+    new_config.num_producers = old_config.num_producers;
+    new_config.num_consumers = old_config.num_consumers;
+    new_config.partition_strategy = old_config.partition_strategy;
+    new_config.sinks = old_config.sinks.clone();
+    new_config.tag_sync.page_size = old_config.tag_sync.page_size;
+    new_config.telemetry.listen = old_config.telemetry.listen.clone();
+    new_config.logging = old_config.logging.clone();
+}
+
+/// Resolves when the configuration file's contents change, checked every `config_watch_secs`, or on SIGHUP.
+pub struct ReloadTrigger {
+    path: PathBuf,
+    contents: Option<Vec<u8>>,
+    #[cfg(unix)]
+    hangup: Option<tokio::signal::unix::Signal>,
+}
+
+impl ReloadTrigger {
+    pub fn new(path: &Path) -> Self {
+        ReloadTrigger {
+            path: path.to_path_buf(),
+            contents: fs::read(path).ok(),
+            #[cfg(unix)]
+            hangup: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+                .inspect_err(|e| warn!("Cannot listen for SIGHUP, only file changes reload the configuration: {}", e))
+                .ok(),
+        }
+    }
+
+    pub async fn wait(&mut self) {
+        loop {
+            // Read every round so a reload can change how often the file is checked.
+            let poll_interval = CONFIG.current().get_config_watch_interval();
+            tokio::select! {
+                _ = tokio::time::sleep(poll_interval.unwrap_or(Duration::MAX)), if poll_interval.is_some() => {
+                    let contents = fs::read(&self.path).ok();
+                    // Editors may truncate before writing; an unreadable or empty file is picked up once written.
+                    if contents.as_ref().is_some_and(|contents| !contents.is_empty()) && contents != self.contents {
+                        self.contents = contents;
+                        info!("Configuration file {} changed", self.path.display());
+                        return;
+                    }
+                }
+                _ = Self::hangup(self) => {
+                    self.contents = fs::read(&self.path).ok();
+                    info!("SIGHUP received, reloading the configuration");
+                    return;
+                }
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    async fn hangup(trigger: &mut Self) {
+        match trigger.hangup.as_mut() {
+            Some(hangup) => {
+                hangup.recv().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn hangup(_trigger: &mut Self) {
+        std::future::pending().await
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use log::{error, warn, LevelFilter};
+use opcua::types::MessageSecurityMode;
+use opcua::crypto::SecurityPolicy;
+use url::Url;
+use crate::config::configuration::{Configuration, Deadband, OpcConfig, SinkConfig};
+use crate::system_initializer::expression::Expression;
+use crate::system_initializer::units::{self, Conversion};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// One problem with the configuration, located by its dotted path, e.g. `opc_sources.0.url`.
+#[derive(Debug, Clone)]
+pub struct Issue {
+    pub severity: Severity,
+    pub field: String,
+    pub message: String,
+}
+
+/// Everything wrong with a configuration, collected in one pass so all of it can be fixed at once.
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub issues: Vec<Issue>,
+}
+
+impl ValidationReport {
+    pub fn error(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.issues.push(Issue { severity: Severity::Error, field: field.into(), message: message.into() });
+    }
+
+    pub fn warning(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.issues.push(Issue { severity: Severity::Warning, field: field.into(), message: message.into() });
+    }
+
+    pub fn errors(&self) -> usize {
+        self.issues.iter().filter(|issue| issue.severity == Severity::Error).count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors() > 0
+    }
+
+    pub fn log(&self) {
+        for issue in &self.issues {
+            match issue.severity {
+                Severity::Error => error!("Configuration error at {}: {}", issue.field, issue.message),
+                Severity::Warning => warn!("Configuration warning at {}: {}", issue.field, issue.message),
+            }
+        }
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for issue in &self.issues {
+            let severity = match issue.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            };
+            writeln!(f, "{}: {}: {}", severity, issue.field, issue.message)?;
+        }
+        write!(f, "{} error(s), {} warning(s)", self.errors(), self.issues.len() - self.errors())
+    }
+}
+
+/// Checks what serde cannot: values that parse but could never connect or run.
+pub fn validate(config: &Configuration, report: &mut ValidationReport) {
+    let c2_endpoints = config.get_c2_endpoints();
+    if c2_endpoints.is_empty() {
+        report.error("c2_endpoints", "no C2 endpoint configured; set base or c2_endpoints");
+    }
+    for (index, endpoint) in c2_endpoints.iter().enumerate() {
+        check_url(report, &format!("c2_endpoints.{}", index), endpoint, &["ws", "wss"]);
+    }
+    if config.header.key.trim().is_empty() {
+        report.error("header.key", "the C2 authorization header needs a name");
+    }
+    if config.header.username.trim().is_empty() {
+        report.warning("header.username", "C2 requests are sent with blank credentials");
+    }
+
+    if config.num_producers == 0 {
+        report.error("num_producers", "at least one producer is needed");
+    }
+    if config.num_consumers == 0 {
+        report.error("num_consumers", "at least one consumer is needed");
+    }
+    if config.batch_size == 0 {
+        report.error("batch_size", "must be at least one record");
+    }
+    if LevelFilter::from_str(&config.log_level).is_err() {
+        report.error("log_level", format!("unknown level {}; use off, error, warn, info, debug or trace", config.log_level));
+    }
+    let subscription = &config.subscription;
+    if subscription.publishing_interval_ms <= 0.0 {
+        report.error("subscription.publishing_interval_ms", "must be positive");
+    }
+    if subscription.max_keep_alive_count == 0 {
+        report.error("subscription.max_keep_alive_count", "must be at least one");
+    }
+    // OPC UA Part 4, 5.13.2: servers revise a lifetime below three keep-alives upwards.
+    if subscription.lifetime_count < 3 * subscription.max_keep_alive_count {
+        report.warning("subscription.lifetime_count", "should be at least three times max_keep_alive_count");
+    }
+    check_not_blank(report, "shutdown.unsent_directory", &config.shutdown.unsent_directory);
+    if !config.logging.otlp_endpoint.trim().is_empty() {
+        check_url(report, "logging.otlp_endpoint", &config.logging.otlp_endpoint, &["http", "https"]);
+    }
+    let listen = &config.telemetry.listen;
+    if !listen.trim().is_empty() && listen.trim().parse::<SocketAddr>().is_err() {
+        report.error("telemetry.listen", format!("{} is not an address and port such as 0.0.0.0:9464", listen));
+    }
+    if !(config.health.max_queue_fill > 0.0 && config.health.max_queue_fill <= 1.0) {
+        report.error("health.max_queue_fill", "must be above 0 and at most 1");
+    }
+    let mut groups = HashSet::new();
+    for (index, group) in config.filters.iter().enumerate() {
+        let field = format!("filters.{}", index);
+        check_not_blank(report, &format!("{}.name", field), &group.name);
+        if !groups.insert(group.name.as_str()) {
+            report.error(format!("{}.name", field), format!("filter group {} is defined twice", group.name));
+        }
+        for (pattern_index, pattern) in group.tags.iter().enumerate() {
+            check_not_blank(report, &format!("{}.tags.{}", field, pattern_index), pattern);
+        }
+        let deadband = match group.deadband {
+            Some(Deadband::Absolute { value }) | Some(Deadband::Percent { value }) => value,
+            None => 0.0,
+        };
+        if deadband < 0.0 {
+            report.error(format!("{}.deadband.value", field), "must not be negative");
+        }
+        if group.compression.is_some_and(|compression| compression.deviation < 0.0) {
+            report.error(format!("{}.compression.deviation", field), "must not be negative");
+        }
+    }
+    let mut groups = HashSet::new();
+    for (index, group) in config.aggregations.iter().enumerate() {
+        let field = format!("aggregations.{}", index);
+        check_not_blank(report, &format!("{}.name", field), &group.name);
+        if !groups.insert(group.name.as_str()) {
+            report.error(format!("{}.name", field), format!("aggregation group {} is defined twice", group.name));
+        }
+        for (pattern_index, pattern) in group.tags.iter().enumerate() {
+            check_not_blank(report, &format!("{}.tags.{}", field, pattern_index), pattern);
+        }
+        if group.window_secs == 0 {
+            report.error(format!("{}.window_secs", field), "must be at least one second");
+        }
+        if group.step_secs > group.window_secs {
+            report.error(format!("{}.step_secs", field), "must not exceed window_secs, or values would fall between windows");
+        }
+        if group.functions.is_empty() {
+            report.error(format!("{}.functions", field), "must name at least one function");
+        }
+    }
+    if !config.aggregations.is_empty() && !config.get_sinks().iter().any(|sink| matches!(sink, SinkConfig::C2)) {
+        report.error("aggregations", "aggregates are sent to C2, which is not among the sinks");
+    }
+    check_calculated_tags(report, config);
+    let mut groups = HashSet::new();
+    for (index, group) in config.scaling.iter().enumerate() {
+        let field = format!("scaling.{}", index);
+        check_not_blank(report, &format!("{}.name", field), &group.name);
+        if !groups.insert(group.name.as_str()) {
+            report.error(format!("{}.name", field), format!("scaling group {} is defined twice", group.name));
+        }
+        for (pattern_index, pattern) in group.tags.iter().enumerate() {
+            check_not_blank(report, &format!("{}.tags.{}", field, pattern_index), pattern);
+        }
+        for (low, high, name) in [(group.raw_low, group.raw_high, "raw"), (group.eu_low, group.eu_high, "eu")] {
+            match (low, high) {
+                (Some(low), Some(high)) if low == high => {
+                    report.error(format!("{}.{}_high", field, name), format!("must differ from {}_low", name));
+                }
+                (Some(_), None) | (None, Some(_)) => {
+                    report.error(format!("{}.{}_low", field, name), format!("{}_low and {}_high go together", name, name));
+                }
+                _ => {}
+            }
+        }
+        if group.raw_low.is_some() && (group.factor.is_some() || group.offset.is_some()) {
+            report.error(format!("{}.factor", field), "a raw range and a factor or offset exclude each other");
+        }
+        if group.clamp && group.eu_low.is_none() && !config.read_eu_properties {
+            report.warning(format!("{}.clamp", field), "needs eu_low and eu_high, or the server's EURange through read_eu_properties");
+        }
+        match (&group.units, &group.convert_to) {
+            (Some(units), Some(convert_to)) => {
+                if let Err(e) = Conversion::between(units, convert_to) {
+                    report.error(format!("{}.convert_to", field), e);
+                }
+            }
+            (None, Some(convert_to)) if !units::is_known(convert_to) => {
+                report.error(format!("{}.convert_to", field), format!("unknown unit {}", convert_to));
+            }
+            _ => {}
+        }
+    }
+    if config.watchdog.stale_factor < 1.0 {
+        report.error("watchdog.stale_factor", "must be at least 1");
+    }
+    if config.supervision.window_secs == 0 {
+        report.error("supervision.window_secs", "must be at least one second");
+    }
+    if config.tag_sync.page_size <= 0 {
+        report.error("tag_sync.page_size", "must be positive");
+    }
+
+    let sources = config.get_opc_sources();
+    if sources.is_empty() {
+        report.error("opc_sources", "no OPC UA source configured; set opc or opc_sources");
+    }
+    let mut names = HashSet::new();
+    for (index, source) in sources.iter().enumerate() {
+        // The legacy `opc` entry comes first in get_opc_sources.
+        let field = match (&config.opc, index) {
+            (Some(_), 0) => "opc".to_string(),
+            (Some(_), index) => format!("opc_sources.{}", index - 1),
+            (None, index) => format!("opc_sources.{}", index),
+        };
+        if !names.insert(source.name.as_str()) {
+            report.error(format!("{}.name", field), format!("source name {} is used more than once", source.name));
+        }
+        validate_opc_source(report, &field, source);
+    }
+
+    for (index, sink) in config.get_sinks().iter().enumerate() {
+        validate_sink(report, &format!("sinks.{}", index), sink);
+    }
+}
+
+fn validate_opc_source(report: &mut ValidationReport, field: &str, source: &OpcConfig) {
+    if source.name.trim().is_empty() {
+        report.error(format!("{}.name", field), "sources need a name to be told apart in logs and commands");
+    }
+    check_url(report, &format!("{}.url", field), &source.url, &["opc.tcp"]);
+    for (index, url) in source.redundant_urls.iter().enumerate() {
+        check_url(report, &format!("{}.redundant_urls.{}", field, index), url, &["opc.tcp"]);
+    }
+    if !source.redundant_urls.is_empty() && source.health_check_secs == 0 {
+        report.error(format!("{}.health_check_secs", field), "must be at least one second with redundant servers");
+    }
+
+    let policy = SecurityPolicy::from_str(&source.security_policy).ok();
+    if policy.is_none() {
+        report.error(format!("{}.security_policy", field), format!("unknown security policy {}", source.security_policy));
+    }
+    let mode = MessageSecurityMode::from(source.security_mode.as_str());
+    if mode == MessageSecurityMode::Invalid {
+        report.error(format!("{}.security_mode", field), format!("unknown security mode {}", source.security_mode));
+    }
+    match (policy, mode) {
+        (Some(SecurityPolicy::None), MessageSecurityMode::Sign | MessageSecurityMode::SignAndEncrypt) => {
+            report.error(format!("{}.security_mode", field), "signing needs a security policy other than None");
+        }
+        (Some(policy), MessageSecurityMode::None) if policy != SecurityPolicy::None => {
+            report.error(format!("{}.security_mode", field), format!("security policy {} needs Sign or SignAndEncrypt", source.security_policy));
+        }
+        _ => {}
+    }
+
+    if !source.username.trim().is_empty() && source.password.is_empty() {
+        report.warning(format!("{}.password", field), "a username is set without a password");
+    }
+    if source.session_pool_size == 0 {
+        report.error(format!("{}.session_pool_size", field), "at least one session is needed");
+    }
+    if source.num_producers == Some(0) {
+        report.error(format!("{}.num_producers", field), "at least one producer is needed; leave it out to use num_producers");
+    }
+}
+
+fn validate_sink(report: &mut ValidationReport, field: &str, sink: &SinkConfig) {
+    match sink {
+        SinkConfig::C2 | SinkConfig::Stdout => {}
+        SinkConfig::Mqtt(config) => {
+            check_not_blank(report, &format!("{}.host", field), &config.host);
+            if config.qos > 2 {
+                report.error(format!("{}.qos", field), "MQTT QoS is 0, 1 or 2");
+            }
+        }
+        SinkConfig::SparkplugB(config) => {
+            check_not_blank(report, &format!("{}.host", field), &config.host);
+            check_not_blank(report, &format!("{}.group_id", field), &config.group_id);
+            check_not_blank(report, &format!("{}.edge_node_id", field), &config.edge_node_id);
+        }
+        SinkConfig::Kafka(config) => {
+            if config.brokers.is_empty() {
+                report.error(format!("{}.brokers", field), "at least one broker is needed");
+            }
+            check_not_blank(report, &format!("{}.topic", field), &config.topic);
+        }
+        SinkConfig::Http(config) => check_url(report, &format!("{}.url", field), &config.url, &["http", "https"]),
+        SinkConfig::File(config) => check_not_blank(report, &format!("{}.directory", field), &config.directory),
+        SinkConfig::Recorder(config) => check_not_blank(report, &format!("{}.directory", field), &config.directory),
+    }
+}
+
+/// Expressions must parse, and calculated tags must not read themselves, directly or through others.
+fn check_calculated_tags(report: &mut ValidationReport, config: &Configuration) {
+    let mut reads: HashMap<&str, Vec<String>> = HashMap::new();
+    for (index, calculated_tag) in config.calculated_tags.iter().enumerate() {
+        let field = format!("calculated_tags.{}", index);
+        check_not_blank(report, &format!("{}.name", field), &calculated_tag.name);
+        if reads.contains_key(calculated_tag.name.as_str()) {
+            report.error(format!("{}.name", field), format!("calculated tag {} is defined twice", calculated_tag.name));
+            continue;
+        }
+        match Expression::parse(&calculated_tag.expression) {
+            Ok(expression) => {
+                reads.insert(&calculated_tag.name, expression.tags());
+            }
+            Err(e) => report.error(format!("{}.expression", field), e),
+        }
+    }
+    for (index, calculated_tag) in config.calculated_tags.iter().enumerate() {
+        // Depth-first from the tag; reaching it again closes a cycle.
+        let mut pending: Vec<&str> = reads.get(calculated_tag.name.as_str()).into_iter().flatten().map(String::as_str).collect();
+        let mut seen = HashSet::new();
+        while let Some(tag) = pending.pop() {
+            if tag == calculated_tag.name {
+                report.error(format!("calculated_tags.{}.expression", index), format!("{} depends on itself", calculated_tag.name));
+                break;
+            }
+            if seen.insert(tag) {
+                pending.extend(reads.get(tag).into_iter().flatten().map(String::as_str));
+            }
+        }
+    }
+}
+
+fn check_not_blank(report: &mut ValidationReport, field: &str, value: &str) {
+    if value.trim().is_empty() {
+        report.error(field, "must not be blank");
+    }
+}
+
+fn check_url(report: &mut ValidationReport, field: &str, value: &str, schemes: &[&str]) {
+    match Url::parse(value.trim()) {
+        Ok(url) if schemes.contains(&url.scheme()) => {}
+        Ok(url) => report.error(field, format!("{} uses {}, expected {}", value, url.scheme(), schemes.join(" or "))),
+        Err(e) => report.error(field, format!("{:?} is not a valid URL: {}", value, e)),
+    }
+}
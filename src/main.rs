@@ -1,27 +1,70 @@
 use tokio;
 use log::info;
-use log4rs;
 
 mod config;
 mod system_initializer;
 mod clients;
+mod sinks;
+mod replay;
+mod cli;
+mod telemetry;
+use crate::cli::{Cli, Command};
 use crate::system_initializer::SystemInitializer;
 use crate::system_initializer::SYSTEM_INITIALIZER;
 
+// message.proto declares no package, so prost names its file after the empty one.
 pub mod message {
-    include!(concat!(env!("OUT_DIR"), "\\message.rs"));
+    include!(concat!(env!("OUT_DIR"), "/_.rs"));
+}
+
+pub mod sparkplug_b {
+    include!(concat!(env!("OUT_DIR"), "/sparkplug_b.rs"));
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<Box<dyn std::error::Error>>> {
 
-    let _ = log4rs::init_file("log4rs.yaml", Default::default());
+    let cli = match Cli::from_env() {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    match cli.command {
+        Command::Help => {
+            print!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Command::ValidateConfig => std::process::exit(cli::validate_config(&cli.config)),
+        _ => {}
+    }
+
+    // The log is set up from the configuration, so configuration problems are reported on the console.
+    if let Err(e) = config::loader::init(&cli.config) {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }
+    let config = config::configuration::CONFIG.current();
+    if let Err(e) = telemetry::logging::init(config.get_logging(), config.get_log_level()) {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }
     info!("Application started.");
 
+    if !matches!(cli.command, Command::Run) {
+        if let Err(e) = cli::execute(cli.command).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let initializer = SYSTEM_INITIALIZER.get_or_init(|| async{
         SystemInitializer::new().await.unwrap()
     }).await;
-    initializer.init_process().await?;
-    
-    Ok(())
+    let exit_code = initializer.init_process().await?;
+    info!("Exiting with status {}", exit_code);
+    telemetry::logging::shutdown();
+    std::process::exit(exit_code);
 }
\ No newline at end of file
@@ -0,0 +1,94 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::{info, error};
+use tokio::time::{sleep_until, Instant};
+use crate::sinks::c2::C2Sink;
+use crate::sinks::recorder::{RecordedFrame, RecordingReader};
+use crate::sinks::Sink;
+
+/// What to replay and how fast, from `replay <file or directory> --speed <factor>`.
+pub struct ReplayOptions {
+    pub path: PathBuf,
+    /// 1 keeps the original pacing, 10 plays ten times faster and 0 sends as fast as C2 accepts.
+    pub speed: f64,
+}
+
+/// Pushes recorded frames to C2 through the configured endpoints, without connecting to any OPC UA server.
+///
+/// The files of all consumers in a directory are merged by capture time, so the replay has the
+/// same order and gaps as the recorded session.
+pub async fn run(options: ReplayOptions) -> Result<(), Box<dyn Error>> {
+    let mut readers = Vec::new();
+    for path in recording_files(&options.path)? {
+        if let Some(reader) = RecordingReader::open(&path).map_err(|e| e.to_string())? {
+            info!("Replaying {}", path.display());
+            readers.push(reader);
+        }
+    }
+    if readers.is_empty() {
+        return Err(format!("No recordings found at {}", options.path.display()).into());
+    }
+
+    // The earliest next frame of every file, as (captured, reader index).
+    let mut heads: Vec<Option<RecordedFrame>> = Vec::new();
+    let mut order = BinaryHeap::new();
+    for (index, reader) in readers.iter_mut().enumerate() {
+        let frame = reader.next_frame().map_err(|e| e.to_string())?;
+        if let Some(frame) = &frame {
+            order.push(Reverse((frame.captured, index)));
+        }
+        heads.push(frame);
+    }
+
+    let mut c2_sink = C2Sink::new();
+    c2_sink.connect().await.map_err(|e| e.to_string())?;
+
+    let started = Instant::now();
+    let mut first_captured = None;
+    let (mut sent, mut failed) = (0u64, 0u64);
+    while let Some(Reverse((captured, index))) = order.pop() {
+        let Some(frame) = heads[index].take() else {
+            continue;
+        };
+        let first_captured = *first_captured.get_or_insert(captured);
+        if options.speed > 0.0 {
+            let offset_ms = (captured - first_captured).max(0) as f64 / options.speed;
+            sleep_until(started + Duration::from_secs_f64(offset_ms / 1000.0)).await;
+        }
+        match c2_sink.send_frame(frame.universal).await.map_err(|e| e.to_string()) {
+            Ok(()) => sent += 1,
+            Err(e) => {
+                error!("Failed to replay the frame captured at {}: {}", captured, e);
+                failed += 1;
+            }
+        }
+
+        let next = readers[index].next_frame().map_err(|e| e.to_string())?;
+        if let Some(next) = &next {
+            order.push(Reverse((next.captured, index)));
+        }
+        heads[index] = next;
+    }
+    info!("Replay finished: {} frames sent, {} failed", sent, failed);
+    Ok(())
+}
+
+/// The file itself, or the files of a directory in name order.
+fn recording_files(path: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry_path = entry?.path();
+        if entry_path.is_file() {
+            files.push(entry_path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
@@ -0,0 +1,186 @@
+use std::collections::VecDeque;
+use async_trait::async_trait;
+use log::{info, error};
+use prost::Message;
+use crate::clients::ws_client::WebSocketClient;
+use crate::clients::c2_endpoints::C2_ENDPOINTS;
+use crate::config::configuration::{C2Mode, SinkConfig, CONFIG};
+use crate::message::Universal;
+use crate::sinks::{Batch, Sink, SinkError};
+
+/// Messages an outbox keeps while C2 is unreachable; the oldest are dropped beyond this.
+const MAX_PENDING_MESSAGES: usize = 10_000;
+
+/// The sink name; saved unsent batches are replayed to this sink only.
+pub const NAME: &str = "c2";
+
+/// Delivers `Universal` messages over the C2 WebSocket, spreading them over the endpoints per `c2_mode`.
+pub struct C2Sink {
+    // Fan-out keeps one connection per endpoint; the other modes a single one.
+    connections: Vec<(usize, WebSocketClient)>,
+}
+
+impl C2Sink {
+    pub fn new() -> Self {
+        C2Sink { connections: Vec::new() }
+    }
+
+    /// Pushes an already encoded `Universal` frame, as replay does with recorded ones.
+    pub async fn send_frame(&mut self, universal_buffer: Vec<u8>) -> Result<(), SinkError> {
+        match C2_ENDPOINTS.mode() {
+            C2Mode::FanOut => self.fan_out(universal_buffer).await,
+            C2Mode::Failover | C2Mode::RoundRobin => self.push(universal_buffer).await,
+        }
+    }
+
+    async fn connect_single(&mut self) -> Result<(), SinkError> {
+        let connection = C2_ENDPOINTS.connect_first(&C2_ENDPOINTS.candidates()).await?;
+        self.connections = vec![connection];
+        Ok(())
+    }
+
+    /// Sends to the current endpoint, moving to the next candidate and retrying once if it fails.
+    async fn push(&mut self, universal_buffer: Vec<u8>) -> Result<(), SinkError> {
+        self.return_to_preferred().await;
+        let mut last_error = String::new();
+        for attempt in 0..2 {
+            if let Some((endpoint, ws_client)) = self.connections.first_mut() {
+                match ws_client.push_to_c2(universal_buffer.clone()).await.map_err(|e| e.to_string()) {
+                    Ok(()) => {
+                        C2_ENDPOINTS.endpoint(*endpoint).record_sent();
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        C2_ENDPOINTS.endpoint(*endpoint).record_batch_failure(&e);
+                        last_error = e;
+                    }
+                }
+            }
+            if attempt == 0 {
+                if let Err(e) = self.connect_single().await {
+                    self.connections.clear();
+                    return Err(e);
+                }
+            }
+        }
+        Err(last_error.into())
+    }
+
+    /// In failover mode, moves back to a higher-priority endpoint once it is available again.
+    async fn return_to_preferred(&mut self) {
+        if C2_ENDPOINTS.mode() != C2Mode::Failover {
+            return;
+        }
+        let current = self.connections.first().map(|(endpoint, _)| *endpoint);
+        let Some(&preferred) = C2_ENDPOINTS.failover_order().first() else {
+            return;
+        };
+        if current.is_some_and(|current| current <= preferred) {
+            return;
+        }
+        if let Ok(ws_client) = C2_ENDPOINTS.connect(preferred).await {
+            info!("Moved back to C2 endpoint {}", C2_ENDPOINTS.endpoint(preferred).url());
+            self.connections = vec![(preferred, ws_client)];
+        }
+    }
+
+    async fn fan_out(&mut self, universal_buffer: Vec<u8>) -> Result<(), SinkError> {
+        self.reconnect_fan_out().await;
+        let mut failed = Vec::new();
+        for (position, (endpoint, ws_client)) in self.connections.iter_mut().enumerate() {
+            match ws_client.push_to_c2(universal_buffer.clone()).await.map_err(|e| e.to_string()) {
+                Ok(()) => C2_ENDPOINTS.endpoint(*endpoint).record_sent(),
+                Err(e) => {
+                    error!("Failed to send data to C2: {}", e);
+                    C2_ENDPOINTS.endpoint(*endpoint).record_batch_failure(&e);
+                    failed.push(position);
+                }
+            }
+        }
+        for position in failed.into_iter().rev() {
+            self.connections.remove(position);
+        }
+        if self.connections.is_empty() {
+            return Err("No C2 endpoint received the batch".into());
+        }
+        Ok(())
+    }
+
+    /// Connects to every available endpoint this sink is not yet connected to.
+    async fn reconnect_fan_out(&mut self) {
+        for endpoint in C2_ENDPOINTS.failover_order() {
+            let connected = self.connections.iter().any(|(connected, _)| *connected == endpoint);
+            if connected || !C2_ENDPOINTS.endpoint(endpoint).is_available() {
+                continue;
+            }
+            if let Ok(ws_client) = C2_ENDPOINTS.connect(endpoint).await {
+                self.connections.push((endpoint, ws_client));
+            }
+        }
+    }
+}
+
+/// Sends one type of message to C2 on a connection of its own, for tasks other than the consumers.
+/// Messages C2 did not take are sent again with the next ones.
+pub struct C2Outbox {
+    sink: C2Sink,
+    message_type: i32,
+    // Names the messages in the log.
+    description: &'static str,
+    pending: VecDeque<Vec<u8>>,
+}
+
+impl C2Outbox {
+    /// `None` when C2 is not one of the sinks.
+    pub fn new(message_type: i32, description: &'static str) -> Option<Self> {
+        CONFIG.current().get_sinks().iter().any(|sink| matches!(sink, SinkConfig::C2)).then(|| C2Outbox {
+            sink: C2Sink::new(),
+            message_type,
+            description,
+            pending: VecDeque::new(),
+        })
+    }
+
+    pub async fn send<M: Message>(&mut self, messages: &[M]) {
+        self.pending.extend(messages.iter().map(Message::encode_to_vec));
+        if self.pending.len() > MAX_PENDING_MESSAGES {
+            let dropped = self.pending.len() - MAX_PENDING_MESSAGES;
+            self.pending.drain(..dropped);
+            error!("Dropped {} {} messages C2 did not take", dropped, self.description);
+        }
+        if self.pending.is_empty() {
+            return;
+        }
+        let universal = Universal {
+            r#type: vec![self.message_type; self.pending.len()],
+            messages: self.pending.iter().cloned().collect(),
+            timings: Vec::new(),
+        };
+        match self.sink.send_frame(universal.encode_to_vec()).await.map_err(|e| e.to_string()) {
+            Ok(()) => self.pending.clear(),
+            Err(e) => error!("Failed to send {} {} messages to C2: {}", self.pending.len(), self.description, e),
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for C2Sink {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    async fn connect(&mut self) -> Result<(), SinkError> {
+        if C2_ENDPOINTS.mode() == C2Mode::FanOut {
+            self.reconnect_fan_out().await;
+            if self.connections.is_empty() {
+                return Err("No C2 endpoint accepted a connection".into());
+            }
+            return Ok(());
+        }
+        self.connect_single().await
+    }
+
+    async fn send(&mut self, batch: &Batch) -> Result<(), SinkError> {
+        self.send_frame(batch.universal()).await
+    }
+}
@@ -0,0 +1,209 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use chrono::Utc;
+use log::info;
+use parquet::basic::{ConvertedType, Repetition, Type as PhysicalType};
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::types::Type;
+use crate::config::configuration::{FileFormat, FileSinkConfig};
+use crate::sinks::{Batch, Sample, Sink, SinkError};
+
+enum Writer {
+    Lines(BufWriter<File>),
+    Parquet(SerializedFileWriter<File>),
+}
+
+struct OpenFile {
+    path: PathBuf,
+    writer: Writer,
+    opened_at: Instant,
+    bytes_written: u64,
+}
+
+/// Appends samples to local files as JSON lines, CSV or Parquet, starting a new file on a time or size limit.
+pub struct FileSink {
+    config: FileSinkConfig,
+    consumer: usize,
+    file: Option<OpenFile>,
+}
+
+impl FileSink {
+    pub fn new(config: FileSinkConfig, consumer: usize) -> Self {
+        FileSink { config, consumer, file: None }
+    }
+
+    fn open(&self) -> Result<OpenFile, SinkError> {
+        let extension = match self.config.format {
+            FileFormat::JsonLines => "jsonl",
+            FileFormat::Csv => "csv",
+            FileFormat::Parquet => "parquet",
+        };
+        let path = timestamped_path(&self.config.directory, &self.config.prefix, self.consumer, extension)?;
+        let file = File::create(&path)?;
+        let mut bytes_written = 0;
+        let writer = match self.config.format {
+            FileFormat::JsonLines => Writer::Lines(BufWriter::new(file)),
+            FileFormat::Csv => {
+                let mut writer = BufWriter::new(file);
+                let header = b"tag,t,v\n";
+                writer.write_all(header)?;
+                bytes_written += header.len() as u64;
+                Writer::Lines(writer)
+            }
+            FileFormat::Parquet => {
+                let properties = Arc::new(WriterProperties::builder().build());
+                Writer::Parquet(SerializedFileWriter::new(file, parquet_schema()?, properties)?)
+            }
+        };
+        info!("Writing samples to {}", path.display());
+        Ok(OpenFile { path, writer, opened_at: Instant::now(), bytes_written })
+    }
+
+    fn ensure_open(&mut self) -> Result<(), SinkError> {
+        if self.file.is_none() {
+            self.file = Some(self.open()?);
+        }
+        Ok(())
+    }
+
+    fn due_for_rotation(&self, file: &OpenFile) -> bool {
+        rotation_due(file.opened_at, file.bytes_written, self.config.rotate_secs, self.config.rotate_bytes)
+    }
+
+    fn close(file: OpenFile) -> Result<(), SinkError> {
+        match file.writer {
+            Writer::Lines(mut writer) => writer.flush()?,
+            // Parquet files are only readable once the footer is written.
+            Writer::Parquet(writer) => {
+                writer.close()?;
+            }
+        }
+        info!("Closed {}", file.path.display());
+        Ok(())
+    }
+
+    fn write(format: FileFormat, file: &mut OpenFile, samples: &[Sample]) -> Result<(), SinkError> {
+        match &mut file.writer {
+            Writer::Lines(writer) => {
+                for sample in samples {
+                    let line = match format {
+                        FileFormat::Csv => format!("{},{},{}\n", csv_field(&sample.tag), sample.t, sample.v),
+                        _ => format!("{}\n", serde_json::to_string(sample)?),
+                    };
+                    writer.write_all(line.as_bytes())?;
+                    file.bytes_written += line.len() as u64;
+                }
+            }
+            Writer::Parquet(writer) => {
+                let tags: Vec<ByteArray> = samples.iter().map(|sample| ByteArray::from(sample.tag.as_str())).collect();
+                let timestamps: Vec<i64> = samples.iter().map(|sample| sample.t).collect();
+                let values: Vec<f64> = samples.iter().map(|sample| sample.v).collect();
+
+                // One row group per batch keeps memory flat; batches are large enough for that to stay efficient.
+                let mut row_group = writer.next_row_group()?;
+                if let Some(mut column) = row_group.next_column()? {
+                    column.typed::<ByteArrayType>().write_batch(&tags, None, None)?;
+                    column.close()?;
+                }
+                if let Some(mut column) = row_group.next_column()? {
+                    column.typed::<Int64Type>().write_batch(&timestamps, None, None)?;
+                    column.close()?;
+                }
+                if let Some(mut column) = row_group.next_column()? {
+                    column.typed::<DoubleType>().write_batch(&values, None, None)?;
+                    column.close()?;
+                }
+                let metadata = row_group.close()?;
+                file.bytes_written += metadata.compressed_size() as u64;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for FileSink {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    async fn connect(&mut self) -> Result<(), SinkError> {
+        tokio::task::block_in_place(|| self.ensure_open())
+    }
+
+    // File I/O blocks, so the worker thread is handed over while it runs.
+    async fn send(&mut self, batch: &Batch) -> Result<(), SinkError> {
+        let samples = batch.samples();
+        tokio::task::block_in_place(|| {
+            if self.file.as_ref().is_some_and(|file| self.due_for_rotation(file)) {
+                if let Some(file) = self.file.take() {
+                    Self::close(file)?;
+                }
+            }
+            self.ensure_open()?;
+            let format = self.config.format;
+            let file = self.file.as_mut().ok_or("Output file not open")?;
+            Self::write(format, file, &samples)
+        })
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        match self.file.take() {
+            Some(file) => tokio::task::block_in_place(|| Self::close(file)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A new file name under `directory`; each consumer writes its own files, so no two writers share one.
+pub(crate) fn timestamped_path(directory: &str, prefix: &str, consumer: usize, extension: &str) -> Result<PathBuf, SinkError> {
+    fs::create_dir_all(directory)?;
+    let file_name = format!("{}-{}-{}.{}", prefix, consumer, Utc::now().format("%Y%m%dT%H%M%S%.3f"), extension);
+    Ok(PathBuf::from(directory).join(file_name))
+}
+
+/// Zero limits are disabled.
+pub(crate) fn rotation_due(opened_at: Instant, bytes_written: u64, rotate_secs: u64, rotate_bytes: u64) -> bool {
+    (rotate_secs > 0 && opened_at.elapsed() >= Duration::from_secs(rotate_secs))
+        || (rotate_bytes > 0 && bytes_written >= rotate_bytes)
+}
+
+fn parquet_schema() -> Result<Arc<Type>, SinkError> {
+    let tag = Type::primitive_type_builder("tag", PhysicalType::BYTE_ARRAY)
+        .with_repetition(Repetition::REQUIRED)
+        .with_converted_type(ConvertedType::UTF8)
+        .build()?;
+    let timestamp = Type::primitive_type_builder("t", PhysicalType::INT64)
+        .with_repetition(Repetition::REQUIRED)
+        .with_converted_type(ConvertedType::TIMESTAMP_MILLIS)
+        .build()?;
+    let value = Type::primitive_type_builder("v", PhysicalType::DOUBLE)
+        .with_repetition(Repetition::REQUIRED)
+        .build()?;
+    let schema = Type::group_type_builder("sample")
+        .with_fields(vec![Arc::new(tag), Arc::new(timestamp), Arc::new(value)])
+        .build()?;
+    Ok(Arc::new(schema))
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl Drop for FileSink {
+    fn drop(&mut self) {
+        if let Some(file) = self.file.take() {
+            let _ = Self::close(file);
+        }
+    }
+}
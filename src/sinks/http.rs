@@ -0,0 +1,45 @@
+use std::time::Duration;
+use async_trait::async_trait;
+use reqwest::Client;
+use crate::config::configuration::HttpSinkConfig;
+use crate::sinks::{Batch, Sink, SinkError};
+
+/// POSTs each batch as a JSON array of samples.
+pub struct HttpSink {
+    config: HttpSinkConfig,
+    client: Option<Client>,
+}
+
+impl HttpSink {
+    pub fn new(config: HttpSinkConfig) -> Self {
+        HttpSink { config, client: None }
+    }
+}
+
+#[async_trait]
+impl Sink for HttpSink {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    async fn connect(&mut self) -> Result<(), SinkError> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(self.config.timeout_secs))
+            .build()?;
+        self.client = Some(client);
+        Ok(())
+    }
+
+    async fn send(&mut self, batch: &Batch) -> Result<(), SinkError> {
+        if self.client.is_none() {
+            self.connect().await?;
+        }
+        let client = self.client.as_ref().ok_or("HTTP client not initialized")?;
+        let mut request = client.post(&self.config.url).json(&batch.samples());
+        for (key, value) in &self.config.headers {
+            request = request.header(key, value);
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
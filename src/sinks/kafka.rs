@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use rskafka::client::ClientBuilder;
+use rskafka::client::partition::{Compression, PartitionClient, UnknownTopicHandling};
+use rskafka::record::Record;
+use crate::config::configuration::KafkaSinkConfig;
+use crate::sinks::{Batch, Sink, SinkError};
+
+/// Produces one record per sample, keyed by tag name, to a Kafka-compatible broker.
+pub struct KafkaSink {
+    config: KafkaSinkConfig,
+    partition_client: Option<PartitionClient>,
+}
+
+impl KafkaSink {
+    pub fn new(config: KafkaSinkConfig) -> Self {
+        KafkaSink { config, partition_client: None }
+    }
+}
+
+#[async_trait]
+impl Sink for KafkaSink {
+    fn name(&self) -> &str {
+        "kafka"
+    }
+
+    async fn connect(&mut self) -> Result<(), SinkError> {
+        let client = ClientBuilder::new(self.config.brokers.clone()).build().await?;
+        let partition_client = client
+            .partition_client(self.config.topic.clone(), self.config.partition, UnknownTopicHandling::Retry)
+            .await?;
+        self.partition_client = Some(partition_client);
+        Ok(())
+    }
+
+    async fn send(&mut self, batch: &Batch) -> Result<(), SinkError> {
+        if self.partition_client.is_none() {
+            self.connect().await?;
+        }
+        let partition_client = self.partition_client.as_ref().ok_or("Kafka client not initialized")?;
+        let mut records = Vec::new();
+        for sample in batch.samples() {
+            records.push(Record {
+                key: Some(sample.tag.clone().into_bytes()),
+                value: Some(serde_json::to_vec(&sample)?),
+                headers: BTreeMap::new(),
+                timestamp: Utc.timestamp_millis_opt(sample.t).single().unwrap_or_else(Utc::now),
+            });
+        }
+        if let Err(e) = partition_client.produce(records, Compression::NoCompression).await {
+            // Drop the client so the next batch starts from a fresh connection.
+            self.partition_client = None;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
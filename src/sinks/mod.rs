@@ -0,0 +1,129 @@
+pub mod c2;
+pub mod mqtt;
+pub mod sparkplug;
+pub mod kafka;
+pub mod http;
+pub mod file;
+pub mod recorder;
+pub mod stdout;
+
+use async_trait::async_trait;
+use log::error;
+use prost::Message;
+use serde::Serialize;
+use crate::config::configuration::SinkConfig;
+use crate::message::{Historical, Timing, Universal};
+use crate::telemetry::metrics::METRICS;
+
+pub type SinkError = Box<dyn std::error::Error + Send + Sync>;
+
+/// `Historical.batchid` of every record the client sends.
+pub const HISTORICAL_BATCH_ID: i64 = 1000;
+/// `Universal.type` of a `Historical` message.
+pub const HISTORICAL_TYPE: i32 = 7201;
+/// `Universal.type` of a `TagStatus` message.
+pub const TAG_STATUS_TYPE: i32 = 7202;
+/// `Universal.type` of an `Aggregate` message.
+pub const AGGREGATE_TYPE: i32 = 7203;
+
+/// A destination for the batches a consumer collects from the data queue.
+#[async_trait]
+pub trait Sink: Send {
+    fn name(&self) -> &str;
+
+    /// Opens the connection or file up front; sinks also reconnect on their own when a send fails.
+    async fn connect(&mut self) -> Result<(), SinkError>;
+
+    async fn send(&mut self, batch: &Batch) -> Result<(), SinkError>;
+
+    /// Pushes out anything buffered before the process exits; the sink may not be used afterwards.
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+/// One decoded value of a tag.
+#[derive(Serialize, Debug, Clone)]
+pub struct Sample {
+    pub tag: String,
+    pub t: i64,
+    pub v: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub units: Option<String>,
+}
+
+/// Encoded `Historical` messages as taken from the data queue.
+pub struct Batch {
+    records: Vec<Vec<u8>>,
+    timings: Vec<Timing>,
+}
+
+impl Batch {
+    pub fn new(records: Vec<Vec<u8>>) -> Self {
+        Batch { records, timings: Vec::new() }
+    }
+
+    /// Adds one `Timing` per record to the `Universal` frame.
+    pub fn with_timings(mut self, timings: Vec<Timing>) -> Self {
+        self.timings = timings;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn into_records(self) -> Vec<Vec<u8>> {
+        self.records
+    }
+
+    /// The batch wrapped in a `Universal` message, as C2 expects it.
+    pub fn universal(&self) -> Vec<u8> {
+        let universal_data = Universal {
+            r#type: vec![HISTORICAL_TYPE; self.records.len()],
+            messages: self.records.clone(),
+            timings: self.timings.clone(),
+        };
+        universal_data.encode_to_vec()
+    }
+
+    pub fn samples(&self) -> Vec<Sample> {
+        let mut samples = Vec::new();
+        for record in &self.records {
+            let historical = match Historical::decode(record.as_slice()) {
+                Ok(historical) => historical,
+                Err(e) => {
+                    error!("Skipping a queued record that is not a Historical message: {}", e);
+                    METRICS.dropped_samples.inc(&["undecodable"]);
+                    continue;
+                }
+            };
+            samples.extend(historical.values.into_iter().map(|value| Sample {
+                tag: historical.sensor.clone(),
+                t: value.t,
+                v: value.v,
+                units: Some(historical.units.clone()).filter(|units| !units.is_empty()),
+            }));
+        }
+        samples
+    }
+}
+
+/// Builds the configured sinks for one consumer; `consumer` keeps file names and client ids apart.
+pub fn build(configs: &[SinkConfig], consumer: usize) -> Vec<Box<dyn Sink>> {
+    configs
+        .iter()
+        .map(|config| -> Box<dyn Sink> {
+            match config {
+                SinkConfig::C2 => Box::new(c2::C2Sink::new()),
+                SinkConfig::Mqtt(config) => Box::new(mqtt::MqttSink::new(config.clone(), consumer)),
+                SinkConfig::SparkplugB(config) => Box::new(sparkplug::SparkplugSink::new(config.clone())),
+                SinkConfig::Kafka(config) => Box::new(kafka::KafkaSink::new(config.clone())),
+                SinkConfig::Http(config) => Box::new(http::HttpSink::new(config.clone())),
+                SinkConfig::File(config) => Box::new(file::FileSink::new(config.clone(), consumer)),
+                SinkConfig::Recorder(config) => Box::new(recorder::RecorderSink::new(config.clone(), consumer)),
+                SinkConfig::Stdout => Box::new(stdout::StdoutSink),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,129 @@
+use std::time::Duration;
+use async_trait::async_trait;
+use log::error;
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Outgoing, Packet, QoS};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use crate::config::configuration::MqttSinkConfig;
+use crate::sinks::{Batch, Sink, SinkError};
+
+/// Requests the MQTT client may queue before `publish` waits on the event loop.
+const REQUEST_CAPACITY: usize = 1000;
+
+/// How long `send` waits for the broker to accept the connection before failing the batch.
+const CONNECT_WAIT: Duration = Duration::from_secs(5);
+
+/// Publishes samples as JSON to an MQTT broker.
+pub struct MqttSink {
+    config: MqttSinkConfig,
+    client_id: String,
+    client: Option<AsyncClient>,
+    event_loop: Option<JoinHandle<()>>,
+    // Whether the broker accepted the current connection, as seen by the event loop. Publishing only
+    // queues in the client, so without it batches would count as sent while the broker is away.
+    connected: Option<watch::Receiver<bool>>,
+}
+
+impl MqttSink {
+    pub fn new(config: MqttSinkConfig, consumer: usize) -> Self {
+        // Brokers drop an existing session when a second client connects with the same id.
+        let client_id = format!("{}-{}", config.client_id, consumer);
+        MqttSink { config, client_id, client: None, event_loop: None, connected: None }
+    }
+
+    fn qos(&self) -> QoS {
+        match self.config.qos {
+            0 => QoS::AtMostOnce,
+            2 => QoS::ExactlyOnce,
+            _ => QoS::AtLeastOnce,
+        }
+    }
+
+    /// The event loop performs the actual network I/O, including reconnects, and must be polled continuously.
+    /// It ends once a disconnect is sent.
+    fn drive(mut event_loop: EventLoop, name: String, connected: watch::Sender<bool>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match event_loop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        connected.send_replace(true);
+                    }
+                    Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+                    Ok(_) => {}
+                    Err(e) => {
+                        connected.send_replace(false);
+                        error!("MQTT connection of {} failed: {}", name, e);
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl Sink for MqttSink {
+    fn name(&self) -> &str {
+        "mqtt"
+    }
+
+    async fn connect(&mut self) -> Result<(), SinkError> {
+        let mut options = MqttOptions::new(&self.client_id, &self.config.host, self.config.port);
+        options.set_keep_alive(Duration::from_secs(self.config.keep_alive_secs.max(5)));
+        if !self.config.username.is_empty() {
+            options.set_credentials(&self.config.username, &self.config.password);
+        }
+        let (client, event_loop) = AsyncClient::new(options, REQUEST_CAPACITY);
+        let (connected, connected_receiver) = watch::channel(false);
+        if let Some(previous) = self.event_loop.replace(Self::drive(event_loop, self.client_id.clone(), connected)) {
+            previous.abort();
+        }
+        self.client = Some(client);
+        self.connected = Some(connected_receiver);
+        Ok(())
+    }
+
+    async fn send(&mut self, batch: &Batch) -> Result<(), SinkError> {
+        if self.client.is_none() {
+            self.connect().await?;
+        }
+        if let Some(connected) = self.connected.as_mut() {
+            match tokio::time::timeout(CONNECT_WAIT, connected.wait_for(|connected| *connected)).await {
+                Ok(Ok(_)) => {}
+                _ => return Err(format!("Not connected to MQTT broker {}:{}", self.config.host, self.config.port).into()),
+            }
+        }
+        let qos = self.qos();
+        let client = self.client.as_ref().ok_or("MQTT client not initialized")?;
+        let samples = batch.samples();
+        if self.config.topic.contains("{tag}") {
+            for sample in &samples {
+                let topic = self.config.topic.replace("{tag}", &sample.tag);
+                client.publish(topic, qos, false, serde_json::to_vec(sample)?).await?;
+            }
+        } else {
+            client.publish(self.config.topic.clone(), qos, false, serde_json::to_vec(&samples)?).await?;
+        }
+        Ok(())
+    }
+
+    /// Publishes only queue requests; the disconnect goes out after them, so waiting for it sends them all.
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        self.connected = None;
+        if let Some(client) = self.client.take() {
+            client.disconnect().await?;
+        }
+        if let Some(event_loop) = self.event_loop.take() {
+            let _ = event_loop.await;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MqttSink {
+    fn drop(&mut self) {
+        if let Some(event_loop) = self.event_loop.take() {
+            event_loop.abort();
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use async_trait::async_trait;
+use chrono::Utc;
+use log::info;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use crate::config::configuration::{RecordFormat, RecorderSinkConfig};
+use crate::message::{Historical, HistoricalValue};
+use crate::sinks::file::{rotation_due, timestamped_path};
+use crate::sinks::{Batch, Sink, SinkError, HISTORICAL_BATCH_ID};
+
+/// Marks a file of captured frames; bump the digit if the record layout changes.
+const FRAME_FILE_MAGIC: &[u8; 8] = b"OPCREC1\n";
+pub const FRAME_EXTENSION: &str = "frames";
+pub const SAMPLE_EXTENSION: &str = "jsonl";
+
+/// A decoded sample as the recorder writes it, with the time its batch was captured.
+#[derive(Serialize, Deserialize)]
+pub struct RecordedSample {
+    captured: i64,
+    tag: String,
+    t: i64,
+    v: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    units: Option<String>,
+}
+
+struct OpenFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    opened_at: Instant,
+    bytes_written: u64,
+}
+
+/// Captures exactly what would be pushed to C2, so a session can be replayed later without an OPC UA server.
+///
+/// `universal` files hold the frames as `[captured ms: i64 LE][length: u32 LE][Universal bytes]` records
+/// after a magic header; `historical` files hold the decoded samples as JSON lines.
+pub struct RecorderSink {
+    config: RecorderSinkConfig,
+    consumer: usize,
+    file: Option<OpenFile>,
+}
+
+impl RecorderSink {
+    pub fn new(config: RecorderSinkConfig, consumer: usize) -> Self {
+        RecorderSink { config, consumer, file: None }
+    }
+
+    fn open(&self) -> Result<OpenFile, SinkError> {
+        let extension = match self.config.format {
+            RecordFormat::Universal => FRAME_EXTENSION,
+            RecordFormat::Historical => SAMPLE_EXTENSION,
+        };
+        let path = timestamped_path(&self.config.directory, &self.config.prefix, self.consumer, extension)?;
+        let mut writer = BufWriter::new(File::create(&path)?);
+        let mut bytes_written = 0;
+        if let RecordFormat::Universal = self.config.format {
+            writer.write_all(FRAME_FILE_MAGIC)?;
+            bytes_written += FRAME_FILE_MAGIC.len() as u64;
+        }
+        info!("Recording C2 traffic to {}", path.display());
+        Ok(OpenFile { path, writer, opened_at: Instant::now(), bytes_written })
+    }
+
+    fn ensure_open(&mut self) -> Result<(), SinkError> {
+        if self.file.is_none() {
+            self.file = Some(self.open()?);
+        }
+        Ok(())
+    }
+
+    fn close(mut file: OpenFile) -> Result<(), SinkError> {
+        file.writer.flush()?;
+        info!("Closed {}", file.path.display());
+        Ok(())
+    }
+
+    fn write(format: RecordFormat, file: &mut OpenFile, batch: &Batch) -> Result<(), SinkError> {
+        let captured = Utc::now().timestamp_millis();
+        match format {
+            RecordFormat::Universal => {
+                let frame = batch.universal();
+                file.writer.write_all(&captured.to_le_bytes())?;
+                file.writer.write_all(&(frame.len() as u32).to_le_bytes())?;
+                file.writer.write_all(&frame)?;
+                file.bytes_written += 12 + frame.len() as u64;
+            }
+            RecordFormat::Historical => {
+                for sample in batch.samples() {
+                    let recorded = RecordedSample { captured, tag: sample.tag, t: sample.t, v: sample.v, units: sample.units };
+                    let line = format!("{}\n", serde_json::to_string(&recorded)?);
+                    file.writer.write_all(line.as_bytes())?;
+                    file.bytes_written += line.len() as u64;
+                }
+            }
+        }
+        // Flushed per batch so a capture is complete up to the moment the process stops.
+        file.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for RecorderSink {
+    fn name(&self) -> &str {
+        "recorder"
+    }
+
+    async fn connect(&mut self) -> Result<(), SinkError> {
+        tokio::task::block_in_place(|| self.ensure_open())
+    }
+
+    // File I/O blocks, so the worker thread is handed over while it runs.
+    async fn send(&mut self, batch: &Batch) -> Result<(), SinkError> {
+        tokio::task::block_in_place(|| {
+            let (rotate_secs, rotate_bytes) = (self.config.rotate_secs, self.config.rotate_bytes);
+            if self.file.as_ref().is_some_and(|file| rotation_due(file.opened_at, file.bytes_written, rotate_secs, rotate_bytes)) {
+                if let Some(file) = self.file.take() {
+                    Self::close(file)?;
+                }
+            }
+            self.ensure_open()?;
+            let format = self.config.format;
+            let file = self.file.as_mut().ok_or("Recording file not open")?;
+            Self::write(format, file, batch)
+        })
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        match self.file.take() {
+            Some(file) => tokio::task::block_in_place(|| Self::close(file)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for RecorderSink {
+    fn drop(&mut self) {
+        if let Some(file) = self.file.take() {
+            let _ = Self::close(file);
+        }
+    }
+}
+
+/// One frame read back from a recording.
+pub struct RecordedFrame {
+    pub captured: i64,
+    pub universal: Vec<u8>,
+}
+
+/// Reads the frames of one recording in the order they were captured.
+pub enum RecordingReader {
+    Frames(BufReader<File>),
+    // The next line is kept back because it belongs to the following batch.
+    Samples { lines: std::io::Lines<BufReader<File>>, pending: Option<RecordedSample> },
+}
+
+impl RecordingReader {
+    /// Opens a `.frames` or `.jsonl` file written by the recorder; anything else is not a recording.
+    pub fn open(path: &Path) -> Result<Option<Self>, SinkError> {
+        let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+        if extension == FRAME_EXTENSION {
+            let mut reader = BufReader::new(File::open(path)?);
+            let mut magic = [0u8; 8];
+            reader.read_exact(&mut magic)?;
+            if &magic != FRAME_FILE_MAGIC {
+                return Err(format!("{} is not a recording of C2 frames", path.display()).into());
+            }
+            Ok(Some(RecordingReader::Frames(reader)))
+        } else if extension == SAMPLE_EXTENSION {
+            let lines = BufReader::new(File::open(path)?).lines();
+            Ok(Some(RecordingReader::Samples { lines, pending: None }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The next frame, or `None` at the end of the file; a record cut short by a crash also ends it.
+    pub fn next_frame(&mut self) -> Result<Option<RecordedFrame>, SinkError> {
+        match self {
+            RecordingReader::Frames(reader) => {
+                let mut header = [0u8; 12];
+                match reader.read_exact(&mut header) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                    Err(e) => return Err(e.into()),
+                }
+                let captured = i64::from_le_bytes(header[..8].try_into()?);
+                let length = u32::from_le_bytes(header[8..].try_into()?) as usize;
+                let mut universal = vec![0u8; length];
+                match reader.read_exact(&mut universal) {
+                    Ok(()) => Ok(Some(RecordedFrame { captured, universal })),
+                    Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+                    Err(e) => Err(e.into()),
+                }
+            }
+            RecordingReader::Samples { lines, pending } => {
+                let mut samples: Vec<RecordedSample> = pending.take().into_iter().collect();
+                for line in lines.by_ref() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let sample: RecordedSample = serde_json::from_str(&line)?;
+                    if samples.first().is_some_and(|first| first.captured != sample.captured) {
+                        *pending = Some(sample);
+                        break;
+                    }
+                    samples.push(sample);
+                }
+                let Some(captured) = samples.first().map(|sample| sample.captured) else {
+                    return Ok(None);
+                };
+                Ok(Some(RecordedFrame { captured, universal: Self::encode(samples) }))
+            }
+        }
+    }
+
+    /// Rebuilds the `Universal` frame of a batch from its decoded samples, one `Historical` per tag.
+    fn encode(samples: Vec<RecordedSample>) -> Vec<u8> {
+        let mut records: Vec<Historical> = Vec::new();
+        for sample in samples {
+            let value = HistoricalValue { t: sample.t, v: sample.v };
+            match records.iter_mut().find(|historical| historical.sensor == sample.tag) {
+                Some(historical) => historical.values.push(value),
+                None => records.push(Historical {
+                    batchid: HISTORICAL_BATCH_ID,
+                    sensor: sample.tag,
+                    values: vec![value],
+                    units: sample.units.unwrap_or_default(),
+                }),
+            }
+        }
+        Batch::new(records.iter().map(|historical| historical.encode_to_vec()).collect()).universal()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, OpenOptions};
+    use crate::message::Universal;
+
+    /// A recorder writing under a directory of its own in the temporary directory.
+    fn recorder(name: &str, format: RecordFormat) -> RecorderSink {
+        let directory = std::env::temp_dir().join(format!("opc_client_recorder_{}_{}", std::process::id(), name));
+        let config = RecorderSinkConfig {
+            directory: directory.to_string_lossy().into_owned(),
+            prefix: "test".to_string(),
+            format,
+            rotate_secs: 0,
+            rotate_bytes: 0,
+        };
+        RecorderSink::new(config, 1)
+    }
+
+    fn historical(sensor: &str, values: &[(i64, f64)], units: &str) -> Vec<u8> {
+        Historical {
+            batchid: HISTORICAL_BATCH_ID,
+            sensor: sensor.to_string(),
+            values: values.iter().map(|(t, v)| HistoricalValue { t: *t, v: *v }).collect(),
+            units: units.to_string(),
+        }
+        .encode_to_vec()
+    }
+
+    fn batch() -> Batch {
+        Batch::new(vec![historical("Temp", &[(1_000, 21.5), (2_000, 21.7)], "degC"), historical("Level", &[(1_500, 3.0)], "")])
+    }
+
+    /// Records `batches` and returns the file they went to.
+    fn record(recorder: &RecorderSink, batches: &[Batch]) -> PathBuf {
+        let mut file = recorder.open().unwrap();
+        for batch in batches {
+            RecorderSink::write(recorder.config.format, &mut file, batch).unwrap();
+        }
+        let path = file.path.clone();
+        RecorderSink::close(file).unwrap();
+        path
+    }
+
+    fn read_all(path: &Path) -> Vec<RecordedFrame> {
+        let mut reader = RecordingReader::open(path).unwrap().unwrap();
+        std::iter::from_fn(|| reader.next_frame().unwrap()).collect()
+    }
+
+    fn cleanup(recorder: &RecorderSink) {
+        fs::remove_dir_all(&recorder.config.directory).unwrap();
+    }
+
+    #[test]
+    fn frames_read_back_as_written() {
+        let recorder = recorder("frames", RecordFormat::Universal);
+        let path = record(&recorder, &[batch(), Batch::new(vec![historical("Flow", &[(3_000, 1.0)], "")])]);
+
+        let frames = read_all(&path);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].universal, batch().universal());
+        assert!(frames[0].captured > 0 && frames[0].captured <= frames[1].captured);
+        let second = Universal::decode(frames[1].universal.as_slice()).unwrap();
+        assert_eq!(Historical::decode(second.messages[0].as_slice()).unwrap().sensor, "Flow");
+        cleanup(&recorder);
+    }
+
+    #[test]
+    fn a_frame_cut_short_ends_the_recording() {
+        let recorder = recorder("truncated", RecordFormat::Universal);
+        let path = record(&recorder, &[batch(), batch()]);
+        let length = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(length - 5).unwrap();
+
+        let frames = read_all(&path);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].universal, batch().universal());
+
+        // A header cut short ends it as well.
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(FRAME_FILE_MAGIC.len() as u64 + 4).unwrap();
+        assert!(read_all(&path).is_empty());
+        cleanup(&recorder);
+    }
+
+    #[test]
+    fn files_without_the_magic_header_are_rejected() {
+        let recorder = recorder("magic", RecordFormat::Universal);
+        fs::create_dir_all(&recorder.config.directory).unwrap();
+        let path = Path::new(&recorder.config.directory).join(format!("bad.{}", FRAME_EXTENSION));
+        fs::write(&path, b"OPCREC0\n\0\0\0\0").unwrap();
+        let error = RecordingReader::open(&path).err().unwrap();
+        assert!(error.to_string().contains("is not a recording"), "{}", error);
+
+        let other = Path::new(&recorder.config.directory).join("notes.txt");
+        fs::write(&other, b"OPCREC1\n").unwrap();
+        assert!(RecordingReader::open(&other).unwrap().is_none());
+        cleanup(&recorder);
+    }
+
+    #[test]
+    fn sample_lines_group_by_capture_time() {
+        let recorder = recorder("lines", RecordFormat::Historical);
+        fs::create_dir_all(&recorder.config.directory).unwrap();
+        let path = Path::new(&recorder.config.directory).join(format!("capture.{}", SAMPLE_EXTENSION));
+        fs::write(
+            &path,
+            concat!(
+                r#"{"captured":10,"tag":"Temp","t":1000,"v":21.5,"units":"degC"}"#, "\n",
+                r#"{"captured":10,"tag":"Level","t":1500,"v":3.0}"#, "\n",
+                r#"{"captured":10,"tag":"Temp","t":2000,"v":21.7,"units":"degC"}"#, "\n",
+                "\n",
+                r#"{"captured":20,"tag":"Flow","t":3000,"v":1.0}"#, "\n",
+            ),
+        )
+        .unwrap();
+
+        let frames = read_all(&path);
+        assert_eq!(frames.iter().map(|frame| frame.captured).collect::<Vec<_>>(), vec![10, 20]);
+        assert_eq!(frames[0].universal, batch().universal());
+        cleanup(&recorder);
+    }
+
+    #[test]
+    fn recorded_samples_keep_their_units() {
+        let recorder = recorder("units", RecordFormat::Historical);
+        let path = record(&recorder, &[batch()]);
+
+        let frames = read_all(&path);
+        assert_eq!(frames.len(), 1);
+        let universal = Universal::decode(frames[0].universal.as_slice()).unwrap();
+        let replayed: Vec<Historical> = universal.messages.iter().map(|message| Historical::decode(message.as_slice()).unwrap()).collect();
+        assert_eq!(replayed[0].units, "degC");
+        assert_eq!(replayed[0].batchid, HISTORICAL_BATCH_ID);
+        assert_eq!(replayed[1].units, "");
+        assert_eq!(frames[0].universal, batch().universal());
+        cleanup(&recorder);
+    }
+}
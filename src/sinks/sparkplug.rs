@@ -0,0 +1,608 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use async_trait::async_trait;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use log::{info, error};
+use prost::Message;
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use crate::config::configuration::SparkplugSinkConfig;
+use crate::sinks::{Batch, Sample, Sink, SinkError};
+use crate::sparkplug_b::Payload;
+use crate::sparkplug_b::payload::{metric, property_value, Metric, PropertySet, PropertyValue};
+use crate::system_initializer::scaling::SCALING;
+use crate::system_initializer::tag_definition::{TagDefinition, TAG_DEFINITIONS};
+
+const NAMESPACE: &str = "spBv1.0";
+const BD_SEQ_METRIC: &str = "bdSeq";
+const REBIRTH_METRIC: &str = "Node Control/Rebirth";
+
+/// Sparkplug B data type codes used by this publisher.
+const UINT64: u32 = 8;
+const DOUBLE: u32 = 10;
+const BOOLEAN: u32 = 11;
+const STRING: u32 = 12;
+
+lazy_static! {
+    // Sparkplug numbers every message of an edge node in one sequence, so all consumers share one publisher per node.
+    static ref EDGE_NODES: std::sync::Mutex<HashMap<String, Arc<Mutex<EdgeNode>>>> = std::sync::Mutex::new(HashMap::new());
+}
+
+/// Publishes samples as Sparkplug B DDATA, with the tag definitions as the device metrics.
+pub struct SparkplugSink {
+    edge_node: Arc<Mutex<EdgeNode>>,
+}
+
+impl SparkplugSink {
+    pub fn new(config: SparkplugSinkConfig) -> Self {
+        let key = format!("{}/{}", config.group_id, config.edge_node_id);
+        let edge_node = EDGE_NODES
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| Arc::new(Mutex::new(EdgeNode::new(config))))
+            .clone();
+        SparkplugSink { edge_node }
+    }
+}
+
+#[async_trait]
+impl Sink for SparkplugSink {
+    fn name(&self) -> &str {
+        "sparkplug_b"
+    }
+
+    async fn connect(&mut self) -> Result<(), SinkError> {
+        self.edge_node.lock().await.connect().await
+    }
+
+    async fn send(&mut self, batch: &Batch) -> Result<(), SinkError> {
+        self.edge_node.lock().await.publish_samples(batch.samples()).await
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        self.edge_node.lock().await.disconnect().await
+    }
+}
+
+struct EdgeNode {
+    config: SparkplugSinkConfig,
+    client: Option<AsyncClient>,
+    event_loop: Option<JoinHandle<()>>,
+    // bdSeq of the current MQTT session, shared with the event loop, which starts the next session.
+    bd_seq: Arc<AtomicU64>,
+    seq: u64,
+    // Set by the event loop on a (re)connect or a rebirth request from a host application.
+    rebirth: Arc<AtomicBool>,
+    // Device id to the names of the metrics in its last DBIRTH.
+    devices: HashMap<String, BTreeSet<String>>,
+    aliases: HashMap<String, u64>,
+    last_values: HashMap<String, Sample>,
+    definitions_version: u64,
+}
+
+impl EdgeNode {
+    fn new(config: SparkplugSinkConfig) -> Self {
+        EdgeNode {
+            config,
+            client: None,
+            event_loop: None,
+            // One before 0, so the first session is 0.
+            bd_seq: Arc::new(AtomicU64::new(255)),
+            seq: 0,
+            rebirth: Arc::new(AtomicBool::new(true)),
+            devices: HashMap::new(),
+            aliases: HashMap::new(),
+            last_values: HashMap::new(),
+            definitions_version: 0,
+        }
+    }
+
+    fn topic(&self, message_type: &str, device: Option<&str>) -> String {
+        match device {
+            Some(device) => format!("{}/{}/{}/{}/{}", NAMESPACE, self.config.group_id, message_type, self.config.edge_node_id, device),
+            None => format!("{}/{}/{}/{}", NAMESPACE, self.config.group_id, message_type, self.config.edge_node_id),
+        }
+    }
+
+    async fn connect(&mut self) -> Result<(), SinkError> {
+        if self.client.is_some() {
+            return Ok(());
+        }
+        let mut options = MqttOptions::new(&self.config.edge_node_id, &self.config.host, self.config.port);
+        options.set_keep_alive(Duration::from_secs(self.config.keep_alive_secs.max(5)));
+        options.set_last_will(Self::will(self.topic("NDEATH", None), next_bd_seq(&self.bd_seq)));
+        if !self.config.username.is_empty() {
+            options.set_credentials(&self.config.username, &self.config.password);
+        }
+
+        let (client, event_loop) = AsyncClient::new(options, 1000);
+        let node = NodeTopics { command: self.topic("NCMD", None), death: self.topic("NDEATH", None) };
+        self.event_loop = Some(Self::drive(event_loop, client.clone(), node, self.bd_seq.clone(), self.rebirth.clone()));
+        self.client = Some(client);
+        self.rebirth.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Publishes NDEATH and ends the MQTT session, so host applications see the node go offline at once
+    /// rather than when the broker notices the connection is gone.
+    async fn disconnect(&mut self) -> Result<(), SinkError> {
+        if let Some(client) = self.client.take() {
+            let death = Self::death(self.bd_seq.load(Ordering::SeqCst));
+            client.publish(self.topic("NDEATH", None), QoS::AtLeastOnce, false, death.encode_to_vec()).await?;
+            client.disconnect().await?;
+        }
+        if let Some(event_loop) = self.event_loop.take() {
+            let _ = event_loop.await;
+        }
+        self.devices.clear();
+        Ok(())
+    }
+
+    /// The broker publishes NDEATH for us when the connection drops; its bdSeq ties it to the NBIRTH.
+    fn will(topic: String, bd_seq: u64) -> LastWill {
+        LastWill::new(topic, Self::death(bd_seq).encode_to_vec(), QoS::AtLeastOnce, false)
+    }
+
+    fn death(bd_seq: u64) -> Payload {
+        Payload {
+            timestamp: Some(now()),
+            metrics: vec![Metric {
+                name: Some(BD_SEQ_METRIC.to_string()),
+                datatype: Some(UINT64),
+                value: Some(metric::Value::LongValue(bd_seq)),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    /// Polls the MQTT event loop, resubscribing to node commands on every connect since the session is clean.
+    /// A dropped connection ends the session, so the next one gets the next bdSeq in its will. The loop
+    /// ends once a disconnect is sent.
+    fn drive(mut event_loop: EventLoop, client: AsyncClient, node: NodeTopics, bd_seq: Arc<AtomicU64>, rebirth: Arc<AtomicBool>) -> JoinHandle<()> {
+        let command_topic = node.command;
+        tokio::spawn(async move {
+            loop {
+                match event_loop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        if let Err(e) = client.try_subscribe(command_topic.clone(), QoS::AtLeastOnce) {
+                            error!("Failed to subscribe to {}: {}", command_topic, e);
+                        }
+                        // A new connection means a new session for host applications, which need fresh births.
+                        rebirth.store(true, Ordering::SeqCst);
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) if publish.topic == command_topic => {
+                        Self::on_command(&command_topic, &publish.payload, &rebirth);
+                    }
+                    Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+                    Ok(_) => {}
+                    Err(e) => {
+                        error!("Sparkplug MQTT connection failed: {}", e);
+                        event_loop.mqtt_options.set_last_will(Self::will(node.death.clone(), next_bd_seq(&bd_seq)));
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                    }
+                }
+            }
+        })
+    }
+
+    /// A rebirth request makes the next publish start with NBIRTH and the DBIRTHs.
+    fn on_command(topic: &str, payload: &[u8], rebirth: &AtomicBool) {
+        if Self::is_rebirth_request(payload) {
+            info!("Rebirth requested on {}", topic);
+            rebirth.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn is_rebirth_request(payload: &[u8]) -> bool {
+        match Payload::decode(payload) {
+            Ok(payload) => payload.metrics.iter().any(|metric| {
+                metric.name.as_deref() == Some(REBIRTH_METRIC)
+                    && matches!(metric.value, Some(metric::Value::BooleanValue(true)))
+            }),
+            Err(e) => {
+                error!("Ignoring an undecodable node command: {}", e);
+                false
+            }
+        }
+    }
+
+    async fn publish_samples(&mut self, samples: Vec<Sample>) -> Result<(), SinkError> {
+        self.connect().await?;
+        if self.rebirth.swap(false, Ordering::SeqCst) {
+            self.birth().await?;
+        } else if TAG_DEFINITIONS.version() != self.definitions_version {
+            self.update_devices().await?;
+        }
+
+        let tag_devices: HashMap<&String, &String> = self
+            .devices
+            .iter()
+            .flat_map(|(device, metrics)| metrics.iter().map(move |metric| (metric, device)))
+            .collect();
+        let mut by_device: HashMap<String, Vec<Sample>> = HashMap::new();
+        for sample in samples {
+            let device = match tag_devices.get(&sample.tag) {
+                Some(device) => (*device).clone(),
+                None => self.device_of(&TagDefinition::from_name(sample.tag.clone())),
+            };
+            by_device.entry(device).or_default().push(sample);
+        }
+
+        for (device, samples) in by_device {
+            // A value for a metric the device was not born with needs a new DBIRTH first.
+            let born = self.devices.get(&device).cloned().unwrap_or_default();
+            let unborn: Vec<String> = samples.iter().filter(|sample| !born.contains(&sample.tag)).map(|sample| sample.tag.clone()).collect();
+            for sample in &samples {
+                self.last_values.insert(sample.tag.clone(), sample.clone());
+            }
+            if !unborn.is_empty() {
+                let mut metrics = born;
+                metrics.extend(unborn);
+                self.rebirth_device(&device, metrics).await?;
+                continue;
+            }
+
+            let metrics = samples
+                .iter()
+                .map(|sample| Metric {
+                    alias: Some(self.alias(&sample.tag)),
+                    timestamp: Some(sample.t.max(0) as u64),
+                    datatype: Some(DOUBLE),
+                    value: Some(metric::Value::DoubleValue(sample.v)),
+                    ..Default::default()
+                })
+                .collect();
+            let topic = self.topic("DDATA", Some(&device));
+            self.publish(topic, metrics).await?;
+        }
+        Ok(())
+    }
+
+    /// NBIRTH followed by a DBIRTH for every device, restarting the sequence at 0.
+    async fn birth(&mut self) -> Result<(), SinkError> {
+        let payload = self.node_birth();
+        let client = self.client.as_ref().ok_or("MQTT client not initialized")?;
+        client.publish(self.topic("NBIRTH", None), QoS::AtMostOnce, false, payload.encode_to_vec()).await?;
+
+        self.definitions_version = TAG_DEFINITIONS.version();
+        self.devices.clear();
+        for (device, metrics) in self.devices_from_definitions() {
+            self.publish_device_birth(&device, metrics).await?;
+        }
+        info!("Published Sparkplug births for {} devices of {}", self.devices.len(), self.config.edge_node_id);
+        Ok(())
+    }
+
+    /// Applies tag definition changes: devices left without tags die, devices whose metrics changed are reborn.
+    async fn update_devices(&mut self) -> Result<(), SinkError> {
+        self.definitions_version = TAG_DEFINITIONS.version();
+        let devices = self.devices_from_definitions();
+        let dead: Vec<String> = self.devices.keys().filter(|device| !devices.contains_key(*device)).cloned().collect();
+        for device in dead {
+            self.publish_device_death(&device).await?;
+        }
+        for (device, metrics) in devices {
+            if self.devices.get(&device) != Some(&metrics) {
+                self.rebirth_device(&device, metrics).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn rebirth_device(&mut self, device: &str, metrics: BTreeSet<String>) -> Result<(), SinkError> {
+        if self.devices.contains_key(device) {
+            self.publish_device_death(device).await?;
+        }
+        self.publish_device_birth(device, metrics).await
+    }
+
+    async fn publish_device_birth(&mut self, device: &str, names: BTreeSet<String>) -> Result<(), SinkError> {
+        let metrics = self.device_birth_metrics(&names, |name| SCALING.units(name));
+        let topic = self.topic("DBIRTH", Some(device));
+        self.publish(topic, metrics).await?;
+        self.devices.insert(device.to_string(), names);
+        Ok(())
+    }
+
+    /// Every metric of a DBIRTH has its name, alias and units, and the last value sent or a null one.
+    fn device_birth_metrics(&mut self, names: &BTreeSet<String>, units: impl Fn(&str) -> Option<String>) -> Vec<Metric> {
+        let mut metrics = Vec::new();
+        for name in names {
+            let alias = self.alias(name);
+            let last_value = self.last_values.get(name);
+            metrics.push(Metric {
+                name: Some(name.clone()),
+                alias: Some(alias),
+                timestamp: Some(last_value.map(|sample| sample.t.max(0) as u64).unwrap_or_else(now)),
+                datatype: Some(DOUBLE),
+                is_null: last_value.is_none().then_some(true),
+                properties: units(name).map(Self::properties),
+                value: last_value.map(|sample| metric::Value::DoubleValue(sample.v)),
+                ..Default::default()
+            });
+        }
+        metrics
+    }
+
+    async fn publish_device_death(&mut self, device: &str) -> Result<(), SinkError> {
+        let topic = self.topic("DDEATH", Some(device));
+        self.publish(topic, Vec::new()).await?;
+        self.devices.remove(device);
+        Ok(())
+    }
+
+    fn node_birth(&mut self) -> Payload {
+        self.seq = 0;
+        Payload {
+            timestamp: Some(now()),
+            metrics: vec![
+                Metric {
+                    name: Some(BD_SEQ_METRIC.to_string()),
+                    datatype: Some(UINT64),
+                    value: Some(metric::Value::LongValue(self.bd_seq.load(Ordering::SeqCst))),
+                    ..Default::default()
+                },
+                Metric {
+                    name: Some(REBIRTH_METRIC.to_string()),
+                    datatype: Some(BOOLEAN),
+                    value: Some(metric::Value::BooleanValue(false)),
+                    ..Default::default()
+                },
+            ],
+            seq: Some(0),
+            ..Default::default()
+        }
+    }
+
+    async fn publish(&mut self, topic: String, metrics: Vec<Metric>) -> Result<(), SinkError> {
+        let payload = self.next_payload(metrics);
+        let client = self.client.as_ref().ok_or("MQTT client not initialized")?;
+        client.publish(topic, QoS::AtMostOnce, false, payload.encode_to_vec()).await?;
+        Ok(())
+    }
+
+    /// Every message after NBIRTH carries the next number of the node's sequence, which wraps after 255.
+    fn next_payload(&mut self, metrics: Vec<Metric>) -> Payload {
+        self.seq = (self.seq + 1) % 256;
+        Payload {
+            timestamp: Some(now()),
+            metrics,
+            seq: Some(self.seq),
+            ..Default::default()
+        }
+    }
+
+    fn devices_from_definitions(&self) -> HashMap<String, BTreeSet<String>> {
+        let mut devices: HashMap<String, BTreeSet<String>> = HashMap::new();
+        for definition in TAG_DEFINITIONS.snapshot() {
+            devices.entry(self.device_of(&definition)).or_default().insert(definition.name);
+        }
+        devices
+    }
+
+    fn device_of(&self, definition: &TagDefinition) -> String {
+        let device = definition
+            .asset_name
+            .as_deref()
+            .filter(|asset| !asset.trim().is_empty())
+            .unwrap_or(&self.config.default_device);
+        // '/', '+' and '#' are MQTT topic separators and wildcards, which Sparkplug ids may not contain.
+        device.replace(['/', '+', '#'], "_")
+    }
+
+    /// Aliases stay fixed for the life of the process, so hosts can keep their mappings across rebirths.
+    fn alias(&mut self, name: &str) -> u64 {
+        let next = self.aliases.len() as u64 + 1;
+        *self.aliases.entry(name.to_string()).or_insert(next)
+    }
+
+    fn properties(units: String) -> PropertySet {
+        PropertySet {
+            keys: vec!["engUnit".to_string()],
+            values: vec![PropertyValue {
+                r#type: Some(STRING),
+                is_null: None,
+                value: Some(property_value::Value::StringValue(units)),
+            }],
+        }
+    }
+}
+
+impl Drop for EdgeNode {
+    fn drop(&mut self) {
+        if let Some(event_loop) = self.event_loop.take() {
+            event_loop.abort();
+        }
+    }
+}
+
+/// Topics the event loop of an edge node needs.
+struct NodeTopics {
+    command: String,
+    death: String,
+}
+
+/// Moves bdSeq on for a new MQTT session; like seq, it wraps after 255.
+fn next_bd_seq(bd_seq: &AtomicU64) -> u64 {
+    let next = (bd_seq.load(Ordering::SeqCst) + 1) % 256;
+    bd_seq.store(next, Ordering::SeqCst);
+    next
+}
+
+fn now() -> u64 {
+    Utc::now().timestamp_millis().max(0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use rumqttc::Request;
+    use super::*;
+
+    fn edge_node() -> EdgeNode {
+        EdgeNode::new(serde_json::from_value(serde_json::json!({"host": "localhost"})).unwrap())
+    }
+
+    fn decode(payload: Payload) -> Payload {
+        Payload::decode(payload.encode_to_vec().as_slice()).unwrap()
+    }
+
+    fn bd_seq_of(payload: &Payload) -> Option<u64> {
+        payload.metrics.iter().find(|metric| metric.name.as_deref() == Some(BD_SEQ_METRIC)).and_then(|metric| match metric.value {
+            Some(metric::Value::LongValue(bd_seq)) => Some(bd_seq),
+            _ => None,
+        })
+    }
+
+    /// Gives the node a client whose publishes queue up in the returned event loop, which is never polled.
+    fn attach_client(node: &mut EdgeNode) -> EventLoop {
+        let (client, event_loop) = AsyncClient::new(MqttOptions::new("test", "localhost", 1883), 100);
+        node.client = Some(client);
+        event_loop
+    }
+
+    fn published(event_loop: &mut EventLoop) -> Vec<(String, Payload)> {
+        event_loop.clean();
+        event_loop
+            .pending
+            .drain(..)
+            .filter_map(|request| match request {
+                Request::Publish(publish) => Some((publish.topic, Payload::decode(publish.payload).unwrap())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn rebirth_command(requested: bool) -> Vec<u8> {
+        Payload {
+            timestamp: Some(now()),
+            metrics: vec![Metric {
+                name: Some(REBIRTH_METRIC.to_string()),
+                datatype: Some(BOOLEAN),
+                value: Some(metric::Value::BooleanValue(requested)),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn node_birth_carries_the_session_bd_seq_and_restarts_seq() {
+        let mut node = edge_node();
+        assert_eq!(next_bd_seq(&node.bd_seq), 0);
+        node.seq = 41;
+
+        let birth = decode(node.node_birth());
+        assert_eq!(birth.seq, Some(0));
+        assert_eq!(bd_seq_of(&birth), Some(0));
+        let rebirth = birth.metrics.iter().find(|metric| metric.name.as_deref() == Some(REBIRTH_METRIC)).unwrap();
+        assert_eq!(rebirth.value, Some(metric::Value::BooleanValue(false)));
+        assert_eq!(node.seq, 0);
+
+        assert_eq!(next_bd_seq(&node.bd_seq), 1);
+        assert_eq!(bd_seq_of(&decode(node.node_birth())), Some(1));
+    }
+
+    #[test]
+    fn data_seq_wraps_after_255() {
+        let mut node = edge_node();
+        node.seq = 254;
+        let metric = Metric {
+            alias: Some(1),
+            datatype: Some(DOUBLE),
+            value: Some(metric::Value::DoubleValue(2.5)),
+            ..Default::default()
+        };
+
+        let data = decode(node.next_payload(vec![metric.clone()]));
+        assert_eq!(data.seq, Some(255));
+        assert_eq!(data.metrics, vec![metric]);
+        assert_eq!(decode(node.next_payload(Vec::new())).seq, Some(0));
+        assert_eq!(decode(node.next_payload(Vec::new())).seq, Some(1));
+    }
+
+    #[test]
+    fn node_death_carries_only_bd_seq() {
+        let death = decode(EdgeNode::death(7));
+        assert_eq!(death.seq, None);
+        assert_eq!(death.metrics.len(), 1);
+        assert_eq!(death.metrics[0].datatype, Some(UINT64));
+        assert_eq!(bd_seq_of(&death), Some(7));
+    }
+
+    #[test]
+    fn bd_seq_wraps_after_255() {
+        let bd_seq = AtomicU64::new(254);
+        assert_eq!(next_bd_seq(&bd_seq), 255);
+        assert_eq!(next_bd_seq(&bd_seq), 0);
+        assert_eq!(bd_seq.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn device_birth_names_every_metric_with_its_units_and_last_value() {
+        let mut node = edge_node();
+        node.alias("Pump.Speed");
+        node.last_values.insert("Pump.Speed".to_string(), Sample { tag: "Pump.Speed".to_string(), t: 1_000, v: 42.5, units: None });
+
+        let names = BTreeSet::from(["Pump.Flow".to_string(), "Pump.Speed".to_string()]);
+        let metrics = decode(Payload { metrics: node.device_birth_metrics(&names, |name| (name == "Pump.Flow").then(|| "m3/h".to_string())), ..Default::default() }).metrics;
+
+        let flow = &metrics[0];
+        assert_eq!(flow.name.as_deref(), Some("Pump.Flow"));
+        assert_eq!(flow.alias, Some(2));
+        assert_eq!(flow.datatype, Some(DOUBLE));
+        assert_eq!(flow.is_null, Some(true));
+        assert_eq!(flow.value, None);
+        let units = flow.properties.as_ref().unwrap();
+        assert_eq!(units.keys, vec!["engUnit".to_string()]);
+        assert_eq!(units.values[0].value, Some(property_value::Value::StringValue("m3/h".to_string())));
+
+        let speed = &metrics[1];
+        assert_eq!(speed.name.as_deref(), Some("Pump.Speed"));
+        assert_eq!(speed.alias, Some(1));
+        assert_eq!(speed.timestamp, Some(1_000));
+        assert_eq!(speed.is_null, None);
+        assert_eq!(speed.properties, None);
+        assert_eq!(speed.value, Some(metric::Value::DoubleValue(42.5)));
+    }
+
+    #[test]
+    fn only_a_true_rebirth_metric_requests_a_rebirth() {
+        let rebirth = AtomicBool::new(false);
+        EdgeNode::on_command("NCMD", &rebirth_command(false), &rebirth);
+        assert!(!rebirth.load(Ordering::SeqCst));
+        EdgeNode::on_command("NCMD", b"not a payload", &rebirth);
+        assert!(!rebirth.load(Ordering::SeqCst));
+        EdgeNode::on_command("NCMD", &rebirth_command(true), &rebirth);
+        assert!(rebirth.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn rebirth_command_publishes_node_birth_before_the_next_data() {
+        let mut node = edge_node();
+        let mut event_loop = attach_client(&mut node);
+        assert_eq!(next_bd_seq(&node.bd_seq), 0);
+        node.publish_samples(Vec::new()).await.unwrap();
+        let first = published(&mut event_loop);
+        assert_eq!(first[0].0, node.topic("NBIRTH", None));
+
+        node.seq = 17;
+        node.publish_samples(Vec::new()).await.unwrap();
+        assert!(published(&mut event_loop).iter().all(|(topic, _)| *topic != node.topic("NBIRTH", None)));
+
+        EdgeNode::on_command("NCMD", &rebirth_command(true), &node.rebirth);
+        node.publish_samples(Vec::new()).await.unwrap();
+        let reborn = published(&mut event_loop);
+        let (topic, birth) = &reborn[0];
+        assert_eq!(topic, &node.topic("NBIRTH", None));
+        assert_eq!(birth.seq, Some(0));
+        assert_eq!(bd_seq_of(birth), Some(0));
+        assert!(!node.rebirth.load(Ordering::SeqCst));
+    }
+}
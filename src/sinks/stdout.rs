@@ -0,0 +1,26 @@
+use std::io::Write;
+use async_trait::async_trait;
+use crate::sinks::{Batch, Sink, SinkError};
+
+/// Writes each sample as a JSON line to standard output, for test rigs and piping into other tools.
+pub struct StdoutSink;
+
+#[async_trait]
+impl Sink for StdoutSink {
+    fn name(&self) -> &str {
+        "stdout"
+    }
+
+    async fn connect(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    async fn send(&mut self, batch: &Batch) -> Result<(), SinkError> {
+        let mut stdout = std::io::stdout().lock();
+        for sample in batch.samples() {
+            serde_json::to_writer(&mut stdout, &sample)?;
+            stdout.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use lazy_static::lazy_static;
+use log::error;
+use crate::config::configuration::CONFIG;
+use crate::system_initializer::sample_filter::Sample;
+use crate::system_initializer::expression::{Expression, Inputs};
+use crate::telemetry::metrics::METRICS;
+
+/// The last two values of a tag a calculated tag reads, by source timestamp.
+struct Input {
+    last: (i64, f64),
+    previous: Option<(i64, f64)>,
+}
+
+#[derive(Default)]
+struct State {
+    // Parsed from `CONFIG.current().get_calculated_tags()` on first use after a reset.
+    calculated: Option<Vec<(String, Expression)>>,
+    // Indexes into `calculated` of the tags that read each tag.
+    dependents: HashMap<String, Vec<usize>>,
+    inputs: HashMap<String, Input>,
+}
+
+impl Inputs for HashMap<String, Input> {
+    fn value(&self, tag: &str) -> Option<f64> {
+        self.get(tag).map(|input| input.last.1)
+    }
+
+    fn rate(&self, tag: &str) -> Option<f64> {
+        let input = self.get(tag)?;
+        let (previous_t, previous_v) = input.previous?;
+        let (t, v) = input.last;
+        Some((v - previous_v) / ((t - previous_t) as f64 / 1000.0))
+    }
+}
+
+/// Evaluates the calculated tags whenever a tag they read receives a value. Their values carry the
+/// timestamps of the value that triggered them and go on like those of any collected tag.
+pub struct CalculatedTags {
+    state: Mutex<State>,
+}
+
+impl CalculatedTags {
+    fn new() -> Self {
+        CalculatedTags { state: Mutex::new(State::default()) }
+    }
+
+    /// Takes a value of a collected tag and returns the calculated tags it changed, with their new
+    /// values. A value that is not good leaves the tags reading it without a value until a good one arrives.
+    pub fn update(&self, tag: &str, value: &Sample, good: bool) -> Vec<(String, Sample)> {
+        if CONFIG.current().get_calculated_tags().is_empty() {
+            return Vec::new();
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.calculated.is_none() {
+            Self::build(&mut state);
+        }
+        let mut updated = Vec::new();
+        // Calculated tags may read other calculated tags; validation rules out cycles.
+        let mut changed = vec![(tag.to_string(), value.clone(), good)];
+        while let Some((tag, value, good)) = changed.pop() {
+            let Some(dependents) = state.dependents.get(&tag).cloned() else {
+                continue;
+            };
+            if !good {
+                state.inputs.remove(&tag);
+                continue;
+            }
+            let input = match state.inputs.remove(&tag) {
+                Some(input) if value.t > input.last.0 => Input { last: (value.t, value.v), previous: Some(input.last) },
+                Some(input) if value.t == input.last.0 => Input { last: (value.t, value.v), previous: input.previous },
+                _ => Input { last: (value.t, value.v), previous: None },
+            };
+            state.inputs.insert(tag.clone(), input);
+
+            let calculated = state.calculated.as_deref().unwrap_or_default();
+            for index in dependents {
+                let (name, expression) = &calculated[index];
+                let Some(result) = expression.evaluate(&state.inputs) else {
+                    continue;
+                };
+                if !result.is_finite() {
+                    METRICS.dropped_samples.inc(&["not_finite"]);
+                    changed.push((name.clone(), value.clone(), false));
+                    continue;
+                }
+                let result = Sample { v: result, ..value.clone() };
+                updated.push((name.clone(), result.clone()));
+                changed.push((name.clone(), result, true));
+            }
+        }
+        updated
+    }
+
+    /// Parses the calculated tags again and forgets every input; called when they change.
+    pub fn reset(&self) {
+        *self.state.lock().unwrap() = State::default();
+    }
+
+    pub fn forget(&self, tags: &[String]) {
+        let mut state = self.state.lock().unwrap();
+        for tag in tags {
+            state.inputs.remove(tag);
+        }
+    }
+
+    fn build(state: &mut State) {
+        let mut calculated = Vec::new();
+        for calculated_tag in CONFIG.current().get_calculated_tags() {
+            // Validation rejects these; a reload that got past it should not take the client down.
+            match Expression::parse(&calculated_tag.expression) {
+                Ok(expression) => calculated.push((calculated_tag.name.clone(), expression)),
+                Err(e) => error!("Calculated tag {} is left out: {}", calculated_tag.name, e),
+            }
+        }
+        for (index, (_, expression)) in calculated.iter().enumerate() {
+            for tag in expression.tags() {
+                state.dependents.entry(tag).or_default().push(index);
+            }
+        }
+        state.calculated = Some(calculated);
+    }
+}
+
+lazy_static! {
+    pub static ref CALCULATED_TAGS: CalculatedTags = CalculatedTags::new();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_needs_a_previous_value() {
+        let mut inputs = HashMap::new();
+        inputs.insert("Counter".to_string(), Input { last: (1_000, 10.0), previous: None });
+        assert_eq!(inputs.rate("Counter"), None);
+        assert_eq!(inputs.value("Counter"), Some(10.0));
+
+        inputs.insert("Counter".to_string(), Input { last: (3_000, 20.0), previous: Some((1_000, 10.0)) });
+        assert_eq!(inputs.rate("Counter"), Some(5.0));
+    }
+}
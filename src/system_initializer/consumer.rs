@@ -1,78 +1,165 @@
 use std::error::Error;
-use log::error;
-use prost::Message;
-use crate::message::Universal;
-use crate::clients::ws_client::WebSocketClient;
-use crate::system_initializer::data_queue::QUEUE;
+use std::time::Instant;
+use chrono::Utc;
+use log::{info, error};
+use tracing::{info_span, Instrument};
+use crate::message::Timing;
+use crate::sinks::{self, Batch, Sink};
+use crate::system_initializer::data_queue::{QueuedRecord, QUEUE};
+use crate::system_initializer::shutdown::SHUTDOWN;
+use crate::telemetry::health::HEALTH;
+use crate::telemetry::metrics::{latency_seconds, METRICS};
 use crate::config::configuration::CONFIG;
 
+/// Batch size and flush interval are read from `CONFIG` for every batch, so reloads apply to running consumers.
+///
+/// A consumer stops once the queue is closed and empty, or when the shutdown deadline passes.
+///
+/// It also measures how old values are: from their source timestamp to the producer receiving them,
+/// through the queue, and until each sink accepted their batch.
 pub struct Consumer {
-    batch_size: usize,
-    ws_obj : Option<WebSocketClient>,
+    consumer_id: usize,
+    sinks: Vec<Box<dyn Sink>>,
     historical_batch: Vec<Vec<u8>>,
+    // One per record of historical_batch.
+    timings: Vec<Timing>,
+    // Numbers the batches this consumer sent, for the batch span.
+    batch_id: u64,
 }
 
 impl Consumer {
-    pub fn new(
-        batch_size: usize,
-    ) -> Self {
+    pub fn new(consumer_id: usize) -> Self {
         Consumer {
-            batch_size,
-            ws_obj:None,
+            consumer_id,
+            sinks: Vec::new(),
             historical_batch: Vec::new(),
+            timings: Vec::new(),
+            batch_id: 0,
         }
     }
 
     pub async fn consume(&mut self) -> Result<(), Box<dyn Error>> {
-        self.connect_to_c2().await?;
-        loop {
-            let historical_value = QUEUE.dequeue();
-            self.historical_batch.push(historical_value);
+        self.connect_sinks().await;
+        let mut last_flush = Instant::now();
+        while !SHUTDOWN.is_past_deadline() {
+            let flush_interval = CONFIG.current().get_flush_interval();
+            // The queue waits on a condition variable; the worker thread is handed over meanwhile so
+            // the sinks' own tasks keep running.
+            let historical_value = tokio::task::block_in_place(|| match flush_interval {
+                Some(flush_interval) => QUEUE.dequeue_timeout(flush_interval.saturating_sub(last_flush.elapsed())),
+                None => QUEUE.dequeue(),
+            });
+            let drained = historical_value.is_none() && QUEUE.is_closed();
+            if let Some(queued) = historical_value {
+                let timing = Self::dequeued(&queued);
+                self.historical_batch.push(queued.record);
+                self.timings.push(timing);
+            }
+
+            let flush_due = flush_interval.is_some_and(|flush_interval| last_flush.elapsed() >= flush_interval);
+            if self.historical_batch.len() >= CONFIG.current().get_batch_size() || flush_due || drained {
+                last_flush = Instant::now();
+                if !self.historical_batch.is_empty() {
+                    self.batch_id += 1;
+                    let span = info_span!("batch", batch_id = self.batch_id, records = self.historical_batch.len());
+                    self.send_batch().instrument(span).await;
+                }
+            }
+            if drained {
+                break;
+            }
+        }
+        self.stop().await;
+        Ok(())
+    }
 
-            if self.historical_batch.len() >= self.batch_size {
-                let universal_buffer = self.process_universal_message(self.historical_batch.clone()).await?;
-                if let Some(ws_obj) = &mut self.ws_obj { 
-                    match ws_obj.push_to_c2(universal_buffer).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("Failed to send data to C2: {}", e);
+    /// During shutdown a batch the C2 sink did not take is kept, to be saved with what is left in the queue.
+    ///
+    /// The saved file is only replayed to C2, so a batch C2 already took is not kept when another sink
+    /// fails; replaying it would send duplicates.
+    async fn send_batch(&mut self) {
+        let timings = std::mem::take(&mut self.timings);
+        let mut batch = Batch::new(std::mem::take(&mut self.historical_batch));
+        if CONFIG.current().get_telemetry().universal_latency {
+            batch = batch.with_timings(timings.clone());
+        }
+        METRICS.batch_records.observe(&[], batch.len() as f64);
+        let mut delivered = true;
+        let mut c2_missed = false;
+        for sink in &mut self.sinks {
+            let is_c2 = sink.name() == sinks::c2::NAME;
+            let started = Instant::now();
+            tokio::select! {
+                result = sink.send(&batch) => match result {
+                    Ok(()) => {
+                        METRICS.sink_send_seconds.observe(&[sink.name()], started.elapsed().as_secs_f64());
+                        Self::observe_ack(sink.name(), &timings);
+                        HEALTH.record_sink(self.consumer_id, sink.name(), Ok(()));
+                    }
+                    Err(e) => {
+                        error!("Failed to send data to {}: {}", sink.name(), e);
+                        HEALTH.record_sink(self.consumer_id, sink.name(), Err(e.to_string()));
+                        METRICS.sink_errors.inc(&[sink.name()]);
+                        if !is_c2 || !SHUTDOWN.is_started() {
+                            METRICS.sink_dropped_records.inc_by(&[sink.name()], batch.len() as u64);
                         }
+                        delivered = false;
+                        c2_missed |= is_c2;
                     }
-                } else {
-                    error!("WebSocket client not initialized");
+                },
+                _ = SHUTDOWN.deadline_reached() => {
+                    error!("Shutdown deadline passed while sending to {}", sink.name());
+                    delivered = false;
+                    c2_missed |= is_c2;
                 }
-                self.historical_batch.clear();
             }
         }
+        if delivered {
+            HEALTH.record_push(self.consumer_id);
+        }
+        if c2_missed && SHUTDOWN.is_started() {
+            SHUTDOWN.keep_unsent(batch.into_records());
+        }
     }
 
-    async fn process_universal_message(
-        &self,
-        historical_values: Vec<Vec<u8>>,
-    ) -> Result<Vec<u8>, Box<dyn Error>> {
-        let universal_data = Universal {
-            r#type: vec![7201; historical_values.len()],
-            messages: historical_values,
-        };
+    /// Completes the times the producer took for a record and observes how long it waited in the queue.
+    fn dequeued(queued: &QueuedRecord) -> Timing {
+        let dequeued_t = Utc::now().timestamp_millis();
+        if queued.timing.received_t != 0 {
+            METRICS.receive_to_dequeue_seconds.observe(&[], latency_seconds(queued.timing.received_t, dequeued_t));
+        }
+        Timing { dequeued_t, ..queued.timing.clone() }
+    }
 
-        let mut universal_buffer = Vec::new();
-        universal_data.encode(&mut universal_buffer)
-            .expect("Failed to encode Universal data");
+    fn observe_ack(sink: &str, timings: &[Timing]) {
+        let acked_t = Utc::now().timestamp_millis();
+        for timing in timings {
+            METRICS.dequeue_to_ack_seconds.observe(&[sink], latency_seconds(timing.dequeued_t, acked_t));
+        }
+    }
 
-        Ok(universal_buffer)
+    async fn stop(&mut self) {
+        SHUTDOWN.keep_unsent(std::mem::take(&mut self.historical_batch));
+        for sink in &mut self.sinks {
+            tokio::select! {
+                result = sink.flush() => if let Err(e) = result {
+                    error!("Failed to flush {}: {}", sink.name(), e);
+                },
+                _ = SHUTDOWN.deadline_reached() => error!("Shutdown deadline passed while flushing {}", sink.name()),
+            }
+        }
+        info!("Consumer {} stopped", self.consumer_id + 1);
     }
 
-    async fn connect_to_c2(&mut self)->Result<(), Box<dyn std::error::Error>>{        
-        let backend_url = CONFIG.get_base();
-        let header_key = CONFIG.get_header_key();
-        let header_value = CONFIG.get_header_value();
-        
-        let mut  ws_obj = WebSocketClient::new(backend_url.to_string(), header_key, header_value);
-        if let Err(e) = ws_obj.connect_to_c2_server().await {
-            error!("Failed to connect to C2 server: {}", e);
-            return Err(e);
+    /// A sink that cannot connect yet is kept; it connects again on its next send.
+    async fn connect_sinks(&mut self) {
+        self.sinks = sinks::build(&CONFIG.current().get_sinks(), self.consumer_id);
+        for sink in &mut self.sinks {
+            let result = sink.connect().await.map_err(|e| e.to_string());
+            if let Err(e) = &result {
+                error!("Failed to connect to {}: {}", sink.name(), e);
+            }
+            HEALTH.record_sink(self.consumer_id, sink.name(), result);
         }
-        self.ws_obj = Some(ws_obj);
-        Ok(())
     }
 }
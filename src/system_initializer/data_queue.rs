@@ -2,17 +2,29 @@ use lazy_static::lazy_static;
 use std::collections::VecDeque;
 use std::error::Error;
 use std::sync::{ Mutex, Condvar};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use log::info;
+use crate::message::Timing;
+
+/// An encoded `Historical` message with the times its value was taken on the way, which travel beside
+/// it so the consumer need not decode the message.
+pub struct QueuedRecord {
+    pub record: Vec<u8>,
+    pub timing: Timing,
+}
 
 pub struct Queue {
-    buffer: Mutex<VecDeque<Vec<u8>>>,
+    buffer: Mutex<VecDeque<QueuedRecord>>,
     empty_slots: Condvar,
     full_slots: Condvar,
-    pub consumed_count: AtomicUsize,
-    pub produced_count: AtomicUsize,
+    // Totals since start; readers take differences for rates.
+    consumed_count: AtomicUsize,
+    produced_count: AtomicUsize,
     max_size: usize,
+    // Set on shutdown: consumers empty the queue and stop instead of waiting for more.
+    closed: AtomicBool,
 }
 
 impl Queue {
@@ -24,12 +36,14 @@ impl Queue {
             consumed_count: AtomicUsize::new(0),
             produced_count: AtomicUsize::new(0),
             max_size: data_queue_size,
+            closed: AtomicBool::new(false),
         }
     }
 
-    pub fn enqueue(&self, item: Vec<u8>) -> Result<(), Box<dyn Error>> {
-        let mut buffer: std::sync::MutexGuard<'_, VecDeque<Vec<u8>>> = self.buffer.lock().unwrap();
-        while buffer.len() == self.max_size {
+    pub fn enqueue(&self, item: QueuedRecord) -> Result<(), Box<dyn Error>> {
+        let mut buffer: std::sync::MutexGuard<'_, VecDeque<QueuedRecord>> = self.buffer.lock().unwrap();
+        // Nothing drains a closed queue for long, so late records are taken rather than waited on.
+        while buffer.len() == self.max_size && !self.is_closed() {
             info!("Buffer is full. Producer is waiting...");
             buffer = self.full_slots.wait(buffer).unwrap();
         }
@@ -40,18 +54,71 @@ impl Queue {
         Ok(())
     }
     
-    pub fn dequeue(&self) -> Vec<u8> {
+    /// `None` once the queue is closed and empty.
+    pub fn dequeue(&self) -> Option<QueuedRecord> {
         let mut buffer = self.buffer.lock().unwrap();
         while buffer.is_empty() {
+            if self.is_closed() {
+                return None;
+            }
             info!("Buffer is empty. Consumer is waiting...");
             buffer = self.empty_slots.wait(buffer).unwrap();
         }
-        let item = buffer.pop_front().unwrap_or_default();
+        let item = buffer.pop_front();
         self.full_slots.notify_one();
         self.consumed_count.fetch_add(1, Ordering::SeqCst);
         item
     }
 
+    /// Like `dequeue`, but gives up after `timeout` so the caller can flush what it holds.
+    pub fn dequeue_timeout(&self, timeout: Duration) -> Option<QueuedRecord> {
+        let deadline = Instant::now() + timeout;
+        let mut buffer = self.buffer.lock().unwrap();
+        while buffer.is_empty() {
+            if self.is_closed() {
+                return None;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return None;
+            }
+            buffer = self.empty_slots.wait_timeout(buffer, remaining).unwrap().0;
+        }
+        let item = buffer.pop_front();
+        self.full_slots.notify_one();
+        self.consumed_count.fetch_add(1, Ordering::SeqCst);
+        item
+    }
+
+    /// Wakes every waiting consumer; from now on an empty queue means there is nothing left to send.
+    pub fn close(&self) {
+        // Taken so no consumer is between its check and its wait when notified.
+        let _buffer = self.buffer.lock().unwrap();
+        self.closed.store(true, Ordering::SeqCst);
+        self.empty_slots.notify_all();
+        self.full_slots.notify_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Takes everything still queued, for records that must be kept when consumers run out of time.
+    pub fn drain(&self) -> Vec<Vec<u8>> {
+        let mut buffer = self.buffer.lock().unwrap();
+        let items: Vec<Vec<u8>> = buffer.drain(..).map(|item| item.record).collect();
+        self.full_slots.notify_all();
+        items
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.lock().unwrap().len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.max_size
+    }
+
     pub fn get_consumed_count(&self) -> usize {
         self.consumed_count.load(Ordering::SeqCst)
     }
@@ -0,0 +1,272 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use log::info;
+use crate::config::configuration::{AggregateFunction, AggregationGroup, CONFIG};
+use crate::message::{Aggregate, AggregateValue};
+use crate::sinks::c2::C2Outbox;
+use crate::sinks::AGGREGATE_TYPE;
+use crate::system_initializer::sample_filter::{matches_group, Sample};
+use crate::telemetry::metrics::METRICS;
+
+/// How often windows past their end and lateness are closed.
+const CLOSE_INTERVAL: Duration = Duration::from_secs(1);
+
+struct TagWindows {
+    // Index into `CONFIG.current().get_aggregations()`; none when no group matches the tag.
+    group: Option<usize>,
+    // Start of the earliest window not sent yet; none while no value waits for a window.
+    next_start: Option<i64>,
+    // Windows before this were sent, so values for them are late.
+    closed_until: i64,
+    // Values from `next_start` on, by source timestamp.
+    values: VecDeque<(i64, f64)>,
+    // The last value before `next_start`, which still holds when the window starts.
+    carried: Option<(i64, f64)>,
+}
+
+/// Computes statistics of each tag's values over tumbling or sliding windows and sends them to C2 as
+/// `Aggregate` messages once a window is over. Windows without values are not sent.
+pub struct EdgeAggregator {
+    tags: Mutex<HashMap<String, TagWindows>>,
+}
+
+impl EdgeAggregator {
+    fn new() -> Self {
+        EdgeAggregator { tags: Mutex::new(HashMap::new()) }
+    }
+
+    /// Takes the value into the windows of the tag's group, if a group matches. Returns whether the
+    /// raw value goes on to the filters and the queue as well.
+    pub fn record(&self, tag: &str, value: &Sample, good: bool) -> bool {
+        let config = CONFIG.current();
+        let groups = config.get_aggregations();
+        if groups.is_empty() {
+            return true;
+        }
+        let mut tags = self.tags.lock().unwrap();
+        let state = tags.entry(tag.to_string()).or_insert_with(|| TagWindows {
+            group: groups.iter().position(|group| matches_group(&group.tags, &group.assets, tag)),
+            next_start: None,
+            closed_until: i64::MIN,
+            values: VecDeque::new(),
+            carried: None,
+        });
+        let Some(group) = state.group.and_then(|group| groups.get(group)) else {
+            return true;
+        };
+
+        if good {
+            let next_start = *state
+                .next_start
+                .get_or_insert_with(|| first_window_start(group, value.t).max(state.closed_until));
+            if value.t < next_start {
+                METRICS.aggregate_late_samples.inc(&[&group.name]);
+            } else {
+                let position = state.values.partition_point(|(t, _)| *t <= value.t);
+                state.values.insert(position, (value.t, value.v));
+            }
+        }
+        if !group.raw {
+            METRICS.suppressed_samples.inc(&[&group.name, "aggregated"]);
+        }
+        group.raw
+    }
+
+    /// Drops open windows and matches every tag to the groups again; called when the groups change.
+    pub fn reset(&self) {
+        let mut tags = self.tags.lock().unwrap();
+        let open = tags.values().filter(|state| !state.values.is_empty()).count();
+        if open > 0 {
+            info!("Aggregations changed, dropping the open windows of {} tags", open);
+        }
+        tags.clear();
+    }
+
+    pub fn forget(&self, tags: &[String]) {
+        let mut tag_windows = self.tags.lock().unwrap();
+        for tag in tags {
+            tag_windows.remove(tag);
+        }
+    }
+
+    /// Closes the windows that are over every second and sends their aggregates to C2.
+    pub async fn run(&self) -> Result<(), String> {
+        let mut c2 = C2Outbox::new(AGGREGATE_TYPE, "aggregate");
+        loop {
+            tokio::time::sleep(CLOSE_INTERVAL).await;
+            let aggregates = self.close(Utc::now().timestamp_millis());
+            if let Some(c2) = c2.as_mut() {
+                c2.send(&aggregates).await;
+            }
+        }
+    }
+
+    /// Aggregates of the windows whose end plus lateness lies before `now_t`.
+    fn close(&self, now_t: i64) -> Vec<Aggregate> {
+        let config = CONFIG.current();
+        let groups = config.get_aggregations();
+        let mut tags = self.tags.lock().unwrap();
+        let mut aggregates = Vec::new();
+        for (tag, state) in tags.iter_mut() {
+            let Some(group) = state.group.and_then(|group| groups.get(group)) else {
+                continue;
+            };
+            let window = group.window_secs as i64 * 1000;
+            while let Some(start) = state.next_start {
+                let end = start + window;
+                if end + group.lateness_ms as i64 > now_t {
+                    break;
+                }
+                if let Some(aggregate) = Self::aggregate(group, tag, start, end, state) {
+                    METRICS.aggregate_windows.inc(&[&group.name]);
+                    aggregates.push(aggregate);
+                }
+                let mut next_start = start + step_ms(group);
+                while state.values.front().is_some_and(|(t, _)| *t < next_start) {
+                    state.carried = state.values.pop_front();
+                }
+                state.closed_until = next_start;
+                // Skips the windows of a gap in the values rather than stepping through each.
+                if let Some((t, _)) = state.values.front() {
+                    next_start = next_start.max(first_window_start(group, *t));
+                }
+                state.next_start = (!state.values.is_empty()).then_some(next_start);
+            }
+        }
+        aggregates
+    }
+
+    fn aggregate(group: &AggregationGroup, tag: &str, start: i64, end: i64, state: &TagWindows) -> Option<Aggregate> {
+        let in_window: Vec<(i64, f64)> = state.values.iter().copied().take_while(|(t, _)| *t < end).collect();
+        let (first, last) = (*in_window.first()?, *in_window.last()?);
+        let count = in_window.len() as f64;
+        let mean = in_window.iter().map(|(_, v)| v).sum::<f64>() / count;
+        let values = group
+            .functions
+            .iter()
+            .map(|function| AggregateValue {
+                function: function.as_str().to_string(),
+                v: match function {
+                    AggregateFunction::Min => in_window.iter().map(|(_, v)| *v).fold(f64::INFINITY, f64::min),
+                    AggregateFunction::Max => in_window.iter().map(|(_, v)| *v).fold(f64::NEG_INFINITY, f64::max),
+                    AggregateFunction::Mean => mean,
+                    AggregateFunction::TimeWeightedAverage => time_weighted_average(&in_window, state.carried, start, end),
+                    AggregateFunction::Count => count,
+                    AggregateFunction::First => first.1,
+                    AggregateFunction::Last => last.1,
+                    AggregateFunction::Stddev => {
+                        (in_window.iter().map(|(_, v)| (v - mean).powi(2)).sum::<f64>() / count).sqrt()
+                    }
+                },
+            })
+            .collect();
+        Some(Aggregate { sensor: tag.to_string(), start_t: start, end_t: end, values })
+    }
+}
+
+/// Each value holds until the next one or the end of the window. The value carried in from before
+/// the window holds from its start; without one the average begins at the first value.
+fn time_weighted_average(values: &[(i64, f64)], carried: Option<(i64, f64)>, start: i64, end: i64) -> f64 {
+    let mut held = carried.map(|(_, v)| (start, v));
+    let (mut weighted, mut duration) = (0.0, 0.0);
+    for (t, v) in values.iter().copied().chain(std::iter::once((end, f64::NAN))) {
+        if let Some((since, held_v)) = held {
+            weighted += held_v * (t - since) as f64;
+            duration += (t - since) as f64;
+        }
+        held = Some((t, v));
+    }
+    if duration > 0.0 {
+        weighted / duration
+    } else {
+        values.last().map_or(f64::NAN, |(_, v)| *v)
+    }
+}
+
+fn step_ms(group: &AggregationGroup) -> i64 {
+    let step = if group.step_secs == 0 { group.window_secs } else { group.step_secs };
+    step.max(1) as i64 * 1000
+}
+
+/// Start of the earliest window that contains `t`.
+fn first_window_start(group: &AggregationGroup, t: i64) -> i64 {
+    let (window, step) = (group.window_secs.max(1) as i64 * 1000, step_ms(group));
+    ((t - window).div_euclid(step) + 1) * step
+}
+
+lazy_static! {
+    pub static ref EDGE_AGGREGATOR: EdgeAggregator = EdgeAggregator::new();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::system_initializer::test_support::{self, assert_close};
+
+    fn group(window_secs: u64, step_secs: u64) -> AggregationGroup {
+        test_support::group(json!({"window_secs": window_secs, "step_secs": step_secs}))
+    }
+
+    fn windows(values: &[(i64, f64)], carried: Option<(i64, f64)>) -> TagWindows {
+        TagWindows { group: Some(0), next_start: Some(0), closed_until: 0, values: values.iter().copied().collect(), carried }
+    }
+
+    #[test]
+    fn tumbling_windows_start_on_multiples_of_the_window() {
+        let group = group(60, 0);
+        assert_eq!(first_window_start(&group, 0), 0);
+        assert_eq!(first_window_start(&group, 59_999), 0);
+        assert_eq!(first_window_start(&group, 60_000), 60_000);
+        assert_eq!(first_window_start(&group, 61_000), 60_000);
+        assert_eq!(first_window_start(&group, -1), -60_000);
+    }
+
+    #[test]
+    fn sliding_windows_start_at_the_earliest_step_that_contains_the_value() {
+        let group = group(60, 10);
+        assert_eq!(step_ms(&group), 10_000);
+        // [10 s, 70 s) is the earliest window holding 61 s; [0, 60 s) ends before it.
+        assert_eq!(first_window_start(&group, 61_000), 10_000);
+        assert_eq!(first_window_start(&group, 60_000), 10_000);
+        assert_eq!(first_window_start(&group, 59_999), 0);
+        assert_eq!(first_window_start(&group, 5_000), -50_000);
+    }
+
+    #[test]
+    fn time_weighted_average_holds_each_value_until_the_next() {
+        // 2 holds for 1 s, 4 for the last 58 s.
+        assert_close(time_weighted_average(&[(1_000, 2.0), (2_000, 4.0)], None, 0, 60_000), 234_000.0 / 59_000.0);
+        // The carried value fills the first second.
+        assert_close(time_weighted_average(&[(1_000, 2.0), (2_000, 4.0)], Some((-5_000, 1.0)), 0, 60_000), 235_000.0 / 60_000.0);
+        assert_close(time_weighted_average(&[(5_000, 7.0)], None, 0, 60_000), 7.0);
+        assert_close(time_weighted_average(&[(60_000, 7.0)], None, 0, 60_000), 7.0);
+        assert!(time_weighted_average(&[], None, 0, 60_000).is_nan());
+    }
+
+    #[test]
+    fn aggregates_take_only_the_values_inside_the_window() {
+        let group = group(30, 0);
+        let state = windows(&[(0, 1.0), (10_000, 3.0), (20_000, 5.0), (30_000, 100.0)], None);
+        let aggregate = EdgeAggregator::aggregate(&group, "Temp", 0, 30_000, &state).unwrap();
+        assert_eq!((aggregate.sensor.as_str(), aggregate.start_t, aggregate.end_t), ("Temp", 0, 30_000));
+        let value = |function: &str| aggregate.values.iter().find(|value| value.function == function).unwrap().v;
+        assert_close(value("min"), 1.0);
+        assert_close(value("max"), 5.0);
+        assert_close(value("mean"), 3.0);
+        assert_close(value("time_weighted_average"), 3.0);
+        assert_close(value("count"), 3.0);
+        assert_close(value("first"), 1.0);
+        assert_close(value("last"), 5.0);
+        assert_close(value("stddev"), (8.0f64 / 3.0).sqrt());
+    }
+
+    #[test]
+    fn empty_windows_have_no_aggregate() {
+        let state = windows(&[(30_000, 1.0)], Some((-1, 4.0)));
+        assert!(EdgeAggregator::aggregate(&group(30, 0), "Temp", 0, 30_000, &state).is_none());
+    }
+}
@@ -0,0 +1,500 @@
+/// The expressions of calculated tags: arithmetic, comparisons and logic over the latest values of
+/// other tags, with booleans as 1 and 0.
+///
+/// From loosest to tightest: `||`, `&&`, `== != < <= > >=`, `+ -`, `* / %`, unary `- !`, and `^`,
+/// which groups to the right. Tags are named as they are or in double quotes when the name is not an
+/// identifier. `true`, `false` and `pi` are constants.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Number(f64),
+    Tag(String),
+    Negate(Box<Expression>),
+    Not(Box<Expression>),
+    Binary(BinaryOp, Box<Expression>, Box<Expression>),
+    Call(Function, Vec<Expression>),
+    /// Change of the tag per second between its last two values.
+    Rate(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Power,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    Abs,
+    Sqrt,
+    Exp,
+    Ln,
+    Log10,
+    Round,
+    Floor,
+    Ceil,
+    Min,
+    Max,
+    /// `if(condition, then, else)`
+    If,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Function> {
+        Some(match name {
+            "abs" => Function::Abs,
+            "sqrt" => Function::Sqrt,
+            "exp" => Function::Exp,
+            "ln" => Function::Ln,
+            "log10" => Function::Log10,
+            "round" => Function::Round,
+            "floor" => Function::Floor,
+            "ceil" => Function::Ceil,
+            "min" => Function::Min,
+            "max" => Function::Max,
+            "if" => Function::If,
+            _ => return None,
+        })
+    }
+
+    /// Whether the function takes this many arguments.
+    fn takes(&self, arguments: usize) -> bool {
+        match self {
+            Function::Min | Function::Max => arguments >= 1,
+            Function::If => arguments == 3,
+            _ => arguments == 1,
+        }
+    }
+}
+
+/// What an expression reads from the running client.
+pub trait Inputs {
+    fn value(&self, tag: &str) -> Option<f64>;
+    fn rate(&self, tag: &str) -> Option<f64>;
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Expression, String> {
+        let mut parser = Parser { tokens: tokenize(text)?, position: 0 };
+        let expression = parser.or()?;
+        match parser.tokens.get(parser.position) {
+            Some((offset, token)) => Err(format!("unexpected {} at {}", token, offset)),
+            None => Ok(expression),
+        }
+    }
+
+    /// The tags the expression reads, once each.
+    pub fn tags(&self) -> Vec<String> {
+        let mut tags = Vec::new();
+        self.collect_tags(&mut tags);
+        tags
+    }
+
+    fn collect_tags(&self, tags: &mut Vec<String>) {
+        match self {
+            Expression::Number(_) => {}
+            Expression::Tag(tag) | Expression::Rate(tag) => {
+                if !tags.contains(tag) {
+                    tags.push(tag.clone());
+                }
+            }
+            Expression::Negate(operand) | Expression::Not(operand) => operand.collect_tags(tags),
+            Expression::Binary(_, left, right) => {
+                left.collect_tags(tags);
+                right.collect_tags(tags);
+            }
+            Expression::Call(_, arguments) => arguments.iter().for_each(|argument| argument.collect_tags(tags)),
+        }
+    }
+
+    /// `None` while a tag it reads has no value yet, or no two for `rate`.
+    pub fn evaluate(&self, inputs: &dyn Inputs) -> Option<f64> {
+        Some(match self {
+            Expression::Number(number) => *number,
+            Expression::Tag(tag) => inputs.value(tag)?,
+            Expression::Rate(tag) => inputs.rate(tag)?,
+            Expression::Negate(operand) => -operand.evaluate(inputs)?,
+            Expression::Not(operand) => boolean(operand.evaluate(inputs)? == 0.0),
+            Expression::Binary(op, left, right) => {
+                let left = left.evaluate(inputs)?;
+                // Only the side that decides is needed, so a condition can guard a missing input.
+                match op {
+                    BinaryOp::Or if left != 0.0 => return Some(1.0),
+                    BinaryOp::And if left == 0.0 => return Some(0.0),
+                    _ => {}
+                }
+                let right = right.evaluate(inputs)?;
+                match op {
+                    BinaryOp::Or | BinaryOp::And => boolean(right != 0.0),
+                    BinaryOp::Equal => boolean(left == right),
+                    BinaryOp::NotEqual => boolean(left != right),
+                    BinaryOp::Less => boolean(left < right),
+                    BinaryOp::LessOrEqual => boolean(left <= right),
+                    BinaryOp::Greater => boolean(left > right),
+                    BinaryOp::GreaterOrEqual => boolean(left >= right),
+                    BinaryOp::Add => left + right,
+                    BinaryOp::Subtract => left - right,
+                    BinaryOp::Multiply => left * right,
+                    BinaryOp::Divide => left / right,
+                    BinaryOp::Remainder => left % right,
+                    BinaryOp::Power => left.powf(right),
+                }
+            }
+            Expression::Call(Function::If, arguments) => {
+                let branch = if arguments[0].evaluate(inputs)? != 0.0 { &arguments[1] } else { &arguments[2] };
+                branch.evaluate(inputs)?
+            }
+            Expression::Call(function, arguments) => {
+                let values = arguments.iter().map(|argument| argument.evaluate(inputs)).collect::<Option<Vec<f64>>>()?;
+                match function {
+                    Function::Abs => values[0].abs(),
+                    Function::Sqrt => values[0].sqrt(),
+                    Function::Exp => values[0].exp(),
+                    Function::Ln => values[0].ln(),
+                    Function::Log10 => values[0].log10(),
+                    Function::Round => values[0].round(),
+                    Function::Floor => values[0].floor(),
+                    Function::Ceil => values[0].ceil(),
+                    Function::Min => values.into_iter().fold(f64::INFINITY, f64::min),
+                    Function::Max => values.into_iter().fold(f64::NEG_INFINITY, f64::max),
+                    Function::If => unreachable!("if is evaluated lazily above"),
+                }
+            }
+        })
+    }
+}
+
+fn boolean(value: bool) -> f64 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    /// A name in double quotes, never a constant or function.
+    QuotedName(String),
+    Symbol(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(number) => write!(f, "number {}", number),
+            Token::Name(name) => write!(f, "name {}", name),
+            Token::QuotedName(name) => write!(f, "name \"{}\"", name),
+            Token::Symbol(symbol) => write!(f, "'{}'", symbol),
+        }
+    }
+}
+
+// Two-character symbols first, so `<=` is not read as `<` and `=`.
+const SYMBOLS: &[&str] = &[
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "^", "!", "(", ")", ",",
+];
+
+/// Splits the text into tokens, each with its character offset for error messages.
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut position = 0;
+    while position < chars.len() {
+        let c = chars[position];
+        let start = position;
+        if c.is_whitespace() {
+            position += 1;
+        } else if c.is_ascii_digit() || (c == '.' && chars.get(position + 1).is_some_and(|c| c.is_ascii_digit())) {
+            while position < chars.len() && (chars[position].is_ascii_digit() || chars[position] == '.') {
+                position += 1;
+            }
+            if position < chars.len() && (chars[position] == 'e' || chars[position] == 'E') {
+                let mut exponent = position + 1;
+                if exponent < chars.len() && (chars[exponent] == '+' || chars[exponent] == '-') {
+                    exponent += 1;
+                }
+                if exponent < chars.len() && chars[exponent].is_ascii_digit() {
+                    position = exponent;
+                    while position < chars.len() && chars[position].is_ascii_digit() {
+                        position += 1;
+                    }
+                }
+            }
+            let number: String = chars[start..position].iter().collect();
+            let number = number.parse().map_err(|_| format!("{} at {} is not a number", number, start))?;
+            tokens.push((start, Token::Number(number)));
+        } else if c.is_alphabetic() || c == '_' {
+            while position < chars.len() && (chars[position].is_alphanumeric() || chars[position] == '_') {
+                position += 1;
+            }
+            tokens.push((start, Token::Name(chars[start..position].iter().collect())));
+        } else if c == '"' {
+            let Some(length) = chars[position + 1..].iter().position(|c| *c == '"') else {
+                return Err(format!("quote at {} is not closed", start));
+            };
+            tokens.push((start, Token::QuotedName(chars[position + 1..position + 1 + length].iter().collect())));
+            position += length + 2;
+        } else {
+            let Some(symbol) = SYMBOLS.iter().find(|symbol| symbol.chars().enumerate().all(|(i, s)| chars.get(position + i) == Some(&s))) else {
+                return Err(format!("unexpected '{}' at {}", c, start));
+            };
+            tokens.push((start, Token::Symbol(symbol)));
+            position += symbol.len();
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent, one method per precedence level.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+}
+
+impl Parser {
+    fn or(&mut self) -> Result<Expression, String> {
+        self.binary(&[("||", BinaryOp::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expression, String> {
+        self.binary(&[("&&", BinaryOp::And)], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expression, String> {
+        self.binary(
+            &[
+                ("==", BinaryOp::Equal),
+                ("!=", BinaryOp::NotEqual),
+                ("<=", BinaryOp::LessOrEqual),
+                (">=", BinaryOp::GreaterOrEqual),
+                ("<", BinaryOp::Less),
+                (">", BinaryOp::Greater),
+            ],
+            Self::sum,
+        )
+    }
+
+    fn sum(&mut self) -> Result<Expression, String> {
+        self.binary(&[("+", BinaryOp::Add), ("-", BinaryOp::Subtract)], Self::product)
+    }
+
+    fn product(&mut self) -> Result<Expression, String> {
+        self.binary(
+            &[("*", BinaryOp::Multiply), ("/", BinaryOp::Divide), ("%", BinaryOp::Remainder)],
+            Self::unary,
+        )
+    }
+
+    /// Left-associative operators of one level.
+    fn binary(
+        &mut self,
+        operators: &[(&str, BinaryOp)],
+        operand: fn(&mut Self) -> Result<Expression, String>,
+    ) -> Result<Expression, String> {
+        let mut left = operand(self)?;
+        while let Some(op) = operators.iter().find(|(symbol, _)| self.peek_symbol(symbol)).map(|(_, op)| *op) {
+            self.position += 1;
+            left = Expression::Binary(op, Box::new(left), Box::new(operand(self)?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, String> {
+        if self.take_symbol("-") {
+            return Ok(Expression::Negate(Box::new(self.unary()?)));
+        }
+        if self.take_symbol("!") {
+            return Ok(Expression::Not(Box::new(self.unary()?)));
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<Expression, String> {
+        let base = self.primary()?;
+        if self.take_symbol("^") {
+            // The exponent may carry its own sign, as in 10^-3.
+            return Ok(Expression::Binary(BinaryOp::Power, Box::new(base), Box::new(self.unary()?)));
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Expression, String> {
+        let Some((offset, token)) = self.tokens.get(self.position).cloned() else {
+            return Err("expression ends too early".to_string());
+        };
+        self.position += 1;
+        match token {
+            Token::Number(number) => Ok(Expression::Number(number)),
+            Token::QuotedName(name) => Ok(Expression::Tag(name)),
+            Token::Symbol("(") => {
+                let expression = self.or()?;
+                self.expect(")")?;
+                Ok(expression)
+            }
+            Token::Name(name) if self.peek_symbol("(") => {
+                self.position += 1;
+                let arguments = self.arguments()?;
+                if name == "rate" {
+                    return match arguments.as_slice() {
+                        [Expression::Tag(tag)] => Ok(Expression::Rate(tag.clone())),
+                        _ => Err(format!("rate at {} takes one tag", offset)),
+                    };
+                }
+                let function = Function::from_name(&name).ok_or_else(|| format!("unknown function {} at {}", name, offset))?;
+                if !function.takes(arguments.len()) {
+                    return Err(format!("{} at {} does not take {} arguments", name, offset, arguments.len()));
+                }
+                Ok(Expression::Call(function, arguments))
+            }
+            Token::Name(name) => Ok(match name.as_str() {
+                "true" => Expression::Number(1.0),
+                "false" => Expression::Number(0.0),
+                "pi" => Expression::Number(std::f64::consts::PI),
+                _ => Expression::Tag(name),
+            }),
+            token => Err(format!("unexpected {} at {}", token, offset)),
+        }
+    }
+
+    /// Arguments after an opening parenthesis, up to and including the closing one.
+    fn arguments(&mut self) -> Result<Vec<Expression>, String> {
+        let mut arguments = Vec::new();
+        if self.take_symbol(")") {
+            return Ok(arguments);
+        }
+        loop {
+            arguments.push(self.or()?);
+            if self.take_symbol(")") {
+                return Ok(arguments);
+            }
+            self.expect(",")?;
+        }
+    }
+
+    fn peek_symbol(&self, symbol: &str) -> bool {
+        matches!(self.tokens.get(self.position), Some((_, Token::Symbol(s))) if *s == symbol)
+    }
+
+    fn take_symbol(&mut self, symbol: &str) -> bool {
+        let found = self.peek_symbol(symbol);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.take_symbol(symbol) {
+            return Ok(());
+        }
+        match self.tokens.get(self.position) {
+            Some((offset, token)) => Err(format!("expected '{}' at {} but found {}", symbol, offset, token)),
+            None => Err(format!("expected '{}' at the end", symbol)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Values by tag, oldest first.
+    struct Values(HashMap<&'static str, Vec<(i64, f64)>>);
+
+    impl Inputs for Values {
+        fn value(&self, tag: &str) -> Option<f64> {
+            self.0.get(tag)?.last().map(|(_, v)| *v)
+        }
+
+        fn rate(&self, tag: &str) -> Option<f64> {
+            match self.0.get(tag)?.as_slice() {
+                [.., (previous_t, previous_v), (t, v)] => Some((v - previous_v) / ((t - previous_t) as f64 / 1000.0)),
+                _ => None,
+            }
+        }
+    }
+
+    fn values(values: &[(&'static str, &[(i64, f64)])]) -> Values {
+        Values(values.iter().map(|(tag, values)| (*tag, values.to_vec())).collect())
+    }
+
+    fn evaluate(text: &str, inputs: &Values) -> Option<f64> {
+        Expression::parse(text).unwrap().evaluate(inputs)
+    }
+
+    #[test]
+    fn power_binds_tighter_than_negation_and_groups_right() {
+        let none = values(&[]);
+        assert_eq!(evaluate("-2^2", &none), Some(-4.0));
+        assert_eq!(evaluate("2^3^2", &none), Some(512.0));
+        assert_eq!(evaluate("10^-3", &none), Some(0.001));
+        assert_eq!(evaluate("1 + 2 * 3 ^ 2", &none), Some(19.0));
+        assert_eq!(evaluate("(1 + 2) * 3", &none), Some(9.0));
+        assert_eq!(evaluate("7 - 2 - 1", &none), Some(4.0));
+        assert_eq!(evaluate("1 + 1 == 2 && 3 > 2 || false", &none), Some(1.0));
+    }
+
+    #[test]
+    fn logic_skips_a_missing_input_it_does_not_need() {
+        let inputs = values(&[("Running", &[(0, 0.0)]), ("Ready", &[(0, 1.0)])]);
+        assert_eq!(evaluate("Running && Missing", &inputs), Some(0.0));
+        assert_eq!(evaluate("Ready || Missing", &inputs), Some(1.0));
+        assert_eq!(evaluate("Ready && Missing", &inputs), None);
+        assert_eq!(evaluate("Running || Missing", &inputs), None);
+        assert_eq!(evaluate("if(Running, Missing, 5)", &inputs), Some(5.0));
+    }
+
+    #[test]
+    fn if_takes_three_arguments() {
+        assert_eq!(Expression::parse("if(1, 2)"), Err("if at 0 does not take 2 arguments".to_string()));
+        assert_eq!(Expression::parse("1 + if(1, 2, 3, 4)"), Err("if at 4 does not take 4 arguments".to_string()));
+        assert!(Expression::parse("if(1, 2, 3)").is_ok());
+    }
+
+    #[test]
+    fn quoted_names_are_tags() {
+        let inputs = values(&[("Line 1.Speed", &[(0, 3.0)]), ("pi", &[(0, 2.0)])]);
+        let expression = Expression::parse("\"Line 1.Speed\" * \"pi\" + pi").unwrap();
+        assert_eq!(expression.tags(), vec!["Line 1.Speed".to_string(), "pi".to_string()]);
+        assert_eq!(expression.evaluate(&inputs), Some(6.0 + std::f64::consts::PI));
+    }
+
+    #[test]
+    fn unterminated_quote_is_an_error() {
+        assert_eq!(Expression::parse("1 + \"Speed"), Err("quote at 4 is not closed".to_string()));
+    }
+
+    #[test]
+    fn rate_needs_two_values() {
+        let inputs = values(&[("Counter", &[(1_000, 10.0)])]);
+        assert_eq!(evaluate("rate(Counter)", &inputs), None);
+        let inputs = values(&[("Counter", &[(1_000, 10.0), (3_000, 20.0)])]);
+        assert_eq!(evaluate("rate(Counter)", &inputs), Some(5.0));
+        assert_eq!(Expression::parse("rate(2)"), Err("rate at 0 takes one tag".to_string()));
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_character() {
+        assert_eq!(Expression::parse("1 $ 2"), Err("unexpected '$' at 2".to_string()));
+        assert_eq!(Expression::parse("1 +"), Err("expression ends too early".to_string()));
+        assert_eq!(Expression::parse("(1 + 2"), Err("expected ')' at the end".to_string()));
+        assert_eq!(Expression::parse("(1 + 2 3"), Err("expected ')' at 7 but found number 3".to_string()));
+        assert_eq!(Expression::parse("1 2"), Err("unexpected number 2 at 2".to_string()));
+        assert_eq!(Expression::parse("foo(1)"), Err("unknown function foo at 0".to_string()));
+    }
+}
@@ -2,66 +2,191 @@ pub mod producer;
 pub mod consumer;
 pub mod data_queue;
 pub mod tags_synchronizer;
+pub mod tag_control;
+pub mod tag_definition;
+pub mod tag_partitioner;
+pub mod tag_catalog;
+pub mod tag_watchdog;
+pub mod sample_filter;
+pub mod edge_aggregator;
+pub mod expression;
+pub mod calculated_tags;
+pub mod units;
+pub mod scaling;
+pub mod shutdown;
+pub mod supervisor;
+#[cfg(test)]
+mod test_support;
 
 use log::{info, error};
+use tracing::{info_span, Instrument};
 use tokio;
-use tokio::time::{sleep, Duration};
-use std::sync::atomic::Ordering;
-use tokio::sync::OnceCell;
-use crate::system_initializer::tags_synchronizer::TagSynchronizer;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout_at, Duration};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::{OnceCell, Mutex};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use opcua::types::StatusCode;
+use crate::system_initializer::producer::{ProducerCommand, TagResults};
+use crate::system_initializer::tag_control::{ControlAction, ControlRequest, ControlReply};
+use crate::system_initializer::tag_definition::{TagDefinition, TAG_DEFINITIONS};
+use crate::system_initializer::tag_catalog::TagCatalog;
+use crate::system_initializer::tag_watchdog::WATCHDOG;
+use crate::system_initializer::sample_filter::SAMPLE_FILTER;
+use crate::system_initializer::edge_aggregator::EDGE_AGGREGATOR;
+use crate::system_initializer::calculated_tags::CALCULATED_TAGS;
+use crate::system_initializer::scaling::SCALING;
+use crate::system_initializer::tags_synchronizer::{TagSynchronizer, TagChanges, TagSyncCommand};
 use crate::config::configuration::CONFIG;
+use crate::config::loader;
+use crate::config::reload::{self, ConfigChanges, LiveChange, ReloadTrigger};
 use crate::clients::ws_client::WebSocketClient;
+use crate::clients::c2_endpoints::C2_ENDPOINTS;
 use crate::system_initializer::data_queue::QUEUE;
+use crate::system_initializer::shutdown::{EXIT_DATA_LOST, EXIT_DELIVERED, EXIT_ESCALATED, EXIT_UNSENT_SAVED, SHUTDOWN};
+use crate::system_initializer::supervisor::{TaskState, SUPERVISOR};
+use crate::telemetry::health::HEALTH;
+use crate::telemetry::server;
+use crate::telemetry::logging;
+
+/// Time consumers get past the shutdown deadline to hand back what they hold.
+const CONSUMER_STOP_GRACE: Duration = Duration::from_secs(2);
+
 pub struct SystemInitializer {
-    tags_vec: Vec<String>,
-    num_producers: usize,
+    tags_vec: Vec<TagDefinition>,
     num_consumers: usize,
+    tag_synchronizer: Mutex<TagSynchronizer>,
+    producer_senders: Mutex<Vec<UnboundedSender<ProducerCommand>>>,
+    tag_catalog: Mutex<TagCatalog>,
+    tag_sync_sender: UnboundedSender<TagSyncCommand>,
+    // Held by the tag sync loop while it runs, so commands wait for it across a restart.
+    tag_sync_receiver: Mutex<UnboundedReceiver<TagSyncCommand>>,
+    // Tag sync, config watch and metrics; aborted on shutdown.
+    background: std::sync::Mutex<Vec<JoinHandle<()>>>,
 }
 
 impl SystemInitializer {
     pub async fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let tags_vec = Self::sync_tags().await?;
-        let num_producers = CONFIG.get_num_producers();
-        let num_consumers = CONFIG.get_num_consumers();
+        let (tag_synchronizer, tags_vec) = Self::sync_tags().await?;
+        let num_consumers = CONFIG.current().get_num_consumers();
+        let tag_catalog = TagCatalog::connect()?;
+        TAG_DEFINITIONS.insert(&tags_vec);
+        HEALTH.record_tag_sync(Ok(()));
+        let (tag_sync_sender, tag_sync_receiver) = mpsc::unbounded_channel();
 
         Ok(Self {
             tags_vec,
-            num_producers,
             num_consumers,
+            tag_synchronizer: Mutex::new(tag_synchronizer),
+            producer_senders: Mutex::new(Vec::new()),
+            tag_catalog: Mutex::new(tag_catalog),
+            tag_sync_sender,
+            tag_sync_receiver: Mutex::new(tag_sync_receiver),
+            background: std::sync::Mutex::new(Vec::new()),
         })
     }
 
-    pub async fn init_process(&self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Runs until SIGINT or SIGTERM, or until a task fails for good, then shuts down and returns the
+    /// process exit code.
+    pub async fn init_process(&'static self) -> Result<i32, Box<dyn std::error::Error>> {
         let producer_handles = self.init_producer().await?;
         let consumer_handles = self.init_consumer().await?;
-        self.log_metrics().await;
+        self.init_tag_sync();
+        self.init_tag_watchdog();
+        self.init_edge_aggregator();
+        self.init_config_watch();
+        self.init_telemetry();
+        self.log_metrics();
+
+        let escalated = tokio::select! {
+            signal = shutdown::signal() => {
+                info!("{} received, shutting down", signal);
+                false
+            }
+            task = SUPERVISOR.escalation() => {
+                error!("Task {} keeps failing, shutting down", task);
+                true
+            }
+        };
+        let exit_code = self.shutdown(producer_handles, consumer_handles).await;
+        Ok(if escalated && exit_code == EXIT_DELIVERED { EXIT_ESCALATED } else { exit_code })
+    }
+
+    /// Stops collecting, gives the consumers until the deadline to deliver what is queued, and
+    /// saves whatever they could not.
+    async fn shutdown(&self, producer_handles: Vec<JoinHandle<()>>, consumer_handles: Vec<JoinHandle<()>>) -> i32 {
+        let deadline = SHUTDOWN.begin(CONFIG.current().get_shutdown_timeout());
+        for task in self.background.lock().unwrap().drain(..) {
+            task.abort();
+        }
 
-        for handle in producer_handles {
-            let _ = handle.await;
+        for sender in self.producer_senders.lock().await.iter() {
+            let _ = sender.send(ProducerCommand::Shutdown);
+        }
+        for (producer, handle) in producer_handles.into_iter().enumerate() {
+            if timeout_at(deadline, handle).await.is_err() {
+                error!("Producer {} did not stop before the shutdown deadline", producer + 1);
+            }
         }
+        self.tag_catalog.lock().await.disconnect();
 
-        for handle in consumer_handles {
-            let _ = handle.await;
+        QUEUE.close();
+        let mut lost = false;
+        for (consumer, handle) in consumer_handles.into_iter().enumerate() {
+            if timeout_at(deadline + CONSUMER_STOP_GRACE, handle).await.is_err() {
+                error!("Consumer {} did not stop; the batch it held is lost", consumer + 1);
+                lost = true;
+            }
         }
-        Ok(())
+
+        let mut unsent = SHUTDOWN.take_unsent();
+        unsent.extend(QUEUE.drain());
+        let exit_code = match (unsent.is_empty(), lost) {
+            (true, false) => EXIT_DELIVERED,
+            (true, true) => EXIT_DATA_LOST,
+            (false, _) => match shutdown::save_unsent(&unsent).await {
+                Ok(()) if !lost => EXIT_UNSENT_SAVED,
+                Ok(()) => EXIT_DATA_LOST,
+                Err(e) => {
+                    error!("Failed to save {} unsent records: {}", unsent.len(), e);
+                    EXIT_DATA_LOST
+                }
+            },
+        };
+        info!("Shutdown complete");
+        exit_code
     }
-    async fn sync_tags() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    async fn sync_tags() -> Result<(TagSynchronizer, Vec<TagDefinition>), Box<dyn std::error::Error>> {
         let mut get_tags_obj = TagSynchronizer::new()?;
         let tags_vec = get_tags_obj.get_tags().await?;
-        Ok(tags_vec)
+        Ok((get_tags_obj, tags_vec))
     }
     async fn init_producer(&self) -> Result<Vec<tokio::task::JoinHandle<()>>, Box<dyn std::error::Error>> {
-        let tags_per_producer = self.tags_vec.len() / self.num_producers;
         let mut producer_handles = Vec::new();
+        let mut producer_senders = self.producer_senders.lock().await;
+        let plans = self.tag_catalog.lock().await.partition(self.tags_vec.clone());
+        info!("Partitioned {} tags across {} producers", self.tags_vec.len(), plans.len());
 
-        for (i, producer_tags) in self.tags_vec.chunks(tags_per_producer).enumerate() {
-            let producer_tags = producer_tags.to_vec();
-            let handle = tokio::spawn(async move {
-                let mut producer = producer::Producer::new();
-                info!("Producer {} starting with {} tags", i + 1, producer_tags.len());
-                if let Err(e) = producer.produce(producer_tags).await {
-                    error!("Producer {} encountered an error: {}", i + 1, e);
+        for (i, (session_pool, producer_tags)) in plans.into_iter().enumerate() {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            producer_senders.push(sender);
+            HEALTH.watch_producer(&session_pool);
+            info!("Producer {} starting with {} tags from {}", i + 1, producer_tags.len(), session_pool.name());
+            let source = session_pool.name().to_string();
+            let producer = Arc::new(Mutex::new(producer::Producer::new(session_pool, producer_tags)));
+            let receiver = Arc::new(Mutex::new(receiver));
+            let handle = SUPERVISOR.supervise(format!("producer-{}", i + 1), move || {
+                let producer = producer.clone();
+                let receiver = receiver.clone();
+                let span = info_span!("producer", producer = i + 1, source = source.as_str());
+                async move {
+                    let mut producer = producer.lock().await;
+                    let mut receiver = receiver.lock().await;
+                    producer.produce(&mut receiver).await.map_err(|e| e.to_string())
                 }
+                .instrument(span)
             });
             producer_handles.push(handle);
         }
@@ -72,12 +197,13 @@ impl SystemInitializer {
     async fn init_consumer(&self) -> Result<Vec<tokio::task::JoinHandle<()>>, Box<dyn std::error::Error>> {
         let mut consumer_handles = Vec::new();
         for i in 0..self.num_consumers {
-            let handle = tokio::spawn(async move {
-                let mut consumer_obj = consumer::Consumer::new(200);
-                info!("Consumer {} starting", i + 1);
-                if let Err(e) = consumer_obj.consume().await {
-                    error!("Consumer {} encountered an error: {}", i + 1, e);
+            let handle = SUPERVISOR.supervise(format!("consumer-{}", i + 1), move || {
+                async move {
+                    let mut consumer_obj = consumer::Consumer::new(i);
+                    info!("Consumer {} starting", i + 1);
+                    consumer_obj.consume().await.map_err(|e| e.to_string())
                 }
+                .instrument(info_span!("consumer", consumer = i + 1))
             });
             consumer_handles.push(handle);
         }
@@ -85,20 +211,316 @@ impl SystemInitializer {
         Ok(consumer_handles)
     }
 
-    async fn log_metrics(&self) {
-        let metrics_handle = tokio::spawn(async move {
+    fn init_tag_sync(&'static self) {
+        let task = SUPERVISOR.supervise("tag-sync".to_string(), move || self.sync_loop().instrument(info_span!("tag_sync")));
+        self.background.lock().unwrap().push(task);
+    }
+
+    async fn sync_loop(&'static self) -> Result<(), String> {
+        let mut interval = CONFIG.current().get_tag_sync_interval();
+        let mut sync_timer = Self::sync_timer(interval);
+        let mut commands = self.tag_sync_receiver.lock().await;
+        let mut tag_synchronizer = self.tag_synchronizer.lock().await;
+        loop {
+            tokio::select! {
+                _ = sync_timer.tick(), if interval > 0 => {
+                    let changes = match tag_synchronizer.sync_changes().await.map_err(|e| e.to_string()) {
+                        Ok(changes) => changes,
+                        Err(e) => {
+                            error!("Tag sync failed: {}", e);
+                            HEALTH.record_tag_sync(Err(e));
+                            continue;
+                        }
+                    };
+                    HEALTH.record_tag_sync(Ok(()));
+                    if !changes.is_empty() {
+                        self.apply_tag_changes(changes).await;
+                    }
+                }
+                Some(command) = commands.recv() => match command {
+                    TagSyncCommand::Resync => match tag_synchronizer.resync().await.map_err(|e| e.to_string()) {
+                        Ok(changes) => {
+                            HEALTH.record_tag_sync(Ok(()));
+                            if !changes.is_empty() {
+                                self.apply_tag_changes(changes).await;
+                            }
+                        }
+                        Err(e) => {
+                            error!("Full tag resync failed: {}", e);
+                            HEALTH.record_tag_sync(Err(e));
+                        }
+                    },
+                    TagSyncCommand::Reschedule => {
+                        interval = CONFIG.current().get_tag_sync_interval();
+                        sync_timer = Self::sync_timer(interval);
+                    }
+                },
+                request = async { tag_synchronizer.receive_control().await.map_err(|e| e.to_string()) } => {
+                    let request = match request {
+                        Ok(request) => request,
+                        Err(e) => {
+                            error!("Control channel to C2 lost: {}", e);
+                            tag_synchronizer.reconnect().await;
+                            continue;
+                        }
+                    };
+                    let reply = self.handle_control(request).await;
+                    if let Err(e) = tag_synchronizer.send_control_reply(&reply).await {
+                        error!("Failed to send control reply: {}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    fn init_tag_watchdog(&self) {
+        let task = SUPERVISOR.supervise("tag-watchdog".to_string(), || WATCHDOG.run());
+        self.background.lock().unwrap().push(task);
+    }
+
+    fn init_edge_aggregator(&self) {
+        let task = SUPERVISOR.supervise("edge-aggregator".to_string(), || EDGE_AGGREGATOR.run());
+        self.background.lock().unwrap().push(task);
+    }
+
+    fn sync_timer(interval: u64) -> tokio::time::Interval {
+        if interval == 0 {
+            info!("Periodic tag sync disabled");
+        }
+        let period = Duration::from_secs(interval.max(1));
+        tokio::time::interval_at(tokio::time::Instant::now() + period, period)
+    }
+
+    /// Serves `/metrics`, `/healthz` and `/readyz` when `telemetry.listen` is set. It keeps serving while the client shuts down.
+    fn init_telemetry(&self) {
+        let listen = CONFIG.current().get_telemetry().listen.trim().to_string();
+        if listen.is_empty() {
+            return;
+        }
+        SUPERVISOR.supervise("telemetry".to_string(), move || {
+            let listen = listen.clone();
+            async move { server::serve(&listen).await }
+        });
+    }
+
+    /// Reloads the configuration when its file changes or on SIGHUP and applies what can change live.
+    fn init_config_watch(&'static self) {
+        let Some(path) = loader::config_file() else {
+            return;
+        };
+        let task = SUPERVISOR.supervise("config-watch".to_string(), move || async move {
+            let mut trigger = ReloadTrigger::new(path);
+            loop {
+                trigger.wait().await;
+                match reload::reload(path).map_err(|e| e.to_string()) {
+                    Ok(changes) => self.apply_config_changes(changes).await,
+                    Err(e) => error!("Configuration reload failed: {}", e),
+                }
+            }
+        });
+        self.background.lock().unwrap().push(task);
+    }
+
+    async fn apply_config_changes(&self, changes: ConfigChanges) {
+        for change in changes.live {
+            match change {
+                LiveChange::LogLevel => logging::set_level(CONFIG.current().get_log_level()),
+                LiveChange::Subscription => {
+                    for (producer, sender) in self.producer_senders.lock().await.iter().enumerate() {
+                        if sender.send(ProducerCommand::UpdateSubscription(CONFIG.current().get_subscription().clone())).is_err() {
+                            error!("Producer {} is no longer running", producer + 1);
+                        }
+                    }
+                }
+                LiveChange::TagFilter => {
+                    let _ = self.tag_sync_sender.send(TagSyncCommand::Resync);
+                }
+                LiveChange::Filters => SAMPLE_FILTER.reset(),
+                LiveChange::Aggregations => EDGE_AGGREGATOR.reset(),
+                LiveChange::CalculatedTags => CALCULATED_TAGS.reset(),
+                LiveChange::Scaling => SCALING.reset(),
+                LiveChange::TagSyncInterval => {
+                    let _ = self.tag_sync_sender.send(TagSyncCommand::Reschedule);
+                }
+                // Read on every use.
+                LiveChange::Batching
+                | LiveChange::ConfigWatch
+                | LiveChange::Shutdown
+                | LiveChange::Supervision
+                | LiveChange::Telemetry
+                | LiveChange::Health
+                | LiveChange::Watchdog => {}
+            }
+        }
+    }
+
+    async fn apply_tag_changes(&self, changes: TagChanges) {
+        let producer_senders = self.producer_senders.lock().await;
+        let mut tag_catalog = self.tag_catalog.lock().await;
+
+        let (renamed_from, renamed_to): (Vec<String>, Vec<TagDefinition>) = changes.renamed.into_iter().unzip();
+        let removed: Vec<String> = changes.removed.into_iter().chain(renamed_from).collect();
+        TAG_DEFINITIONS.remove(&removed);
+        WATCHDOG.forget(&removed);
+        SAMPLE_FILTER.forget(&removed);
+        EDGE_AGGREGATOR.forget(&removed);
+        CALCULATED_TAGS.forget(&removed);
+        SCALING.forget(&removed);
+        let (removals, _) = tag_catalog.release(removed);
+        let added: Vec<TagDefinition> = changes.added.into_iter().chain(renamed_to).collect();
+        TAG_DEFINITIONS.insert(&added);
+        TAG_DEFINITIONS.insert(&changes.updated);
+        let additions = tag_catalog.assign(added);
+
+        let mut updates: HashMap<usize, Vec<TagDefinition>> = HashMap::new();
+        for tag in changes.updated {
+            match tag_catalog.owner(&tag.name) {
+                Some(producer) => updates.entry(producer).or_default().push(tag),
+                None => error!("Updated tag {} is not assigned to any producer", tag.name),
+            }
+        }
+
+        for (producer, tags) in removals {
+            if producer_senders[producer].send(ProducerCommand::RemoveTags(tags, None)).is_err() {
+                error!("Producer {} is no longer running", producer + 1);
+            }
+        }
+        // The sync point has moved past these tags, so the producers keep and retry those they cannot
+        // subscribe to yet rather than reply.
+        for (producer, tags) in additions {
+            if producer_senders[producer].send(ProducerCommand::AddTags(tags, None)).is_err() {
+                error!("Producer {} is no longer running", producer + 1);
+            }
+        }
+        for (producer, tags) in updates {
+            if producer_senders[producer].send(ProducerCommand::UpdateTags(tags)).is_err() {
+                error!("Producer {} is no longer running", producer + 1);
+            }
+        }
+    }
+
+    async fn handle_control(&self, request: ControlRequest) -> ControlReply {
+        info!("Control request {}: {:?} for {} tags", request.request_id, request.action, request.tags.len());
+        let producer_senders = self.producer_senders.lock().await;
+        let mut tag_catalog = self.tag_catalog.lock().await;
+
+        let (routed, unknown) = match request.action {
+            ControlAction::AddTags => {
+                let tags = request.tags.into_iter().map(TagDefinition::from_name).collect();
+                let routed = tag_catalog.assign(tags)
+                    .into_iter()
+                    .map(|(producer, tags)| (producer, tags.into_iter().map(|tag| tag.name).collect()))
+                    .collect();
+                (routed, Vec::new())
+            }
+            ControlAction::RemoveTags => tag_catalog.release(request.tags),
+            ControlAction::SetSampling => tag_catalog.owned(request.tags),
+        };
+        drop(tag_catalog);
+
+        let mut tag_results: TagResults = unknown
+            .into_iter()
+            .map(|tag| (tag, StatusCode::BadNodeIdUnknown))
+            .collect();
+        let mut replies = Vec::new();
+        for (producer, tags) in routed {
+            let (reply_sender, reply_receiver) = oneshot::channel();
+            let command = match (request.action, request.sampling_interval) {
+                (ControlAction::AddTags, _) => {
+                    let definitions = tags.iter().cloned().map(TagDefinition::from_name).collect();
+                    ProducerCommand::AddTags(definitions, Some(reply_sender))
+                }
+                (ControlAction::RemoveTags, _) => ProducerCommand::RemoveTags(tags.clone(), Some(reply_sender)),
+                (ControlAction::SetSampling, Some(sampling_interval)) => {
+                    ProducerCommand::SetSampling(tags.clone(), sampling_interval, Some(reply_sender))
+                }
+                (ControlAction::SetSampling, None) => {
+                    tag_results.extend(tags.into_iter().map(|tag| (tag, StatusCode::BadInvalidArgument)));
+                    continue;
+                }
+            };
+            if producer_senders[producer].send(command).is_err() {
+                error!("Producer {} is no longer running", producer + 1);
+            }
+            replies.push((tags, reply_receiver));
+        }
+        drop(producer_senders);
+
+        for (tags, reply_receiver) in replies {
+            match reply_receiver.await {
+                Ok(producer_results) => tag_results.extend(producer_results),
+                Err(_) => tag_results.extend(tags.into_iter().map(|tag| (tag, StatusCode::BadServerHalted))),
+            }
+        }
+
+        let applied: Vec<String> = tag_results
+            .iter()
+            .filter(|(_, status)| status.is_good())
+            .map(|(tag, _)| tag.clone())
+            .collect();
+        match request.action {
+            ControlAction::AddTags => {
+                TAG_DEFINITIONS.insert_names(&applied);
+                // Ownership was recorded before the producers answered; tags they could not collect
+                // are placed afresh when requested again.
+                let applied: HashSet<&String> = applied.iter().collect();
+                let rejected: Vec<String> = tag_results
+                    .iter()
+                    .filter(|(tag, _)| !applied.contains(tag))
+                    .map(|(tag, _)| tag.clone())
+                    .collect();
+                if !rejected.is_empty() {
+                    self.tag_catalog.lock().await.release(rejected);
+                }
+            }
+            ControlAction::RemoveTags => {
+                TAG_DEFINITIONS.remove(&applied);
+                WATCHDOG.forget(&applied);
+                SAMPLE_FILTER.forget(&applied);
+                EDGE_AGGREGATOR.forget(&applied);
+                CALCULATED_TAGS.forget(&applied);
+                SCALING.forget(&applied);
+            }
+            ControlAction::SetSampling => {}
+        }
+        ControlReply::new(request.request_id, tag_results)
+    }
+
+    fn log_metrics(&self) {
+        let metrics_handle = SUPERVISOR.supervise("metrics".to_string(), || async {
+            // The queue counts totals, so the counts of one interval are differences between readings.
+            let mut last_consumed = QUEUE.get_consumed_count();
+            let mut last_produced = QUEUE.get_produced_count();
             loop {
                 sleep(Duration::from_secs(10)).await;
-                let consumed_count = QUEUE.get_consumed_count();
-                let produced_count = QUEUE.get_produced_count();
+                let consumed_total = QUEUE.get_consumed_count();
+                let produced_total = QUEUE.get_produced_count();
+                let consumed_count = consumed_total - last_consumed;
+                let produced_count = produced_total - last_produced;
+                (last_consumed, last_produced) = (consumed_total, produced_total);
                 info!("In the last 10 seconds: Produced: {}, Consumed: {}", produced_count, consumed_count);
                 info!("Average: {}", (consumed_count / 10));
-                QUEUE.consumed_count.store(0, Ordering::SeqCst);
-                QUEUE.produced_count.store(0, Ordering::SeqCst);
+                C2_ENDPOINTS.log_health();
+                Self::log_task_states();
             }
         });
 
-        let _ = metrics_handle.await;
-    }   
+        self.background.lock().unwrap().push(metrics_handle);
+    }
+
+    fn log_task_states() {
+        let tasks = SUPERVISOR.statuses();
+        let running = tasks.iter().filter(|task| task.state == TaskState::Running).count();
+        info!("Tasks running: {} of {}", running, tasks.len());
+        for task in tasks.iter().filter(|task| task.state != TaskState::Running) {
+            info!(
+                "Task {} is {:?} after {} restarts, last error: {}",
+                task.name,
+                task.state,
+                task.restarts,
+                task.last_error.as_deref().unwrap_or("none")
+            );
+        }
+    }
 }
 pub static SYSTEM_INITIALIZER: OnceCell<SystemInitializer> = OnceCell::const_new();
\ No newline at end of file
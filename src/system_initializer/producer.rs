@@ -1,60 +1,244 @@
-use tokio;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::oneshot;
+use tokio::time::{sleep_until, Duration, Instant};
 use log::{info, error};
 use crate::clients::opcua_client::OpcuaClient;
-use opcua::types::ObjectId;
+use crate::clients::session_pool::{SessionPool, TagNode};
+use crate::config::configuration::{SubscriptionConfig, CONFIG};
+use crate::system_initializer::tag_definition::TagDefinition;
 use std::error::Error;
+use std::collections::{HashMap, HashSet};
 use opcua::client::prelude::*;
 
+pub type TagResults = Vec<(String, StatusCode)>;
+
+/// Wait before the first retry of tags that could not be subscribed; it doubles with each failed retry.
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+pub type CommandReply = Option<oneshot::Sender<TagResults>>;
+
+pub enum ProducerCommand {
+    AddTags(Vec<TagDefinition>, CommandReply),
+    RemoveTags(Vec<String>, CommandReply),
+    UpdateTags(Vec<TagDefinition>),
+    SetSampling(Vec<String>, f64, CommandReply),
+    UpdateSubscription(SubscriptionConfig),
+    /// Deletes the producer's subscriptions and stops it.
+    Shutdown,
+}
+
 pub struct Producer {
     opcua_client: OpcuaClient,
+    definitions: HashMap<String, TagDefinition>,
+    // Tags kept with their definitions although subscribing to them failed, and when to try them again.
+    failed: HashSet<String>,
+    retry_at: Option<Instant>,
+    retry_delay: Duration,
 }
 
 impl Producer {
-    pub fn new() -> Self {
-        let opcua_client = OpcuaClient::new();
-        Producer { opcua_client }
+    pub fn new(session_pool: Arc<SessionPool>, tags: Vec<TagDefinition>) -> Self {
+        let opcua_client = OpcuaClient::new(session_pool);
+        let definitions = tags.into_iter().map(|tag| (tag.name.clone(), tag)).collect();
+        Producer { opcua_client, definitions, failed: HashSet::new(), retry_at: None, retry_delay: MIN_RETRY_DELAY }
     }
 
-    pub async fn produce(&mut self, tags: Vec<String>) -> Result<(), Box<dyn Error>> {
-        if let Err(e) = self.opcua_client.connect_to_opcserver() {
-            error!("Failed to connect: {}", e);
-            return Ok(());
-        }
-        info!("Connected to the OPC UA server successfully.");
+    /// Subscribes to the producer's tags and applies commands until told to shut down. The producer
+    /// outlives a failed run, so a restart picks up the tags it had and replaces the subscriptions it left.
+    ///
+    /// Tags that cannot be subscribed are retried with backoff until they can, or until they are removed.
+    pub async fn produce(&mut self, commands: &mut UnboundedReceiver<ProducerCommand>) -> Result<(), Box<dyn Error>> {
+        self.opcua_client.delete_subscriptions()?;
+        self.failed.clear();
+        self.retry_at = None;
+        self.retry_delay = MIN_RETRY_DELAY;
+        let tags: Vec<TagDefinition> = self.definitions.values().cloned().collect();
+        self.add_tags(&tags, true).await;
 
-        let root_node_id = ObjectId::RootFolder.into();
-        let matched_tags = match self.opcua_client.browse_nodes(root_node_id, &tags) {
-            Ok(tags) => {
-                info!("Matched tags count: {}", tags.len());
-                tags
+        loop {
+            let retry_at = self.retry_at;
+            let retry_due = async move {
+                match retry_at {
+                    Some(retry_at) => sleep_until(retry_at).await,
+                    None => std::future::pending().await,
+                }
+            };
+            let command = tokio::select! {
+                command = commands.recv() => command,
+                _ = retry_due => {
+                    self.retry_failed().await;
+                    continue;
+                }
+            };
+            let Some(command) = command else {
+                break;
+            };
+            match command {
+                ProducerCommand::AddTags(tags, reply) => {
+                    // Without a reply nobody learns of a failure, as with tags from the periodic sync,
+                    // so the producer keeps trying them itself.
+                    let keep_failed = reply.is_none();
+                    let tag_results = self.add_tags(&tags, keep_failed).await;
+                    Self::reply(tags.into_iter().map(|tag| tag.name).collect(), Ok(tag_results), reply);
+                }
+                ProducerCommand::RemoveTags(tags, reply) => {
+                    for tag in &tags {
+                        self.definitions.remove(tag);
+                        self.failed.remove(tag);
+                    }
+                    let tag_results = self.opcua_client.unsubscribe_tags(&tags);
+                    Self::reply(tags, tag_results, reply);
+                }
+                ProducerCommand::UpdateTags(tags) => self.update_tags(tags),
+                ProducerCommand::SetSampling(tags, sampling_interval, reply) => {
+                    for tag in &tags {
+                        if let Some(definition) = self.definitions.get_mut(tag) {
+                            definition.sampling_interval = Some(sampling_interval);
+                        }
+                    }
+                    let tag_results = self.opcua_client.set_sampling_interval(&tags, sampling_interval);
+                    Self::reply(tags, tag_results, reply);
+                }
+                ProducerCommand::UpdateSubscription(settings) => self.update_subscription(&settings),
+                ProducerCommand::Shutdown => {
+                    if let Err(e) = self.opcua_client.delete_subscriptions() {
+                        error!("Failed to delete subscriptions: {}", e);
+                    }
+                    break;
+                }
             }
-            Err(e) => {
-                error!("Error browsing: {}", e);
-                return Ok(());
+        }
+
+        Ok(())
+    }
+
+    fn reply(tags: Vec<String>, tag_results: Result<TagResults, StatusCode>, reply: CommandReply) {
+        let tag_results = tag_results.unwrap_or_else(|e| {
+            error!("Failed to apply tag command: {}", e);
+            tags.into_iter().map(|tag| (tag, e)).collect()
+        });
+        if let Some(reply) = reply {
+            let _ = reply.send(tag_results);
+        }
+    }
+
+    /// Subscribes to the tags not monitored yet. Tags that fail are kept for retries when `keep_failed`,
+    /// and otherwise forgotten.
+    async fn add_tags(&mut self, tags: &[TagDefinition], keep_failed: bool) -> TagResults {
+        let mut tag_results = Vec::new();
+        let mut new_tags = Vec::new();
+        for tag in tags {
+            if self.opcua_client.is_monitored(&tag.name) {
+                tag_results.push((tag.name.clone(), StatusCode::Good));
+                continue;
             }
-        };
+            new_tags.push(tag.name.clone());
+            self.definitions.insert(tag.name.clone(), tag.clone());
+        }
+        if new_tags.is_empty() {
+            return tag_results;
+        }
+
+        let matched_tags = self.opcua_client.resolve_tags(&new_tags);
+        info!("Matched tags count: {}", matched_tags.len());
+        let matched_tags: Vec<(TagNode, f64)> = matched_tags
+            .into_iter()
+            .map(|tag| {
+                let sampling_interval = self.sampling_interval_for(&tag.name);
+                (tag, sampling_interval)
+            })
+            .collect();
 
         let batch_size = 1000;
-        let total_tags = matched_tags.len();
-        let mut start = 0;
-        while start < total_tags {
-            let end = (start + batch_size).min(total_tags);
-            let tag_batch = matched_tags[start..end].to_vec();
-            if let Err(err) = self.opcua_client.subscribe_tags(tag_batch.clone(), 2).await {
-                error!("Failed to subscribe to variables: {}", err);
+        for tag_batch in matched_tags.chunks(batch_size) {
+            match self.opcua_client.subscribe_tags(tag_batch.to_vec()).await {
+                Ok(batch_results) => tag_results.extend(batch_results),
+                Err(err) => {
+                    error!("Failed to subscribe to variables: {}", err);
+                    tag_results.extend(tag_batch.iter().map(|(tag, _)| (tag.name.clone(), err)));
+                }
+            }
+        }
+
+        let resolved: HashSet<String> = tag_results.iter().map(|(tag, _)| tag.clone()).collect();
+        for tag in new_tags {
+            if !resolved.contains(&tag) {
+                tag_results.push((tag.clone(), StatusCode::BadNodeIdUnknown));
+            }
+            if self.opcua_client.is_monitored(&tag) {
+                self.failed.remove(&tag);
+            } else if keep_failed {
+                self.failed.insert(tag);
             } else {
-                //info!("Subscribed");
+                // Whoever asked for the tag learns it failed, so it keeps no definition here.
+                self.definitions.remove(&tag);
             }
-            start = end;
         }
-        if let Some(session) = self.opcua_client.session.clone() {
-            tokio::task::spawn_blocking(move || {
-                Session::run(session);
-            });
-        } else {
-            error!("Session is not available.");
+        if !self.failed.is_empty() && self.retry_at.is_none() {
+            info!("Retrying {} tags that could not be subscribed in {:?}", self.failed.len(), self.retry_delay);
+            self.retry_at = Some(Instant::now() + self.retry_delay);
         }
+        tag_results
+    }
 
-        Ok(())
+    async fn retry_failed(&mut self) {
+        self.retry_at = None;
+        let tags: Vec<TagDefinition> = self
+            .failed
+            .drain()
+            .filter_map(|tag| self.definitions.get(&tag).cloned())
+            .collect();
+        if tags.is_empty() {
+            return;
+        }
+        let attempted = tags.len();
+        self.retry_delay = (self.retry_delay * 2).min(MAX_RETRY_DELAY);
+        self.add_tags(&tags, true).await;
+        info!("Subscribed to {} of {} retried tags", attempted - self.failed.len(), attempted);
+        if self.failed.is_empty() {
+            self.retry_delay = MIN_RETRY_DELAY;
+        }
+    }
+
+    fn update_tags(&mut self, tags: Vec<TagDefinition>) {
+        for tag in tags {
+            let previous = self.definitions.insert(tag.name.clone(), tag.clone());
+            let previous_interval = previous.and_then(|previous| previous.sampling_interval);
+            if tag.sampling_interval == previous_interval {
+                continue;
+            }
+            let sampling_interval = tag.sampling_interval.unwrap_or(CONFIG.current().get_subscription().default_sampling_interval_ms);
+            if let Err(e) = self.opcua_client.set_sampling_interval(std::slice::from_ref(&tag.name), sampling_interval) {
+                error!("Failed to update sampling interval of {}: {}", tag.name, e);
+            }
+        }
+    }
+
+    /// Tags C2 gives no sampling interval follow the configured default, so they are resampled too.
+    fn update_subscription(&mut self, settings: &SubscriptionConfig) {
+        if let Err(e) = self.opcua_client.modify_subscriptions(settings) {
+            error!("Failed to modify subscriptions: {}", e);
+        }
+        let default_sampled: Vec<String> = self
+            .definitions
+            .values()
+            .filter(|definition| definition.sampling_interval.is_none() && self.opcua_client.is_monitored(&definition.name))
+            .map(|definition| definition.name.clone())
+            .collect();
+        if default_sampled.is_empty() {
+            return;
+        }
+        if let Err(e) = self.opcua_client.set_sampling_interval(&default_sampled, settings.default_sampling_interval_ms) {
+            error!("Failed to update the default sampling interval: {}", e);
+        }
+    }
+
+    fn sampling_interval_for(&self, tag_name: &str) -> f64 {
+        self.definitions
+            .get(tag_name)
+            .and_then(|definition| definition.sampling_interval)
+            .unwrap_or(CONFIG.current().get_subscription().default_sampling_interval_ms)
     }
 }
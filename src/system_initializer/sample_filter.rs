@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use lazy_static::lazy_static;
+use log::info;
+use crate::config::configuration::{Compression, CompressionAlgorithm, Deadband, FilterGroup, CONFIG};
+use crate::system_initializer::tag_definition::TAG_DEFINITIONS;
+use crate::telemetry::metrics::METRICS;
+
+/// A value of a tag as the client handles it, with the times it was taken on the way that stay in the
+/// client. Only `t` and `v` go into the encoded `HistoricalValue`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sample {
+    pub t: i64,
+    pub v: f64,
+    pub server_t: i64,
+    pub received_t: i64,
+}
+
+/// The values of one tag a compression algorithm kept or still holds.
+#[derive(Default)]
+struct Compressor {
+    // Last value sent, and the one before it for the backslope.
+    archived: Option<Sample>,
+    previous_archived: Option<Sample>,
+    // Latest value, sent only if a later one shows it is needed.
+    held: Option<Sample>,
+    // Swinging door: the narrowest slopes from the archived value seen since.
+    upper_slope: f64,
+    lower_slope: f64,
+}
+
+impl Compressor {
+    /// Takes the next value and returns those to send, with whether a held value was dropped for it.
+    fn push(&mut self, compression: &Compression, value: Sample) -> (Vec<Sample>, bool) {
+        let Some(archived) = self.archived.clone() else {
+            self.archive(value.clone());
+            return (vec![value], false);
+        };
+        let latest_t = self.held.as_ref().map_or(archived.t, |held| held.t);
+        if value.t <= latest_t {
+            // Out of order: start over from this value rather than draw lines backwards in time.
+            let mut sent: Vec<Sample> = self.held.take().into_iter().collect();
+            sent.push(value.clone());
+            self.archive(value);
+            return (sent, false);
+        }
+
+        let overdue = compression.max_interval_ms > 0 && value.t - archived.t >= compression.max_interval_ms as i64;
+        let needed = overdue
+            || match compression.algorithm {
+                CompressionAlgorithm::SwingingDoor => self.door_closes(compression.deviation, &archived, &value),
+                CompressionAlgorithm::BoxcarBackslope => self.leaves_boxcar_and_backslope(compression.deviation, &archived, &value),
+            };
+        if !needed {
+            let dropped = self.held.replace(value).is_some();
+            return (Vec::new(), dropped);
+        }
+        match self.held.take() {
+            Some(held) => {
+                self.archive(held.clone());
+                if compression.algorithm == CompressionAlgorithm::SwingingDoor {
+                    self.door_closes(compression.deviation, &held, &value);
+                }
+                self.held = Some(value);
+                (vec![held], false)
+            }
+            None => {
+                self.archive(value.clone());
+                (vec![value], false)
+            }
+        }
+    }
+
+    fn archive(&mut self, value: Sample) {
+        self.previous_archived = self.archived.replace(value);
+        self.upper_slope = f64::INFINITY;
+        self.lower_slope = f64::NEG_INFINITY;
+    }
+
+    /// Narrows the door to the new value; it closes once no line from the archived value stays
+    /// within `deviation` of every value since.
+    fn door_closes(&mut self, deviation: f64, archived: &Sample, value: &Sample) -> bool {
+        let elapsed = (value.t - archived.t) as f64;
+        if elapsed <= 0.0 {
+            return false;
+        }
+        self.upper_slope = self.upper_slope.min((value.v + deviation - archived.v) / elapsed);
+        self.lower_slope = self.lower_slope.max((value.v - deviation - archived.v) / elapsed);
+        self.lower_slope > self.upper_slope
+    }
+
+    /// Needed when the value is more than `deviation` away from the archived one and from the line
+    /// through the last two archived values.
+    fn leaves_boxcar_and_backslope(&self, deviation: f64, archived: &Sample, value: &Sample) -> bool {
+        let leaves_boxcar = (value.v - archived.v).abs() > deviation;
+        let leaves_backslope = match &self.previous_archived {
+            Some(previous) if archived.t > previous.t => {
+                let slope = (archived.v - previous.v) / (archived.t - previous.t) as f64;
+                let projected = archived.v + slope * (value.t - archived.t) as f64;
+                (value.v - projected).abs() > deviation
+            }
+            _ => true,
+        };
+        leaves_boxcar && leaves_backslope
+    }
+}
+
+struct TagFilter {
+    // Index into `CONFIG.current().get_filters()`; none when no group matches the tag.
+    group: Option<usize>,
+    // Last value that passed the interval and deadband checks.
+    last_passed: Option<Sample>,
+    compressor: Compressor,
+}
+
+/// The client-side filter stage between the subscription callbacks and the queue.
+pub struct SampleFilter {
+    tags: Mutex<HashMap<String, TagFilter>>,
+}
+
+impl SampleFilter {
+    fn new() -> Self {
+        SampleFilter { tags: Mutex::new(HashMap::new()) }
+    }
+
+    /// Returns the values of the tag to queue: none, this one, or one held back earlier.
+    pub fn filter(&self, tag: &str, value: Sample, good: bool) -> Vec<Sample> {
+        let config = CONFIG.current();
+        let groups = config.get_filters();
+        if !good || groups.is_empty() {
+            return vec![value];
+        }
+        let mut tags = self.tags.lock().unwrap();
+        if !tags.contains_key(tag) {
+            let group = Self::group_of(tag, groups);
+            tags.insert(tag.to_string(), TagFilter { group, last_passed: None, compressor: Compressor::default() });
+        }
+        let Some(state) = tags.get_mut(tag) else {
+            return vec![value];
+        };
+        let Some(group) = state.group.and_then(|group| groups.get(group)) else {
+            return vec![value];
+        };
+
+        if let Some(reason) = Self::suppressed_by(group, state.last_passed.as_ref(), &value) {
+            METRICS.suppressed_samples.inc(&[&group.name, reason]);
+            return Vec::new();
+        }
+        state.last_passed = Some(value.clone());
+        let Some(compression) = &group.compression else {
+            return vec![value];
+        };
+        let (sent, dropped) = state.compressor.push(compression, value);
+        if dropped {
+            METRICS.suppressed_samples.inc(&[&group.name, "compression"]);
+        }
+        sent
+    }
+
+    /// Matches every tag to the groups again; called when the filters change.
+    pub fn reset(&self) {
+        let mut tags = self.tags.lock().unwrap();
+        let held = tags.values().filter(|state| state.compressor.held.is_some()).count();
+        if held > 0 {
+            info!("Filters changed, dropping {} values held for compression", held);
+        }
+        tags.clear();
+    }
+
+    pub fn forget(&self, tags: &[String]) {
+        let mut tag_filters = self.tags.lock().unwrap();
+        for tag in tags {
+            tag_filters.remove(tag);
+        }
+    }
+
+    fn group_of(tag: &str, groups: &[FilterGroup]) -> Option<usize> {
+        groups.iter().position(|group| matches_group(&group.tags, &group.assets, tag))
+    }
+
+    fn suppressed_by(group: &FilterGroup, last: Option<&Sample>, value: &Sample) -> Option<&'static str> {
+        let last = last?;
+        if group.min_interval_ms > 0 && value.t - last.t < group.min_interval_ms as i64 {
+            return Some("min_interval");
+        }
+        let change = (value.v - last.v).abs();
+        match group.deadband {
+            Some(Deadband::Absolute { value: limit }) if change <= limit => Some("deadband"),
+            Some(Deadband::Percent { value: percent }) if change <= last.v.abs() * percent / 100.0 => Some("deadband"),
+            _ => None,
+        }
+    }
+}
+
+/// Whether the tag's name matches one of `tags` or its asset one of `assets`; both empty match every tag.
+pub fn matches_group(tags: &[String], assets: &[String], tag: &str) -> bool {
+    if tags.is_empty() && assets.is_empty() {
+        return true;
+    }
+    let asset = TAG_DEFINITIONS.get(tag).and_then(|definition| definition.asset_name);
+    tags.iter().any(|pattern| matches_pattern(pattern, tag))
+        || asset.as_deref().is_some_and(|asset| assets.iter().any(|pattern| matches_pattern(pattern, asset)))
+}
+
+/// Glob match where `*` matches any text, including none, and `?` exactly one character.
+fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was, and the text position it currently stands for up to.
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+lazy_static! {
+    pub static ref SAMPLE_FILTER: SampleFilter = SampleFilter::new();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::system_initializer::test_support::group;
+
+    fn value(t: i64, v: f64) -> Sample {
+        Sample { t, v, ..Default::default() }
+    }
+
+    fn compression(algorithm: CompressionAlgorithm, deviation: f64) -> Compression {
+        Compression { algorithm, deviation, max_interval_ms: 0 }
+    }
+
+    /// Pushes `(t, v)` pairs and returns the times of the values sent after each.
+    fn run(compression: &Compression, values: &[(i64, f64)]) -> Vec<Vec<i64>> {
+        let mut compressor = Compressor::default();
+        values
+            .iter()
+            .map(|(t, v)| compressor.push(compression, value(*t, *v)).0.iter().map(|sent| sent.t).collect())
+            .collect()
+    }
+
+    #[test]
+    fn swinging_door_keeps_the_corners_of_the_signal() {
+        let compression = compression(CompressionAlgorithm::SwingingDoor, 1.0);
+        let sent = run(&compression, &[(0, 0.0), (1, 0.0), (2, 0.0), (3, 0.0), (4, 10.0), (5, 20.0), (6, 20.0)]);
+        // Flat until 3, a ramp to 5 on which 4 lies, flat again.
+        assert_eq!(sent, vec![vec![0], vec![], vec![], vec![], vec![3], vec![], vec![5]]);
+    }
+
+    #[test]
+    fn swinging_door_counts_the_held_values_it_drops() {
+        let compression = compression(CompressionAlgorithm::SwingingDoor, 1.0);
+        let mut compressor = Compressor::default();
+        assert_eq!(compressor.push(&compression, value(0, 0.0)), (vec![value(0, 0.0)], false));
+        assert_eq!(compressor.push(&compression, value(1, 0.5)), (vec![], false));
+        assert_eq!(compressor.push(&compression, value(2, 0.2)), (vec![], true));
+    }
+
+    #[test]
+    fn boxcar_backslope_drops_values_near_the_archived_value_or_its_slope() {
+        let compression = compression(CompressionAlgorithm::BoxcarBackslope, 1.0);
+        let sent = run(&compression, &[(0, 0.0), (1, 0.5), (2, 5.0), (3, 6.0), (4, 13.0), (5, 5.5)]);
+        // 1 stays within the box; 4 lies on the slope through 1 and 2, and 5 within the box around 2.
+        assert_eq!(sent, vec![vec![0], vec![], vec![1], vec![2], vec![], vec![]]);
+    }
+
+    #[test]
+    fn max_interval_sends_the_held_value() {
+        let compression = Compression { max_interval_ms: 10, ..compression(CompressionAlgorithm::BoxcarBackslope, 1.0) };
+        let sent = run(&compression, &[(0, 0.0), (5, 0.1), (10, 0.2), (15, 0.3)]);
+        assert_eq!(sent, vec![vec![0], vec![], vec![5], vec![10]]);
+    }
+
+    #[test]
+    fn out_of_order_values_restart_compression() {
+        let compression = compression(CompressionAlgorithm::SwingingDoor, 1.0);
+        let sent = run(&compression, &[(10, 0.0), (20, 0.1), (15, 0.2), (16, 0.2)]);
+        assert_eq!(sent, vec![vec![10], vec![], vec![20, 15], vec![]]);
+    }
+
+    #[test]
+    fn deadband_and_min_interval_suppress_values() {
+        let group: FilterGroup = group(json!({
+            "deadband": {"type": "percent", "value": 10.0},
+            "min_interval_ms": 1000,
+        }));
+        let last = value(0, 50.0);
+        assert_eq!(SampleFilter::suppressed_by(&group, None, &value(0, 50.0)), None);
+        assert_eq!(SampleFilter::suppressed_by(&group, Some(&last), &value(999, 80.0)), Some("min_interval"));
+        assert_eq!(SampleFilter::suppressed_by(&group, Some(&last), &value(1000, 55.0)), Some("deadband"));
+        assert_eq!(SampleFilter::suppressed_by(&group, Some(&last), &value(1000, 55.1)), None);
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(matches_pattern("Line1.*", "Line1.Temp"));
+        assert!(matches_pattern("*.Temp", "Area.Line1.Temp"));
+        assert!(matches_pattern("T?mp", "Temp"));
+        assert!(!matches_pattern("T?mp", "Tmp"));
+        assert!(matches_pattern("*a*b", "xaxxb"));
+        assert!(!matches_pattern("*a*b", "xaxxbc"));
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("**Temp", "Temp"));
+        assert!(!matches_pattern("Temp", "Temperature"));
+        assert!(!matches_pattern("", "Temp"));
+        assert!(matches_pattern("Flow*", "Flow"));
+    }
+
+    #[test]
+    fn empty_groups_match_every_tag() {
+        assert!(matches_group(&[], &[], "Anything"));
+        assert!(matches_group(&["Line?.*".to_string()], &[], "Line2.Flow"));
+        assert!(!matches_group(&["Line?.*".to_string()], &[], "Line12.Flow"));
+    }
+}
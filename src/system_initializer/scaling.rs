@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use lazy_static::lazy_static;
+use log::warn;
+use crate::config::configuration::{ScalingFunction, ScalingGroup, CONFIG};
+use crate::system_initializer::sample_filter::matches_group;
+use crate::system_initializer::tag_definition::TAG_DEFINITIONS;
+use crate::system_initializer::units::Conversion;
+
+/// The EURange and EngineeringUnits properties of a tag's variable on the server.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EuProperties {
+    pub range: Option<(f64, f64)>,
+    pub units: Option<String>,
+}
+
+/// How one tag's values are scaled, worked out from its group, C2's metadata and the server's properties.
+#[derive(Debug, Clone, Default)]
+struct TagScale {
+    function: ScalingFunction,
+    // Raw and engineering ranges, mapped onto each other when both are known.
+    raw: Option<(f64, f64)>,
+    eu: Option<(f64, f64)>,
+    factor: f64,
+    offset: f64,
+    clamp: bool,
+    conversion: Option<Conversion>,
+    units: Option<String>,
+}
+
+impl TagScale {
+    fn resolve(tag: &str, group: Option<&ScalingGroup>, server: Option<&EuProperties>) -> TagScale {
+        let definition = TAG_DEFINITIONS.get(tag);
+        let server = server.cloned().unwrap_or_default();
+        let mut scale = TagScale {
+            factor: definition.as_ref().and_then(|definition| definition.scale_factor).unwrap_or(1.0),
+            offset: definition.as_ref().and_then(|definition| definition.scale_offset).unwrap_or(0.0),
+            eu: server.range,
+            units: definition.and_then(|definition| definition.units).filter(|units| !units.trim().is_empty()).or(server.units),
+            ..Default::default()
+        };
+        let Some(group) = group else {
+            return scale;
+        };
+        scale.function = group.function;
+        scale.raw = group.raw_low.zip(group.raw_high);
+        scale.eu = group.eu_low.zip(group.eu_high).or(scale.eu);
+        scale.factor = group.factor.unwrap_or(scale.factor);
+        scale.offset = group.offset.unwrap_or(scale.offset);
+        scale.clamp = group.clamp;
+        scale.units = group.units.clone().or(scale.units);
+        if let Some(convert_to) = &group.convert_to {
+            match scale.units.as_deref().map(|units| Conversion::between(units, convert_to)) {
+                Some(Ok(conversion)) => {
+                    scale.conversion = Some(conversion);
+                    scale.units = Some(convert_to.clone());
+                }
+                Some(Err(e)) => warn!("Not converting {} to {}: {}", tag, convert_to, e),
+                None => warn!("Not converting {} to {}: its units are not known", tag, convert_to),
+            }
+        }
+        scale
+    }
+
+    fn apply(&self, raw: f64) -> f64 {
+        let mut value = match (self.raw, self.eu) {
+            (Some((raw_low, raw_high)), Some((eu_low, eu_high))) => {
+                let share = (raw - raw_low) / (raw_high - raw_low);
+                eu_low + self.shape(share) * (eu_high - eu_low)
+            }
+            _ => self.shape(raw) * self.factor + self.offset,
+        };
+        if let Some((eu_low, eu_high)) = self.eu.filter(|_| self.clamp) {
+            value = value.clamp(eu_low.min(eu_high), eu_low.max(eu_high));
+        }
+        match self.conversion {
+            Some(conversion) => conversion.apply(value),
+            None => value,
+        }
+    }
+
+    fn shape(&self, value: f64) -> f64 {
+        match self.function {
+            ScalingFunction::Linear => value,
+            // Below zero is noise around no flow.
+            ScalingFunction::SquareRoot => value.max(0.0).sqrt(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    server: HashMap<String, EuProperties>,
+    // Worked out on a tag's first value, and again when its definition or properties change.
+    resolved: HashMap<String, TagScale>,
+    definitions_version: u64,
+}
+
+/// Scales the raw values of collected tags to engineering values and knows their units.
+pub struct Scaling {
+    state: Mutex<State>,
+}
+
+impl Scaling {
+    fn new() -> Self {
+        Scaling { state: Mutex::new(State::default()) }
+    }
+
+    pub fn scale(&self, tag: &str, raw: f64) -> f64 {
+        self.with_scale(tag, |scale| scale.apply(raw))
+    }
+
+    /// Units of the tag's values after scaling, when known.
+    pub fn units(&self, tag: &str) -> Option<String> {
+        self.with_scale(tag, |scale| scale.units.clone())
+    }
+
+    /// Records the properties the server reported for a subscribed tag.
+    pub fn set_server_properties(&self, tag: &str, properties: EuProperties) {
+        let mut state = self.state.lock().unwrap();
+        if state.server.get(tag) != Some(&properties) {
+            state.resolved.remove(tag);
+            state.server.insert(tag.to_string(), properties);
+        }
+    }
+
+    /// Matches every tag to the groups again; called when the scaling groups change.
+    pub fn reset(&self) {
+        self.state.lock().unwrap().resolved.clear();
+    }
+
+    pub fn forget(&self, tags: &[String]) {
+        let mut state = self.state.lock().unwrap();
+        for tag in tags {
+            state.server.remove(tag);
+            state.resolved.remove(tag);
+        }
+    }
+
+    fn with_scale<T>(&self, tag: &str, f: impl FnOnce(&TagScale) -> T) -> T {
+        let mut state = self.state.lock().unwrap();
+        let version = TAG_DEFINITIONS.version();
+        if state.definitions_version != version {
+            state.resolved.clear();
+            state.definitions_version = version;
+        }
+        if let Some(scale) = state.resolved.get(tag) {
+            return f(scale);
+        }
+        let config = CONFIG.current();
+        let group = config.get_scaling().iter().find(|group| matches_group(&group.tags, &group.assets, tag));
+        let scale = TagScale::resolve(tag, group, state.server.get(tag));
+        let result = f(&scale);
+        state.resolved.insert(tag.to_string(), scale);
+        result
+    }
+}
+
+lazy_static! {
+    pub static ref SCALING: Scaling = Scaling::new();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::system_initializer::test_support::{assert_close, group};
+
+    /// Resolves a tag C2 has no definition for, so only the group and the server decide.
+    fn scale(settings: serde_json::Value, server: Option<EuProperties>) -> TagScale {
+        TagScale::resolve("scaling.test.unknown", Some(&group(settings)), server.as_ref())
+    }
+
+    #[test]
+    fn raw_range_maps_onto_the_engineering_range() {
+        let scale = scale(json!({"raw_low": 0.0, "raw_high": 27648.0, "eu_low": 0.0, "eu_high": 10.0}), None);
+        assert_close(scale.apply(0.0), 0.0);
+        assert_close(scale.apply(13824.0), 5.0);
+        assert_close(scale.apply(27648.0), 10.0);
+        assert_close(scale.apply(30000.0), 30000.0 / 2764.8);
+    }
+
+    #[test]
+    fn server_range_stands_in_for_a_missing_engineering_range() {
+        let server = EuProperties { range: Some((0.0, 100.0)), units: Some("kPa".to_string()) };
+        let scale = scale(json!({"raw_low": 0.0, "raw_high": 1000.0}), Some(server));
+        assert_close(scale.apply(500.0), 50.0);
+        assert_eq!(scale.units.as_deref(), Some("kPa"));
+    }
+
+    #[test]
+    fn square_root_extraction() {
+        let scale = scale(json!({"function": "square_root", "raw_low": 4.0, "raw_high": 20.0, "eu_low": 0.0, "eu_high": 100.0}), None);
+        assert_close(scale.apply(4.0), 0.0);
+        assert_close(scale.apply(8.0), 50.0);
+        assert_close(scale.apply(20.0), 100.0);
+        // Below the live zero is no flow rather than NaN.
+        assert_close(scale.apply(3.0), 0.0);
+    }
+
+    #[test]
+    fn clamp_limits_values_to_the_engineering_range() {
+        let settings = json!({"raw_low": 0.0, "raw_high": 27648.0, "eu_low": 10.0, "eu_high": 0.0, "clamp": true});
+        let scale = scale(settings, None);
+        assert_close(scale.apply(13824.0), 5.0);
+        assert_close(scale.apply(30000.0), 0.0);
+        assert_close(scale.apply(-1000.0), 10.0);
+    }
+
+    #[test]
+    fn factor_and_offset_apply_without_ranges() {
+        let scale = scale(json!({"factor": 0.1, "offset": -5.0}), None);
+        assert_close(scale.apply(100.0), 5.0);
+        let unscaled = TagScale::resolve("scaling.test.unknown", None, None);
+        assert_close(unscaled.apply(42.0), 42.0);
+    }
+
+    #[test]
+    fn conversion_follows_scaling_and_renames_the_units() {
+        let scale = scale(json!({"factor": 2.0, "units": "degF", "convert_to": "degC"}), None);
+        assert_close(scale.apply(106.0), 100.0);
+        assert_eq!(scale.units.as_deref(), Some("degC"));
+
+        let mismatched = TagScale::resolve("scaling.test.unknown", Some(&group(json!({"units": "bar", "convert_to": "degC"}))), None);
+        assert_eq!(mismatched.conversion, None);
+        assert_eq!(mismatched.units.as_deref(), Some("bar"));
+    }
+}
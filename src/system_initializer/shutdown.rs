@@ -0,0 +1,108 @@
+use std::sync::Mutex;
+use std::time::Duration;
+use lazy_static::lazy_static;
+use log::info;
+use tokio::sync::watch;
+use tokio::time::{sleep_until, Instant};
+use crate::config::configuration::{RecordFormat, RecorderSinkConfig, CONFIG};
+use crate::sinks::recorder::RecorderSink;
+use crate::sinks::{Batch, Sink, SinkError};
+
+/// Everything queued was delivered.
+pub const EXIT_DELIVERED: i32 = 0;
+/// Records were lost: consumers did not stop in time or the unsent records could not be written.
+pub const EXIT_DATA_LOST: i32 = 1;
+/// Undelivered records were saved to `shutdown.unsent_directory` for `replay`.
+pub const EXIT_UNSENT_SAVED: i32 = 3;
+/// A task kept failing and the supervisor stopped the client; queued records were still delivered.
+pub const EXIT_ESCALATED: i32 = 4;
+
+/// The shutdown deadline shared with the consumers, and the records they could not deliver before it.
+pub struct Shutdown {
+    deadline: watch::Sender<Option<Instant>>,
+    unsent: Mutex<Vec<Vec<u8>>>,
+}
+
+impl Shutdown {
+    fn new() -> Self {
+        Shutdown {
+            deadline: watch::channel(None).0,
+            unsent: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Starts the clock on delivering what is queued.
+    pub fn begin(&self, timeout: Duration) -> Instant {
+        let deadline = Instant::now() + timeout;
+        self.deadline.send_replace(Some(deadline));
+        deadline
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.deadline.borrow().is_some()
+    }
+
+    pub async fn started(&self) {
+        let _ = self.deadline.subscribe().wait_for(Option::is_some).await;
+    }
+
+    pub fn is_past_deadline(&self) -> bool {
+        self.deadline.borrow().is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Resolves at the deadline, so never while the process is running normally.
+    pub async fn deadline_reached(&self) {
+        let mut deadline = self.deadline.subscribe();
+        let deadline = deadline.wait_for(Option::is_some).await.ok().and_then(|deadline| *deadline);
+        match deadline {
+            Some(deadline) => sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    pub fn keep_unsent(&self, records: Vec<Vec<u8>>) {
+        self.unsent.lock().unwrap().extend(records);
+    }
+
+    pub fn take_unsent(&self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut *self.unsent.lock().unwrap())
+    }
+}
+
+lazy_static! {
+    pub static ref SHUTDOWN: Shutdown = Shutdown::new();
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix, with the name of the signal.
+pub async fn signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            return tokio::select! {
+                _ = tokio::signal::ctrl_c() => "SIGINT",
+                _ = terminate.recv() => "SIGTERM",
+            };
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+    "SIGINT"
+}
+
+/// Writes records as a recording of C2 frames, so `replay` can deliver them once C2 is reachable.
+pub async fn save_unsent(records: &[Vec<u8>]) -> Result<(), SinkError> {
+    let config = RecorderSinkConfig {
+        directory: CONFIG.current().get_unsent_directory().to_string(),
+        prefix: "unsent".to_string(),
+        format: RecordFormat::Universal,
+        rotate_secs: 0,
+        rotate_bytes: 0,
+    };
+    let mut recorder = RecorderSink::new(config, 0);
+    for chunk in records.chunks(CONFIG.current().get_batch_size()) {
+        recorder.send(&Batch::new(chunk.to_vec())).await?;
+    }
+    recorder.flush().await?;
+    info!("Saved {} unsent records to {}", records.len(), CONFIG.current().get_unsent_directory());
+    Ok(())
+}
@@ -0,0 +1,146 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::Mutex;
+use futures_util::FutureExt;
+use lazy_static::lazy_static;
+use log::{info, error};
+use serde::Serialize;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
+use crate::config::configuration::CONFIG;
+use crate::system_initializer::shutdown::SHUTDOWN;
+
+/// Longest wait between two restarts, however often a task failed.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Running,
+    /// Failed and waiting out the backoff before it starts again.
+    Restarting,
+    Stopped,
+    /// Failed more often than the restart limit allows; the client is shutting down.
+    Failed,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TaskStatus {
+    pub name: String,
+    pub state: TaskState,
+    pub restarts: usize,
+    pub last_error: Option<String>,
+}
+
+/// Keeps the producers, consumers and background tasks running, restarting each one that fails on its
+/// own. A task that fails more than `supervision.max_restarts` times within `supervision.window_secs` is
+/// escalated: the supervisor gives up on it and asks for the client to shut down.
+pub struct Supervisor {
+    tasks: Mutex<Vec<TaskStatus>>,
+    escalated: watch::Sender<Option<String>>,
+}
+
+impl Supervisor {
+    fn new() -> Self {
+        Supervisor {
+            tasks: Mutex::new(Vec::new()),
+            escalated: watch::channel(None).0,
+        }
+    }
+
+    /// Runs the future `start` returns until it ends, starting it again after an error or a panic.
+    /// A task that returns `Ok` or ends during shutdown is not restarted.
+    pub fn supervise<F, Fut>(&'static self, name: String, start: F) -> JoinHandle<()>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let index = {
+            let mut tasks = self.tasks.lock().unwrap();
+            tasks.push(TaskStatus { name: name.clone(), state: TaskState::Running, restarts: 0, last_error: None });
+            tasks.len() - 1
+        };
+        tokio::spawn(async move {
+            let mut recent_failures: VecDeque<Instant> = VecDeque::new();
+            loop {
+                let failure = match AssertUnwindSafe(start()).catch_unwind().await {
+                    Ok(Ok(())) => None,
+                    Ok(Err(e)) => Some(e),
+                    Err(panic) => Some(format!("panicked: {}", panic_message(&*panic))),
+                };
+                let Some(failure) = failure else {
+                    self.update(index, |task| task.state = TaskState::Stopped);
+                    return;
+                };
+                error!("Task {} failed: {}", name, failure);
+                self.update(index, |task| task.last_error = Some(failure.clone()));
+                if SHUTDOWN.is_started() {
+                    self.update(index, |task| task.state = TaskState::Stopped);
+                    return;
+                }
+
+                let config = CONFIG.current();
+                let settings = config.get_supervision();
+                let window = Duration::from_secs(settings.window_secs);
+                recent_failures.retain(|failed_at| failed_at.elapsed() < window);
+                if recent_failures.len() >= settings.max_restarts {
+                    error!("Task {} failed {} times within {:?}, giving up on it", name, recent_failures.len() + 1, window);
+                    self.update(index, |task| task.state = TaskState::Failed);
+                    self.escalated.send_if_modified(|escalated| escalated.replace(name.clone()).is_none());
+                    return;
+                }
+                recent_failures.push_back(Instant::now());
+
+                let backoff = Duration::from_millis(settings.backoff_ms)
+                    .saturating_mul(1 << (recent_failures.len() - 1).min(16))
+                    .min(MAX_BACKOFF);
+                self.update(index, |task| task.state = TaskState::Restarting);
+                tokio::select! {
+                    _ = sleep(backoff) => {}
+                    _ = SHUTDOWN.started() => {
+                        self.update(index, |task| task.state = TaskState::Stopped);
+                        return;
+                    }
+                }
+                info!("Restarting task {} after {:?}", name, backoff);
+                self.update(index, |task| {
+                    task.state = TaskState::Running;
+                    task.restarts += 1;
+                });
+            }
+        })
+    }
+
+    pub fn statuses(&self) -> Vec<TaskStatus> {
+        self.tasks.lock().unwrap().clone()
+    }
+
+    /// Resolves with the name of the first task that exhausted its restarts.
+    pub async fn escalation(&self) -> String {
+        let mut escalated = self.escalated.subscribe();
+        let name = escalated.wait_for(Option::is_some).await.ok().and_then(|name| name.clone());
+        match name {
+            Some(name) => name,
+            None => std::future::pending().await,
+        }
+    }
+
+    fn update(&self, index: usize, change: impl FnOnce(&mut TaskStatus)) {
+        change(&mut self.tasks.lock().unwrap()[index]);
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause")
+}
+
+lazy_static! {
+    pub static ref SUPERVISOR: Supervisor = Supervisor::new();
+}
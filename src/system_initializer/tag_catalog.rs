@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use log::{info, error};
+use opcua::types::NodeId;
+use crate::clients::session_pool::SessionPool;
+use crate::config::configuration::{CONFIG, OpcConfig};
+use crate::system_initializer::tag_definition::TagDefinition;
+use crate::system_initializer::tag_partitioner::TagPartitioner;
+use crate::telemetry::metrics::METRICS;
+
+struct SourceEntry {
+    config: OpcConfig,
+    session_pool: Arc<SessionPool>,
+    partitioner: TagPartitioner,
+    first_producer: usize,
+}
+
+/// Routes tags to the OPC UA source that serves them and, within it, to one of its producers.
+/// Producer indexes are global: each source owns a contiguous range of them.
+pub struct TagCatalog {
+    sources: Vec<SourceEntry>,
+}
+
+impl TagCatalog {
+    pub fn connect() -> Result<Self, Box<dyn std::error::Error>> {
+        let config = CONFIG.current();
+        let configs = config.get_opc_sources();
+        if configs.is_empty() {
+            return Err("No OPC UA source configured".into());
+        }
+        let mut sources = Vec::new();
+        for config in configs {
+            let session_pool = Arc::new(SessionPool::connect(config)?);
+            SessionPool::watch(&session_pool);
+            METRICS.watch_session_pool(&session_pool);
+            sources.push(SourceEntry {
+                config: config.clone(),
+                session_pool,
+                partitioner: TagPartitioner::new(CONFIG.current().get_partition_strategy(), 0),
+                first_producer: 0,
+            });
+        }
+        Ok(TagCatalog { sources })
+    }
+
+    /// Splits the initial tag list across sources and their producers, returning one entry per producer.
+    pub fn partition(&mut self, tags: Vec<TagDefinition>) -> Vec<(Arc<SessionPool>, Vec<TagDefinition>)> {
+        let mut by_source: Vec<Vec<TagDefinition>> = vec![Vec::new(); self.sources.len()];
+        for tag in tags {
+            let source = self.source_for(&tag);
+            by_source[source].push(tag);
+        }
+
+        let mut plans = Vec::new();
+        for (source, tags) in self.sources.iter_mut().zip(by_source) {
+            // The configured count even for few tags at start, as tags added later spread over them and
+            // the hash ring of consistent hashing depends on it.
+            let num_producers = source
+                .config
+                .num_producers
+                .unwrap_or_else(|| CONFIG.current().get_num_producers())
+                .max(1);
+            info!("Partitioning {} tags of {} across {} producers", tags.len(), source.config.name, num_producers);
+            source.partitioner = TagPartitioner::new(CONFIG.current().get_partition_strategy(), num_producers);
+            source.first_producer = plans.len();
+            for producer_tags in source.partitioner.partition(tags) {
+                plans.push((source.session_pool.clone(), producer_tags));
+            }
+        }
+        plans
+    }
+
+    /// Assigns tags added after start-up; tags that already have an owner stay with it.
+    pub fn assign(&mut self, tags: Vec<TagDefinition>) -> HashMap<usize, Vec<TagDefinition>> {
+        let mut by_source: HashMap<usize, Vec<TagDefinition>> = HashMap::new();
+        for tag in tags {
+            let source = self.owning_source(&tag.name).unwrap_or_else(|| self.source_for(&tag));
+            by_source.entry(source).or_default().push(tag);
+        }
+
+        let mut additions: HashMap<usize, Vec<TagDefinition>> = HashMap::new();
+        for (source, tags) in by_source {
+            let source = &mut self.sources[source];
+            for (producer, tags) in source.partitioner.assign(tags) {
+                additions.entry(source.first_producer + producer).or_default().extend(tags);
+            }
+        }
+        additions
+    }
+
+    /// Groups tags by owning producer, returning the tags no producer owns separately.
+    pub fn owned(&self, tags: Vec<String>) -> (HashMap<usize, Vec<String>>, Vec<String>) {
+        let mut owned: HashMap<usize, Vec<String>> = HashMap::new();
+        let mut unknown = Vec::new();
+        for tag in tags {
+            match self.owner(&tag) {
+                Some(producer) => owned.entry(producer).or_default().push(tag),
+                None => unknown.push(tag),
+            }
+        }
+        (owned, unknown)
+    }
+
+    pub fn owner(&self, tag: &str) -> Option<usize> {
+        self.sources
+            .iter()
+            .find_map(|source| source.partitioner.owner(tag).map(|producer| source.first_producer + producer))
+    }
+
+    /// Like `owned`, but also forgets the assignments so the tags can be placed afresh later.
+    pub fn release(&mut self, tags: Vec<String>) -> (HashMap<usize, Vec<String>>, Vec<String>) {
+        let mut by_source: HashMap<usize, Vec<String>> = HashMap::new();
+        let mut unknown = Vec::new();
+        for tag in tags {
+            match self.owning_source(&tag) {
+                Some(source) => by_source.entry(source).or_default().push(tag),
+                None => unknown.push(tag),
+            }
+        }
+
+        let mut released: HashMap<usize, Vec<String>> = HashMap::new();
+        for (source, tags) in by_source {
+            let source = &mut self.sources[source];
+            let (owned, _) = source.partitioner.release(tags);
+            for (producer, tags) in owned {
+                released.entry(source.first_producer + producer).or_default().extend(tags);
+            }
+        }
+        (released, unknown)
+    }
+
+    /// Where each tag would be collected from: the source it routes to and its nodes there, if any.
+    pub fn resolve(&self, tags: &[TagDefinition]) -> Vec<(TagDefinition, String, Vec<NodeId>)> {
+        tags.iter()
+            .map(|tag| {
+                let source = &self.sources[self.source_for(tag)];
+                let nodes = source.session_pool.resolve(std::slice::from_ref(&tag.name));
+                let node_ids = nodes.into_iter().map(|node| node.node_id).collect();
+                (tag.clone(), source.config.name.clone(), node_ids)
+            })
+            .collect()
+    }
+
+    /// Closes the sessions of every source once its producers have deleted their subscriptions.
+    pub fn disconnect(&self) {
+        tokio::task::block_in_place(|| {
+            for source in &self.sources {
+                source.session_pool.disconnect();
+            }
+        });
+    }
+
+    fn owning_source(&self, tag: &str) -> Option<usize> {
+        self.sources.iter().position(|source| source.partitioner.owner(tag).is_some())
+    }
+
+    /// The source C2 pinned the tag to, else the first one whose address space has it, else the first source.
+    fn source_for(&self, tag: &TagDefinition) -> usize {
+        if let Some(name) = &tag.source {
+            match self.sources.iter().position(|source| &source.config.name == name) {
+                Some(source) => return source,
+                None => error!("Tag {} names unknown OPC UA source {}", tag.name, name),
+            }
+        }
+        self.sources
+            .iter()
+            .position(|source| source.session_pool.contains(&tag.name))
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+use crate::system_initializer::producer::TagResults;
+
+pub const TAG_CONTROL_RESULT_MSG_TYPE: &str = "TAG_CONTROL_RESULT";
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ControlAction {
+    AddTags,
+    RemoveTags,
+    SetSampling,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ControlRequest {
+    #[serde(rename = "requestId")]
+    pub request_id: String,
+    pub action: ControlAction,
+    pub tags: Vec<String>,
+    #[serde(rename = "samplingInterval", default)]
+    pub sampling_interval: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ControlReply {
+    msg_type: &'static str,
+    #[serde(rename = "requestId")]
+    request_id: String,
+    results: Vec<TagResultReply>,
+}
+
+#[derive(Serialize, Debug)]
+struct TagResultReply {
+    #[serde(rename = "tagName")]
+    tag_name: String,
+    #[serde(rename = "statusCode")]
+    status_code: String,
+    success: bool,
+}
+
+impl ControlReply {
+    pub fn new(request_id: String, tag_results: TagResults) -> Self {
+        let results = tag_results
+            .into_iter()
+            .map(|(tag_name, status)| TagResultReply {
+                tag_name,
+                status_code: status.name().to_string(),
+                success: status.is_good(),
+            })
+            .collect();
+        ControlReply {
+            msg_type: TAG_CONTROL_RESULT_MSG_TYPE,
+            request_id,
+            results,
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use log::error;
+use lazy_static::lazy_static;
+
+/// A tag as described by C2, carrying the metadata that drives how it is collected.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct TagDefinition {
+    #[serde(default)]
+    pub id: Option<i64>,
+    #[serde(rename = "tagName")]
+    pub name: String,
+    #[serde(rename = "assetName", default)]
+    pub asset_name: Option<String>,
+    #[serde(default)]
+    pub units: Option<String>,
+    #[serde(rename = "dataType", default)]
+    pub data_type: Option<String>,
+    #[serde(rename = "scaleFactor", default)]
+    pub scale_factor: Option<f64>,
+    #[serde(rename = "scaleOffset", default)]
+    pub scale_offset: Option<f64>,
+    /// Requested OPC UA sampling interval in milliseconds; the subscription's publishing interval when absent.
+    #[serde(rename = "samplingInterval", default)]
+    pub sampling_interval: Option<f64>,
+    /// How often C2 expects the value to change, in milliseconds.
+    #[serde(rename = "expectedUpdatePeriod", default)]
+    pub expected_update_period: Option<f64>,
+    /// Name of the configured OPC UA source that serves this tag, when C2 pins it to one.
+    #[serde(rename = "opcSource", default)]
+    pub source: Option<String>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl TagDefinition {
+    pub fn from_name(name: String) -> Self {
+        TagDefinition {
+            id: None,
+            name,
+            asset_name: None,
+            units: None,
+            data_type: None,
+            scale_factor: None,
+            scale_offset: None,
+            sampling_interval: None,
+            expected_update_period: None,
+            source: None,
+            enabled: true,
+            deleted: false,
+        }
+    }
+
+    pub fn from_record(record: &Value) -> Option<Self> {
+        match serde_json::from_value(record.clone()) {
+            Ok(definition) => Some(definition),
+            Err(e) => {
+                error!("Skipping tag record C2 sent in an unexpected shape: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Identifies the tag across syncs. Renames are only detectable through the C2 id,
+    /// so the name is used when the id is absent.
+    pub fn key(&self) -> String {
+        match self.id {
+            Some(id) => id.to_string(),
+            None => self.name.clone(),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.enabled && !self.deleted
+    }
+}
+
+/// Definitions of the tags currently collected, for sinks that describe values by their metadata.
+pub struct TagDefinitions {
+    definitions: RwLock<HashMap<String, TagDefinition>>,
+    version: AtomicU64,
+}
+
+impl TagDefinitions {
+    fn new() -> Self {
+        TagDefinitions {
+            definitions: RwLock::new(HashMap::new()),
+            version: AtomicU64::new(0),
+        }
+    }
+
+    pub fn insert(&self, tags: &[TagDefinition]) {
+        let mut definitions = self.definitions.write().unwrap();
+        for tag in tags {
+            definitions.insert(tag.name.clone(), tag.clone());
+        }
+        self.version.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Adds tags known only by name, keeping the metadata of any already defined.
+    pub fn insert_names(&self, names: &[String]) {
+        let mut definitions = self.definitions.write().unwrap();
+        for name in names {
+            definitions.entry(name.clone()).or_insert_with(|| TagDefinition::from_name(name.clone()));
+        }
+        self.version.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn remove(&self, names: &[String]) {
+        let mut definitions = self.definitions.write().unwrap();
+        for name in names {
+            definitions.remove(name);
+        }
+        self.version.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn get(&self, name: &str) -> Option<TagDefinition> {
+        self.definitions.read().unwrap().get(name).cloned()
+    }
+
+    pub fn snapshot(&self) -> Vec<TagDefinition> {
+        self.definitions.read().unwrap().values().cloned().collect()
+    }
+
+    /// Changes on every update, so readers can tell when to rebuild what they derived from the definitions.
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::SeqCst)
+    }
+}
+
+lazy_static! {
+    pub static ref TAG_DEFINITIONS: TagDefinitions = TagDefinitions::new();
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use crate::config::configuration::PartitionStrategy;
+use crate::system_initializer::tag_definition::TagDefinition;
+
+/// Virtual nodes per producer on the consistent hash ring; enough to keep partitions within a few percent.
+const RING_REPLICAS: usize = 64;
+
+struct Assignment {
+    producer: usize,
+    weight: f64,
+}
+
+/// Decides which producer monitors each tag and remembers the decision for later routing.
+pub struct TagPartitioner {
+    strategy: PartitionStrategy,
+    num_partitions: usize,
+    assignments: HashMap<String, Assignment>,
+    loads: Vec<f64>,
+    asset_owners: HashMap<String, usize>,
+    ring: Vec<(u64, usize)>,
+}
+
+impl TagPartitioner {
+    pub fn new(strategy: PartitionStrategy, num_partitions: usize) -> Self {
+        let mut ring = Vec::new();
+        if let PartitionStrategy::ConsistentHash = strategy {
+            for producer in 0..num_partitions {
+                for replica in 0..RING_REPLICAS {
+                    ring.push((ring_hash(format!("producer-{}-{}", producer, replica).as_bytes()), producer));
+                }
+            }
+            ring.sort_unstable();
+        }
+        TagPartitioner {
+            strategy,
+            num_partitions,
+            assignments: HashMap::new(),
+            loads: vec![0.0; num_partitions],
+            asset_owners: HashMap::new(),
+            ring,
+        }
+    }
+
+    /// Splits the initial tag list into exactly `num_partitions` partitions, some possibly empty.
+    pub fn partition(&mut self, tags: Vec<TagDefinition>) -> Vec<Vec<TagDefinition>> {
+        let mut partitions = vec![Vec::new(); self.num_partitions];
+        if self.num_partitions == 0 {
+            return partitions;
+        }
+        match self.strategy {
+            PartitionStrategy::RoundRobin => {
+                for (i, tag) in tags.into_iter().enumerate() {
+                    self.record(&tag, i % self.num_partitions);
+                    partitions[i % self.num_partitions].push(tag);
+                }
+            }
+            PartitionStrategy::ByAsset => {
+                let mut groups: HashMap<String, Vec<TagDefinition>> = HashMap::new();
+                for tag in tags {
+                    groups.entry(tag.asset_name.clone().unwrap_or_default()).or_default().push(tag);
+                }
+                let mut groups: Vec<(String, Vec<TagDefinition>)> = groups.into_iter().collect();
+                groups.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)));
+
+                // An asset larger than a fair share is split so one producer does not carry it alone.
+                let total: usize = groups.iter().map(|(_, group)| group.len()).sum();
+                let fair_share = total.div_ceil(self.num_partitions).max(1);
+                for (_, group) in groups {
+                    for chunk in group.chunks(fair_share) {
+                        let producer = self.least_loaded();
+                        for tag in chunk {
+                            self.record(tag, producer);
+                        }
+                        partitions[producer].extend_from_slice(chunk);
+                    }
+                }
+            }
+            PartitionStrategy::ByChangeRate => {
+                let mut tags = tags;
+                tags.sort_by(|a, b| change_rate(b).total_cmp(&change_rate(a)).then_with(|| a.name.cmp(&b.name)));
+                for tag in tags {
+                    let producer = self.least_loaded();
+                    self.record(&tag, producer);
+                    partitions[producer].push(tag);
+                }
+            }
+            PartitionStrategy::ConsistentHash => {
+                for tag in tags {
+                    let producer = self.ring_owner(&tag);
+                    self.record(&tag, producer);
+                    partitions[producer].push(tag);
+                }
+            }
+        }
+        partitions
+    }
+
+    /// Assigns tags added after start-up; tags that already have an owner stay with it.
+    pub fn assign(&mut self, tags: Vec<TagDefinition>) -> HashMap<usize, Vec<TagDefinition>> {
+        let mut additions: HashMap<usize, Vec<TagDefinition>> = HashMap::new();
+        if self.num_partitions == 0 {
+            return additions;
+        }
+        for tag in tags {
+            let producer = match self.assignments.get(&tag.name) {
+                Some(assignment) => assignment.producer,
+                None => {
+                    let producer = self.choose(&tag);
+                    self.record(&tag, producer);
+                    producer
+                }
+            };
+            additions.entry(producer).or_default().push(tag);
+        }
+        additions
+    }
+
+    /// Groups tags by owning producer, returning the tags no producer owns separately.
+    pub fn owned(&self, tags: Vec<String>) -> (HashMap<usize, Vec<String>>, Vec<String>) {
+        let mut owned: HashMap<usize, Vec<String>> = HashMap::new();
+        let mut unknown = Vec::new();
+        for tag in tags {
+            match self.assignments.get(&tag) {
+                Some(assignment) => owned.entry(assignment.producer).or_default().push(tag),
+                None => unknown.push(tag),
+            }
+        }
+        (owned, unknown)
+    }
+
+    pub fn owner(&self, tag: &str) -> Option<usize> {
+        self.assignments.get(tag).map(|assignment| assignment.producer)
+    }
+
+    /// Like `owned`, but also forgets the assignments so the tags can be placed afresh later.
+    pub fn release(&mut self, tags: Vec<String>) -> (HashMap<usize, Vec<String>>, Vec<String>) {
+        let (owned, unknown) = self.owned(tags);
+        for tag in owned.values().flatten() {
+            if let Some(assignment) = self.assignments.remove(tag) {
+                self.loads[assignment.producer] -= assignment.weight;
+            }
+        }
+        (owned, unknown)
+    }
+
+    fn choose(&self, tag: &TagDefinition) -> usize {
+        match self.strategy {
+            PartitionStrategy::ConsistentHash => self.ring_owner(tag),
+            PartitionStrategy::ByAsset => tag
+                .asset_name
+                .as_ref()
+                .and_then(|asset| self.asset_owners.get(asset).copied())
+                .unwrap_or_else(|| self.least_loaded()),
+            PartitionStrategy::RoundRobin | PartitionStrategy::ByChangeRate => self.least_loaded(),
+        }
+    }
+
+    fn record(&mut self, tag: &TagDefinition, producer: usize) {
+        let weight = self.weight(tag);
+        self.loads[producer] += weight;
+        if let Some(asset) = &tag.asset_name {
+            self.asset_owners.entry(asset.clone()).or_insert(producer);
+        }
+        self.assignments.insert(tag.name.clone(), Assignment { producer, weight });
+    }
+
+    fn weight(&self, tag: &TagDefinition) -> f64 {
+        match self.strategy {
+            PartitionStrategy::ByChangeRate => change_rate(tag),
+            _ => 1.0,
+        }
+    }
+
+    fn least_loaded(&self) -> usize {
+        self.loads
+            .iter()
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(producer, _)| producer)
+            .unwrap_or(0)
+    }
+
+    fn ring_owner(&self, tag: &TagDefinition) -> usize {
+        let hash = ring_hash(tag.key().as_bytes());
+        let index = self.ring.partition_point(|(point, _)| *point < hash);
+        self.ring.get(index).or_else(|| self.ring.first()).map(|(_, producer)| *producer).unwrap_or(0)
+    }
+}
+
+/// Expected updates per second, from C2's update period hint or the requested sampling interval.
+fn change_rate(tag: &TagDefinition) -> f64 {
+    match tag.expected_update_period.or(tag.sampling_interval) {
+        Some(period) if period > 0.0 => 1000.0 / period,
+        _ => 1.0,
+    }
+}
+
+/// FNV-1a, used instead of `DefaultHasher` because assignments must be stable across builds and restarts,
+/// finished with MurmurHash3's mix: FNV alone leaves keys that differ in their last characters, such as
+/// `Tag1` and `Tag2`, close together on the ring and the partitions far apart in size.
+fn ring_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(range: std::ops::Range<i64>) -> Vec<TagDefinition> {
+        range
+            .map(|id| TagDefinition { id: Some(id), ..TagDefinition::from_name(format!("Tag{}", id)) })
+            .collect()
+    }
+
+    fn sizes(partitions: &[Vec<TagDefinition>]) -> Vec<usize> {
+        partitions.iter().map(Vec::len).collect()
+    }
+
+    #[test]
+    fn round_robin_and_change_rate_balance_evenly() {
+        let mut partitioner = TagPartitioner::new(PartitionStrategy::RoundRobin, 4);
+        assert_eq!(sizes(&partitioner.partition(tags(0..10))), vec![3, 3, 2, 2]);
+
+        let mut tags = tags(0..8);
+        for (i, tag) in tags.iter_mut().enumerate() {
+            // Two fast tags and six slow ones.
+            tag.expected_update_period = Some(if i < 2 { 100.0 } else { 1000.0 });
+        }
+        let mut partitioner = TagPartitioner::new(PartitionStrategy::ByChangeRate, 2);
+        let partitions = partitioner.partition(tags);
+        assert_eq!(sizes(&partitions), vec![4, 4]);
+        let rate = |partition: &Vec<TagDefinition>| partition.iter().map(change_rate).sum::<f64>();
+        assert_eq!(rate(&partitions[0]), rate(&partitions[1]));
+    }
+
+    #[test]
+    fn by_asset_keeps_assets_together() {
+        let mut tags = tags(0..6);
+        for (i, tag) in tags.iter_mut().enumerate() {
+            tag.asset_name = Some(if i < 3 { "Pump" } else { "Valve" }.to_string());
+        }
+        let mut partitioner = TagPartitioner::new(PartitionStrategy::ByAsset, 2);
+        let partitions = partitioner.partition(tags);
+        assert_eq!(sizes(&partitions), vec![3, 3]);
+        for partition in &partitions {
+            assert!(partition.iter().all(|tag| tag.asset_name == partition[0].asset_name));
+        }
+
+        let mut added = TagDefinition::from_name("Pump.Speed".to_string());
+        added.asset_name = Some("Pump".to_string());
+        let pump_owner = partitioner.owner("Tag0").unwrap();
+        assert_eq!(partitioner.assign(vec![added]).keys().copied().collect::<Vec<_>>(), vec![pump_owner]);
+    }
+
+    #[test]
+    fn consistent_hash_balances_within_a_margin() {
+        let mut partitioner = TagPartitioner::new(PartitionStrategy::ConsistentHash, 4);
+        for size in sizes(&partitioner.partition(tags(0..4000))) {
+            assert!((700..=1300).contains(&size), "partition of {} tags", size);
+        }
+    }
+
+    #[test]
+    fn placement_is_stable() {
+        let mut first = TagPartitioner::new(PartitionStrategy::ConsistentHash, 4);
+        let mut second = TagPartitioner::new(PartitionStrategy::ConsistentHash, 4);
+        first.partition(tags(0..200));
+        second.partition(tags(0..200).into_iter().rev().collect());
+        for tag in tags(0..200) {
+            assert_eq!(first.owner(&tag.name), second.owner(&tag.name));
+        }
+
+        // Tags that already have an owner stay with it, whatever the strategy would choose now.
+        let mut partitioner = TagPartitioner::new(PartitionStrategy::RoundRobin, 3);
+        partitioner.partition(tags(0..3));
+        let owner = partitioner.owner("Tag2");
+        partitioner.assign(tags(2..3));
+        assert_eq!(partitioner.owner("Tag2"), owner);
+    }
+
+    #[test]
+    fn adding_and_removing_tags_moves_no_other_tag() {
+        let mut partitioner = TagPartitioner::new(PartitionStrategy::ConsistentHash, 4);
+        partitioner.partition(tags(0..500));
+        let before: Vec<Option<usize>> = tags(0..500).iter().map(|tag| partitioner.owner(&tag.name)).collect();
+
+        partitioner.assign(tags(500..600));
+        partitioner.release(tags(0..100).into_iter().map(|tag| tag.name).collect());
+        for (tag, owner) in tags(0..500).iter().zip(&before).skip(100) {
+            assert_eq!(partitioner.owner(&tag.name), *owner);
+        }
+        for tag in tags(0..100) {
+            assert_eq!(partitioner.owner(&tag.name), None);
+        }
+        // A released tag added again lands where it was.
+        partitioner.assign(tags(0..1));
+        assert_eq!(partitioner.owner("Tag0"), before[0]);
+    }
+
+    #[test]
+    fn another_producer_takes_only_its_share() {
+        let mut four = TagPartitioner::new(PartitionStrategy::ConsistentHash, 4);
+        let mut five = TagPartitioner::new(PartitionStrategy::ConsistentHash, 5);
+        four.partition(tags(0..2000));
+        five.partition(tags(0..2000));
+        let moved = tags(0..2000).iter().filter(|tag| four.owner(&tag.name) != five.owner(&tag.name)).count();
+        // Ideally a fifth of the tags, all of them to the new producer.
+        assert!(moved < 600, "{} tags moved", moved);
+        for tag in tags(0..2000) {
+            if four.owner(&tag.name) != five.owner(&tag.name) {
+                assert_eq!(five.owner(&tag.name), Some(4));
+            }
+        }
+    }
+}
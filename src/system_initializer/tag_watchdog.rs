@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use opcua::types::StatusCode;
+use crate::config::configuration::{WatchdogConfig, CONFIG};
+use crate::message::TagStatus;
+use crate::sinks::c2::C2Outbox;
+use crate::sinks::TAG_STATUS_TYPE;
+use crate::system_initializer::tag_definition::{TagDefinition, TAG_DEFINITIONS};
+use crate::telemetry::metrics::METRICS;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TagState {
+    Ok,
+    /// No value within `stale_factor` times the expected update period.
+    Stale,
+    /// The last value came with a Bad status code.
+    Bad,
+}
+
+impl TagState {
+    pub const ALL: [TagState; 3] = [TagState::Ok, TagState::Stale, TagState::Bad];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TagState::Ok => "ok",
+            TagState::Stale => "stale",
+            TagState::Bad => "bad",
+        }
+    }
+}
+
+struct TagHealth {
+    // When the last value arrived, or when the watchdog first saw the tag while it has none.
+    last_update: Instant,
+    // Wall-clock time of the last value for C2; 0 while there is none.
+    last_update_t: i64,
+    status: StatusCode,
+    state: TagState,
+}
+
+impl TagHealth {
+    fn new() -> Self {
+        TagHealth { last_update: Instant::now(), last_update_t: 0, status: StatusCode::Good, state: TagState::Ok }
+    }
+}
+
+/// Tracks when each tag last reported a value and with which status, and notices tags that stop
+/// updating or turn Bad. A change is logged, counted in the metrics and sent to C2 as a `TagStatus`
+/// message, and reported again as `ok` once good values arrive.
+pub struct TagWatchdog {
+    tags: Mutex<HashMap<String, TagHealth>>,
+}
+
+impl TagWatchdog {
+    fn new() -> Self {
+        TagWatchdog { tags: Mutex::new(HashMap::new()) }
+    }
+
+    /// Called for every value a subscription delivers.
+    pub fn record_update(&self, tag: &str, status: StatusCode) {
+        let mut tags = self.tags.lock().unwrap();
+        if !tags.contains_key(tag) {
+            tags.insert(tag.to_string(), TagHealth::new());
+        }
+        if let Some(health) = tags.get_mut(tag) {
+            health.last_update = Instant::now();
+            health.last_update_t = Utc::now().timestamp_millis();
+            health.status = status;
+        }
+    }
+
+    /// Drops removed tags, which would otherwise turn stale.
+    pub fn forget(&self, tags: &[String]) {
+        let mut tag_health = self.tags.lock().unwrap();
+        for tag in tags {
+            tag_health.remove(tag);
+        }
+    }
+
+    /// When each tag that reported a value last did, by tag name.
+    pub fn last_updates(&self) -> Vec<(String, Instant)> {
+        let tags = self.tags.lock().unwrap();
+        let mut last_updates: Vec<(String, Instant)> = tags
+            .iter()
+            .filter(|(_, health)| health.last_update_t != 0)
+            .map(|(tag, health)| (tag.clone(), health.last_update))
+            .collect();
+        last_updates.sort();
+        last_updates
+    }
+
+    pub fn state_counts(&self) -> Vec<(TagState, usize)> {
+        let tags = self.tags.lock().unwrap();
+        TagState::ALL
+            .iter()
+            .map(|state| (*state, tags.values().filter(|health| health.state == *state).count()))
+            .collect()
+    }
+
+    /// Checks the tags every `watchdog.check_interval_secs` and sends what changed to C2, when C2 is one
+    /// of the sinks.
+    pub async fn run(&self) -> Result<(), String> {
+        let mut c2 = C2Outbox::new(TAG_STATUS_TYPE, "tag status");
+        loop {
+            let interval = CONFIG.current().get_watchdog().check_interval_secs;
+            tokio::time::sleep(Duration::from_secs(interval.max(1))).await;
+            if interval == 0 {
+                continue;
+            }
+            let changes = self.check();
+            if let Some(c2) = c2.as_mut() {
+                c2.send(&changes).await;
+            }
+        }
+    }
+
+    /// Compares every collected tag against its expected update period and returns the changes.
+    fn check(&self) -> Vec<TagStatus> {
+        let config = CONFIG.current();
+        self.check_at(&TAG_DEFINITIONS.snapshot(), config.get_watchdog(), Instant::now(), Utc::now().timestamp_millis())
+    }
+
+    fn check_at(&self, definitions: &[TagDefinition], settings: &WatchdogConfig, now: Instant, now_t: i64) -> Vec<TagStatus> {
+        let mut tags = self.tags.lock().unwrap();
+        let mut changes = Vec::new();
+        for definition in definitions.iter().filter(|definition| definition.is_active()) {
+            let health = tags.entry(definition.name.clone()).or_insert_with(TagHealth::new);
+            let silent_for = now.saturating_duration_since(health.last_update);
+            let state = if health.status.is_bad() {
+                TagState::Bad
+            } else if Self::stale_after(definition, settings).is_some_and(|stale_after| silent_for > stale_after) {
+                TagState::Stale
+            } else {
+                TagState::Ok
+            };
+            if state == health.state {
+                continue;
+            }
+            match state {
+                TagState::Ok => info!("Tag {} is updating again", definition.name),
+                TagState::Stale => warn!("Tag {} has not updated for {:?}", definition.name, silent_for),
+                TagState::Bad => warn!("Tag {} reports {}", definition.name, health.status),
+            }
+            METRICS.tag_state_changes.inc(&[state.as_str()]);
+            health.state = state;
+            changes.push(TagStatus {
+                sensor: definition.name.clone(),
+                state: state.as_str().to_string(),
+                t: now_t,
+                last_update_t: health.last_update_t,
+                status_code: health.status.bits(),
+            });
+        }
+        changes
+    }
+
+    fn stale_after(definition: &TagDefinition, settings: &WatchdogConfig) -> Option<Duration> {
+        let period = match definition.expected_update_period.filter(|period| *period > 0.0) {
+            Some(period) => Duration::from_secs_f64(period / 1000.0),
+            None if settings.default_period_secs > 0 => Duration::from_secs(settings.default_period_secs),
+            None => return None,
+        };
+        Some(period.mul_f64(settings.stale_factor))
+    }
+}
+
+lazy_static! {
+    pub static ref WATCHDOG: TagWatchdog = TagWatchdog::new();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(default_period_secs: u64) -> WatchdogConfig {
+        WatchdogConfig { check_interval_secs: 1, default_period_secs, stale_factor: 3.0 }
+    }
+
+    fn tag(name: &str, expected_update_period: Option<f64>) -> TagDefinition {
+        TagDefinition { expected_update_period, ..TagDefinition::from_name(name.to_string()) }
+    }
+
+    fn states(changes: &[TagStatus]) -> Vec<(&str, &str)> {
+        changes.iter().map(|change| (change.sensor.as_str(), change.state.as_str())).collect()
+    }
+
+    #[test]
+    fn stale_after_scales_the_expected_period() {
+        let settings = settings(10);
+        assert_eq!(TagWatchdog::stale_after(&tag("Temp", Some(500.0)), &settings), Some(Duration::from_millis(1500)));
+        assert_eq!(TagWatchdog::stale_after(&tag("Temp", None), &settings), Some(Duration::from_secs(30)));
+        assert_eq!(TagWatchdog::stale_after(&tag("Temp", Some(0.0)), &settings), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn tags_without_a_period_are_not_checked_when_the_default_is_zero() {
+        let watchdog = TagWatchdog::new();
+        let settings = settings(0);
+        assert_eq!(TagWatchdog::stale_after(&tag("Temp", None), &settings), None);
+        watchdog.record_update("Temp", StatusCode::Good);
+        let later = Instant::now() + Duration::from_secs(86_400);
+        assert!(watchdog.check_at(&[tag("Temp", None)], &settings, later, 0).is_empty());
+    }
+
+    #[test]
+    fn a_bad_status_is_reported_once() {
+        let watchdog = TagWatchdog::new();
+        let tags = [tag("Temp", Some(1000.0))];
+        watchdog.record_update("Temp", StatusCode::BadSensorFailure);
+        let now = Instant::now();
+
+        let changes = watchdog.check_at(&tags, &settings(0), now, 42);
+        assert_eq!(states(&changes), vec![("Temp", "bad")]);
+        assert_eq!(changes[0].status_code, StatusCode::BadSensorFailure.bits());
+        assert_eq!(changes[0].t, 42);
+        assert!(changes[0].last_update_t > 0);
+        assert!(watchdog.check_at(&tags, &settings(0), now, 43).is_empty());
+    }
+
+    #[test]
+    fn a_silent_tag_turns_stale_and_back_to_ok() {
+        let watchdog = TagWatchdog::new();
+        let tags = [tag("Temp", Some(1000.0))];
+        watchdog.record_update("Temp", StatusCode::Good);
+        let start = Instant::now();
+
+        // Stale past three times the expected period of a second.
+        assert!(watchdog.check_at(&tags, &settings(0), start + Duration::from_millis(2900), 0).is_empty());
+        let changes = watchdog.check_at(&tags, &settings(0), start + Duration::from_millis(3100), 0);
+        assert_eq!(states(&changes), vec![("Temp", "stale")]);
+        assert_eq!(watchdog.state_counts(), vec![(TagState::Ok, 0), (TagState::Stale, 1), (TagState::Bad, 0)]);
+
+        watchdog.record_update("Temp", StatusCode::Good);
+        let changes = watchdog.check_at(&tags, &settings(0), Instant::now(), 0);
+        assert_eq!(states(&changes), vec![("Temp", "ok")]);
+    }
+
+    #[test]
+    fn tags_that_never_report_turn_stale_too() {
+        let watchdog = TagWatchdog::new();
+        let tags = [tag("Flow", Some(1000.0)), TagDefinition { enabled: false, ..tag("Off", Some(1000.0)) }];
+        assert!(watchdog.check_at(&tags, &settings(0), Instant::now(), 0).is_empty());
+        let changes = watchdog.check_at(&tags, &settings(0), Instant::now() + Duration::from_secs(4), 0);
+        assert_eq!(states(&changes), vec![("Flow", "stale")]);
+        assert_eq!(changes[0].last_update_t, 0);
+    }
+}
@@ -1,45 +1,325 @@
+use std::collections::{HashMap, HashSet};
 use log::{info, error};
+use chrono::Utc;
+use tokio::time::{sleep, Duration};
 use crate::system_initializer:: WebSocketClient;
+use crate::clients::ws_client::TagResponse;
+use crate::clients::c2_endpoints::C2_ENDPOINTS;
 use crate::config::configuration::CONFIG;
+use crate::system_initializer::tag_control::{ControlRequest, ControlReply};
+use crate::system_initializer::tag_definition::TagDefinition;
 use serde_json;
 
+#[derive(Default, Debug)]
+pub struct TagChanges {
+    pub added: Vec<TagDefinition>,
+    pub removed: Vec<String>,
+    pub renamed: Vec<(String, TagDefinition)>,
+    pub updated: Vec<TagDefinition>,
+}
+
+impl TagChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.renamed.is_empty() && self.updated.is_empty()
+    }
+}
+
+/// Requests to the tag sync loop from outside it, such as a configuration reload.
+pub enum TagSyncCommand {
+    /// Fetch the whole tag list again, e.g. because the tag filter changed.
+    Resync,
+    /// Pick up a new sync interval.
+    Reschedule,
+}
+
+/// How far before the local clock the next incremental sync starts when C2's records carry no
+/// modification time, so that changes made while a fetch ran are not skipped.
+const SYNC_OVERLAP_MS: i64 = 60_000;
+
 pub struct TagSynchronizer {
-    ws_client: WebSocketClient
+    ws_client: WebSocketClient,
+    endpoint: usize,
+    catalog: HashMap<String, TagDefinition>,
+    last_sync: i64,
 }
 
 impl TagSynchronizer {
     pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let backend_url = CONFIG.get_base();
-        let header_key = CONFIG.get_header_key();
-        let header_value = CONFIG.get_header_value();
-        
-        let ws_client = WebSocketClient::new(backend_url.to_string(), header_key, header_value);
-        
-        Ok(TagSynchronizer {ws_client})
+        if C2_ENDPOINTS.len() == 0 {
+            return Err("No C2 endpoint configured".into());
+        }
+        let config = CONFIG.current();
+        let header_key = config.get_header_key();
+        let header_value = config.get_header_value();
+
+        let ws_client = WebSocketClient::new(C2_ENDPOINTS.endpoint(0).url().to_string(), header_key, header_value);
+
+        Ok(TagSynchronizer {ws_client, endpoint: 0, catalog: HashMap::new(), last_sync: 0})
     }
 
-    pub async fn get_tags(&mut self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-        if let Err(e) = self.ws_client.connect_to_c2_server().await {
-            error!("Failed to connect to C2 server: {}", e);
-            return Err(e);
-        }
+    pub async fn get_tags(&mut self) -> Result<Vec<TagDefinition>, Box<dyn std::error::Error>> {
+        self.connect().await;
+
+        let sync_started = Utc::now().timestamp_millis();
+        let last_modified = CONFIG.current().get_message().filter.last_modified;
+        let response = self.fetch_tags(last_modified).await?;
 
-        let tag_request = CONFIG.get_message();
-        let tag_request = serde_json::to_string(&tag_request)?;
-        if let Err(e) = self.ws_client.send_tag_request(tag_request).await {
-            error!("Failed to send request: {}", e);
-            return Err(e);
+        self.catalog.clear();
+        for tag in Self::definitions(&response).into_iter().filter(TagDefinition::is_active) {
+            self.catalog.insert(tag.key(), tag);
         }
+        self.last_sync = Self::sync_point(&response, sync_started).unwrap_or(last_modified);
+
+        let tags_vec: Vec<TagDefinition> = self.catalog.values().cloned().collect();
+        info!("Tags received from C2: {}", tags_vec.len());
+        Ok(tags_vec)
+    }
 
-        let tags_vec = match self.ws_client.receive_response().await {
-            Ok(tags) => tags,
+    /// Fetches tags modified since the previous sync and diffs them against the known catalog.
+    pub async fn sync_changes(&mut self) -> Result<TagChanges, Box<dyn std::error::Error>> {
+        let sync_started = Utc::now().timestamp_millis();
+        let first_attempt = self.fetch_tags(self.last_sync).await.map_err(|e| e.to_string());
+        let response = match first_attempt {
+            Ok(response) => response,
             Err(e) => {
-                error!("Failed to receive response: {}", e);
-                return Err(e);
+                // The C2 connection may have dropped since the last sync, retry once on a fresh one.
+                error!("Incremental tag sync failed, reconnecting: {}", e);
+                C2_ENDPOINTS.endpoint(self.endpoint).record_failure(&e);
+                self.connect().await;
+                self.fetch_tags(self.last_sync).await?
             }
         };
 
-        info!("Tags received from C2: {}", tags_vec.len());
-        Ok(tags_vec)
+        let changes = self.diff(Self::definitions(&response));
+        if let Some(sync_point) = Self::sync_point(&response, sync_started) {
+            self.last_sync = sync_point;
+        }
+        info!(
+            "Tag sync: {} added, {} removed, {} renamed, {} updated",
+            changes.added.len(), changes.removed.len(), changes.renamed.len(), changes.updated.len()
+        );
+        Ok(changes)
+    }
+
+    /// Fetches the full tag list with the current filter; known tags it no longer contains are removed.
+    pub async fn resync(&mut self) -> Result<TagChanges, Box<dyn std::error::Error>> {
+        let sync_started = Utc::now().timestamp_millis();
+        let last_modified = CONFIG.current().get_message().filter.last_modified;
+        let response = self.fetch_tags(last_modified).await?;
+        let changes = self.replace_catalog(response, sync_started)?;
+        info!(
+            "Full tag resync: {} added, {} removed, {} renamed, {} updated",
+            changes.added.len(), changes.removed.len(), changes.renamed.len(), changes.updated.len()
+        );
+        Ok(changes)
+    }
+
+    /// Makes the full tag list the catalog. Only a list C2 finished with its final batch is taken,
+    /// as tags missing from a cut-off one would be unsubscribed.
+    fn replace_catalog(&mut self, response: TagResponse, sync_started: i64) -> Result<TagChanges, Box<dyn std::error::Error>> {
+        if !response.final_batch {
+            return Err(format!("Tag list from C2 ended without its final batch after {} records", response.records.len()).into());
+        }
+        let synced_tags: Vec<TagDefinition> = Self::definitions(&response)
+            .into_iter()
+            .filter(TagDefinition::is_active)
+            .collect();
+
+        let synced_keys: HashSet<String> = synced_tags.iter().map(TagDefinition::key).collect();
+        let mut dropped = Vec::new();
+        self.catalog.retain(|key, known| {
+            let keep = synced_keys.contains(key);
+            if !keep {
+                dropped.push(known.name.clone());
+            }
+            keep
+        });
+        let mut changes = self.diff(synced_tags);
+        changes.removed.extend(dropped);
+        if let Some(sync_point) = Self::sync_point(&response, sync_started) {
+            self.last_sync = sync_point;
+        }
+        Ok(changes)
+    }
+
+    fn definitions(response: &TagResponse) -> Vec<TagDefinition> {
+        response.records.iter().filter_map(TagDefinition::from_record).collect()
+    }
+
+    /// Where the next incremental sync starts after a fetch, if it returned anything: the newest
+    /// change by C2's clock, so skew against the local clock cannot skip changes. Tags fetched
+    /// again diff as unchanged.
+    fn sync_point(response: &TagResponse, sync_started: i64) -> Option<i64> {
+        if response.records.is_empty() {
+            return None;
+        }
+        Some(response.newest_modified().unwrap_or(sync_started - SYNC_OVERLAP_MS))
+    }
+
+    /// Applies synced tags to the known catalog and returns what changed.
+    fn diff(&mut self, synced_tags: Vec<TagDefinition>) -> TagChanges {
+        let mut changes = TagChanges::default();
+        for tag in synced_tags {
+            let key = tag.key();
+            match self.catalog.get(&key) {
+                Some(known) if !tag.is_active() => {
+                    changes.removed.push(known.name.clone());
+                    self.catalog.remove(&key);
+                }
+                Some(known) if known.name != tag.name => {
+                    changes.renamed.push((known.name.clone(), tag.clone()));
+                    self.catalog.insert(key, tag);
+                }
+                Some(known) if *known != tag => {
+                    changes.updated.push(tag.clone());
+                    self.catalog.insert(key, tag);
+                }
+                None if tag.is_active() => {
+                    changes.added.push(tag.clone());
+                    self.catalog.insert(key, tag);
+                }
+                _ => {}
+            }
+        }
+        changes
+    }
+
+    pub async fn receive_control(&mut self) -> Result<ControlRequest, Box<dyn std::error::Error>> {
+        loop {
+            let control = self.ws_client.receive_control().await?;
+            match serde_json::from_value(control) {
+                Ok(request) => return Ok(request),
+                Err(e) => error!("Ignoring malformed control message: {}", e),
+            }
+        }
+    }
+
+    pub async fn reconnect(&mut self) {
+        C2_ENDPOINTS.endpoint(self.endpoint).record_failure("control channel lost");
+        self.connect().await;
+    }
+
+    /// Tag sync always follows the failover order, whatever mode the consumers use.
+    async fn connect(&mut self) {
+        loop {
+            match C2_ENDPOINTS.connect_first(&C2_ENDPOINTS.failover_order()).await {
+                Ok((endpoint, ws_client)) => {
+                    self.endpoint = endpoint;
+                    self.ws_client = ws_client;
+                    return;
+                }
+                Err(e) => {
+                    error!("Failed to connect to C2 server: {}", e);
+                    sleep(Duration::from_secs(5)).await;
+                }
+            }
+        }
+    }
+
+    pub async fn send_control_reply(&mut self, reply: &ControlReply) -> Result<(), Box<dyn std::error::Error>> {
+        let reply = serde_json::to_string(reply)?;
+        self.ws_client.send_control_reply(reply).await
+    }
+
+    async fn fetch_tags(&mut self, last_modified: i64) -> Result<TagResponse, Box<dyn std::error::Error>> {
+        let config = CONFIG.current();
+        let page_size = config.get_tag_sync_page_size();
+        let mut tag_request = config.get_message().clone();
+        tag_request.filter.last_modified = last_modified;
+        if last_modified > 0 {
+            // Incremental requests must see deletions so they can be unsubscribed.
+            tag_request.filter.filter_deleted = false;
+        }
+
+        let mut response = TagResponse::default();
+        let mut starting_row = 0;
+        loop {
+            if page_size > 0 {
+                tag_request.filter.starting_row = starting_row;
+                tag_request.filter.max_record_count = page_size;
+            }
+
+            let request = serde_json::to_string(&tag_request)?;
+            if let Err(e) = self.ws_client.send_tag_request(request).await {
+                error!("Failed to send request: {}", e);
+                return Err(e);
+            }
+
+            let page = match self.ws_client.receive_response().await {
+                Ok(page) => page,
+                Err(e) => {
+                    error!("Failed to receive response: {}", e);
+                    return Err(e);
+                }
+            };
+
+            let page_len = page.records.len() as i32;
+            response.extend(page);
+            if page_size <= 0 || page_len < page_size {
+                break;
+            }
+            starting_row += page_len;
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A batch as C2 sends it, with its two stray characters.
+    fn batch(data: serde_json::Value, final_batch: bool) -> String {
+        let mut text = json!({"msg_type": "TAG_RESPONSE", "msg": {"data": data, "finalBatch": final_batch}}).to_string();
+        text.insert(31, '~');
+        text.insert(text.len() - 1, '~');
+        text
+    }
+
+    fn synchronizer(tags: &[(i64, &str)]) -> TagSynchronizer {
+        let catalog = tags
+            .iter()
+            .map(|(id, name)| (id.to_string(), TagDefinition { id: Some(*id), ..TagDefinition::from_name(name.to_string()) }))
+            .collect();
+        TagSynchronizer {
+            ws_client: WebSocketClient::new(String::new(), "x-key".to_string(), String::new()),
+            endpoint: 0,
+            catalog,
+            last_sync: 0,
+        }
+    }
+
+    #[test]
+    fn cut_off_resync_removes_nothing() {
+        let mut synchronizer = synchronizer(&[(1, "Temp"), (2, "Level"), (3, "Flow")]);
+        let mut response = TagResponse::default();
+        response.push(&batch(json!([{"id": 1, "tagName": "Temp", "lastModified": 500}]), false)).unwrap();
+
+        assert!(synchronizer.replace_catalog(response, 1_000).is_err());
+        assert_eq!(synchronizer.catalog.len(), 3);
+        assert_eq!(synchronizer.last_sync, 0);
+    }
+
+    #[test]
+    fn complete_resync_removes_missing_tags() {
+        let mut synchronizer = synchronizer(&[(1, "Temp"), (2, "Level"), (3, "Flow")]);
+        let mut response = TagResponse::default();
+        response.push(&batch(json!([{"id": 1, "tagName": "Temp", "lastModified": 500}]), false)).unwrap();
+        response.push(&batch(json!([{"id": 2, "tagName": "Level", "lastModified": 700}]), true)).unwrap();
+
+        let changes = synchronizer.replace_catalog(response, 1_000).unwrap();
+        assert_eq!(changes.removed, vec!["Flow".to_string()]);
+        assert!(changes.added.is_empty());
+        assert_eq!(synchronizer.catalog.len(), 2);
+        assert_eq!(synchronizer.last_sync, 700);
+    }
+
+    #[test]
+    fn sync_point_falls_back_to_the_local_clock_with_an_overlap() {
+        let mut response = TagResponse::default();
+        assert_eq!(TagSynchronizer::sync_point(&response, 100_000), None);
+        response.push(&batch(json!([{"id": 1, "tagName": "Temp"}]), true)).unwrap();
+        assert_eq!(TagSynchronizer::sync_point(&response, 100_000), Some(100_000 - SYNC_OVERLAP_MS));
     }
 }
@@ -0,0 +1,17 @@
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+/// Reads a configuration group from the settings under test, named "test" unless they name it.
+pub fn group<T: DeserializeOwned>(settings: Value) -> T {
+    let mut group = json!({"name": "test"});
+    group.as_object_mut().unwrap().extend(settings.as_object().unwrap().clone());
+    serde_json::from_value(group).unwrap()
+}
+
+pub fn assert_close(actual: f64, expected: f64) {
+    assert_within(actual, expected, 1e-9);
+}
+
+pub fn assert_within(actual: f64, expected: f64, tolerance: f64) {
+    assert!((actual - expected).abs() < tolerance, "{} != {}", actual, expected);
+}
@@ -0,0 +1,160 @@
+/// The names of a unit, and its factor and offset to its quantity's base unit.
+type Unit = (&'static [&'static str], f64, f64);
+
+/// Engineering units the client converts between, by quantity, such that `base = value * factor + offset`.
+const UNITS: &[(&str, &[Unit])] = &[
+    ("temperature", &[
+        (&["K"], 1.0, 0.0),
+        (&["degC", "°C", "C"], 1.0, 273.15),
+        (&["degF", "°F", "F"], 5.0 / 9.0, 459.67 * 5.0 / 9.0),
+    ]),
+    ("pressure", &[
+        (&["Pa"], 1.0, 0.0),
+        (&["kPa"], 1e3, 0.0),
+        (&["MPa"], 1e6, 0.0),
+        (&["mbar"], 1e2, 0.0),
+        (&["bar"], 1e5, 0.0),
+        (&["psi"], 6894.757293168, 0.0),
+        (&["atm"], 101_325.0, 0.0),
+        (&["mmHg"], 133.322387415, 0.0),
+        (&["inH2O"], 249.08891, 0.0),
+    ]),
+    ("volume flow", &[
+        (&["m3/s", "m³/s"], 1.0, 0.0),
+        (&["m3/h", "m³/h"], 1.0 / 3600.0, 0.0),
+        (&["L/s", "l/s"], 1e-3, 0.0),
+        (&["L/min", "l/min"], 1e-3 / 60.0, 0.0),
+        (&["L/h", "l/h"], 1e-3 / 3600.0, 0.0),
+        (&["gpm", "gal/min"], 0.003785411784 / 60.0, 0.0),
+        (&["cfm", "ft3/min"], 0.028316846592 / 60.0, 0.0),
+    ]),
+    ("mass flow", &[
+        (&["kg/s"], 1.0, 0.0),
+        (&["kg/h"], 1.0 / 3600.0, 0.0),
+        (&["t/h"], 1000.0 / 3600.0, 0.0),
+        (&["lb/h"], 0.45359237 / 3600.0, 0.0),
+    ]),
+    ("volume", &[
+        (&["m3", "m³"], 1.0, 0.0),
+        (&["L", "l"], 1e-3, 0.0),
+        (&["gal"], 0.003785411784, 0.0),
+        (&["ft3"], 0.028316846592, 0.0),
+    ]),
+    ("mass", &[
+        (&["kg"], 1.0, 0.0),
+        (&["g"], 1e-3, 0.0),
+        (&["t"], 1000.0, 0.0),
+        (&["lb"], 0.45359237, 0.0),
+    ]),
+    ("length", &[
+        (&["m"], 1.0, 0.0),
+        (&["mm"], 1e-3, 0.0),
+        (&["cm"], 1e-2, 0.0),
+        (&["km"], 1e3, 0.0),
+        (&["in"], 0.0254, 0.0),
+        (&["ft"], 0.3048, 0.0),
+    ]),
+    ("power", &[
+        (&["W"], 1.0, 0.0),
+        (&["kW"], 1e3, 0.0),
+        (&["MW"], 1e6, 0.0),
+        (&["hp"], 745.699872, 0.0),
+    ]),
+    ("energy", &[
+        (&["J"], 1.0, 0.0),
+        (&["kJ"], 1e3, 0.0),
+        (&["MJ"], 1e6, 0.0),
+        (&["Wh"], 3.6e3, 0.0),
+        (&["kWh"], 3.6e6, 0.0),
+        (&["MWh"], 3.6e9, 0.0),
+    ]),
+    ("frequency", &[
+        (&["Hz"], 1.0, 0.0),
+        (&["kHz"], 1e3, 0.0),
+        (&["rpm", "1/min"], 1.0 / 60.0, 0.0),
+    ]),
+];
+
+/// Converts values from one unit to another of the same quantity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Conversion {
+    factor: f64,
+    offset: f64,
+}
+
+impl Conversion {
+    /// The conversion between two units, or why there is none.
+    pub fn between(from: &str, to: &str) -> Result<Conversion, String> {
+        let (from_quantity, from_factor, from_offset) = lookup(from).ok_or_else(|| format!("unknown unit {}", from))?;
+        let (to_quantity, to_factor, to_offset) = lookup(to).ok_or_else(|| format!("unknown unit {}", to))?;
+        if from_quantity != to_quantity {
+            return Err(format!("{} is a {} and {} a {}", from, from_quantity, to, to_quantity));
+        }
+        Ok(Conversion { factor: from_factor / to_factor, offset: (from_offset - to_offset) / to_factor })
+    }
+
+    pub fn apply(&self, value: f64) -> f64 {
+        value * self.factor + self.offset
+    }
+}
+
+pub fn is_known(unit: &str) -> bool {
+    lookup(unit).is_some()
+}
+
+fn lookup(unit: &str) -> Option<(&'static str, f64, f64)> {
+    let unit = unit.trim();
+    UNITS.iter().find_map(|(quantity, units)| {
+        units
+            .iter()
+            .find(|(names, _, _)| names.contains(&unit))
+            .map(|(_, factor, offset)| (*quantity, *factor, *offset))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system_initializer::test_support::assert_within;
+
+    fn convert(value: f64, from: &str, to: &str) -> f64 {
+        Conversion::between(from, to).unwrap().apply(value)
+    }
+
+    // The factors of the units table have about seven significant digits.
+    fn assert_close(actual: f64, expected: f64) {
+        assert_within(actual, expected, 1e-6);
+    }
+
+    #[test]
+    fn temperatures_convert_with_their_offsets() {
+        assert_close(convert(212.0, "degF", "degC"), 100.0);
+        assert_close(convert(-40.0, "°C", "°F"), -40.0);
+        assert_close(convert(0.0, "C", "K"), 273.15);
+        assert_close(convert(0.0, "K", "degF"), -459.67);
+    }
+
+    #[test]
+    fn factors_convert_within_a_quantity() {
+        assert_close(convert(1.0, "bar", "psi"), 14.5037738);
+        assert_close(convert(1.0, "atm", "kPa"), 101.325);
+        assert_close(convert(1.0, "m3/h", "L/min"), 1000.0 / 60.0);
+        assert_close(convert(60.0, "rpm", "Hz"), 1.0);
+        assert_close(convert(1.0, "kWh", "MJ"), 3.6);
+        assert_close(convert(12.0, "in", "ft"), 1.0);
+        assert_close(convert(5.0, "kg", "kg"), 5.0);
+    }
+
+    #[test]
+    fn units_of_different_quantities_do_not_convert() {
+        assert_eq!(Conversion::between("bar", "degC").unwrap_err(), "bar is a pressure and degC a temperature");
+        assert_eq!(Conversion::between("furlong", "m").unwrap_err(), "unknown unit furlong");
+    }
+
+    #[test]
+    fn names_are_matched_exactly_after_trimming() {
+        assert!(is_known(" kPa "));
+        assert!(is_known("m³/h"));
+        assert!(!is_known("KPA"));
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use crate::clients::session_pool::SessionPool;
+use crate::config::configuration::CONFIG;
+use crate::system_initializer::data_queue::QUEUE;
+use crate::system_initializer::shutdown::SHUTDOWN;
+use crate::system_initializer::supervisor::{TaskState, TaskStatus, SUPERVISOR};
+
+#[derive(Default)]
+struct ConsumerHealth {
+    sinks: BTreeMap<String, SinkHealth>,
+    last_push: Option<Instant>,
+}
+
+struct SinkHealth {
+    connected: bool,
+    last_error: Option<String>,
+}
+
+#[derive(Default)]
+struct TagSyncHealth {
+    last_success: Option<Instant>,
+    last_error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ProducerReport {
+    pub producer: usize,
+    pub source: String,
+    pub endpoint: String,
+    pub sessions: usize,
+    pub connected_sessions: usize,
+}
+
+#[derive(Serialize)]
+pub struct SinkReport {
+    pub sink: String,
+    pub connected: bool,
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ConsumerReport {
+    pub consumer: usize,
+    pub sinks: Vec<SinkReport>,
+    pub last_push_age_secs: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct TagSyncReport {
+    pub last_success_age_secs: Option<f64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct QueueReport {
+    pub depth: usize,
+    pub capacity: usize,
+    pub fill: f64,
+}
+
+/// The body of `/healthz` and `/readyz`; `problems` lists why the check failed, if it did.
+#[derive(Serialize)]
+pub struct HealthReport {
+    pub healthy: bool,
+    pub problems: Vec<String>,
+    pub shutting_down: bool,
+    pub producers: Vec<ProducerReport>,
+    pub consumers: Vec<ConsumerReport>,
+    pub tag_sync: TagSyncReport,
+    pub queue: QueueReport,
+    pub last_push_age_secs: Option<f64>,
+    pub tasks: Vec<TaskStatus>,
+}
+
+/// What producers, consumers and tag sync last reported about their connections, for the health checks.
+///
+/// `/healthz` fails when restarting the process is the remedy: a task gave up, the queue is nearly
+/// full, nothing was delivered for too long or tag sync keeps failing. `/readyz` also fails while a
+/// producer has no OPC UA session, a consumer's sink is disconnected or the client is shutting down.
+pub struct Health {
+    started: Instant,
+    producers: Mutex<Vec<Weak<SessionPool>>>,
+    consumers: Mutex<BTreeMap<usize, ConsumerHealth>>,
+    tag_sync: Mutex<TagSyncHealth>,
+}
+
+impl Health {
+    fn new() -> Self {
+        Health {
+            started: Instant::now(),
+            producers: Mutex::new(Vec::new()),
+            consumers: Mutex::new(BTreeMap::new()),
+            tag_sync: Mutex::new(TagSyncHealth::default()),
+        }
+    }
+
+    /// Producers are numbered in the order they are watched.
+    pub fn watch_producer(&self, session_pool: &Arc<SessionPool>) {
+        self.producers.lock().unwrap().push(Arc::downgrade(session_pool));
+    }
+
+    /// Records the outcome of a consumer connecting or sending to one of its sinks.
+    pub fn record_sink(&self, consumer: usize, sink: &str, result: Result<(), String>) {
+        let mut consumers = self.consumers.lock().unwrap();
+        let sinks = &mut consumers.entry(consumer).or_default().sinks;
+        let health = SinkHealth { connected: result.is_ok(), last_error: result.err() };
+        match sinks.get_mut(sink) {
+            Some(sink) => {
+                sink.connected = health.connected;
+                sink.last_error = health.last_error.or(sink.last_error.take());
+            }
+            None => {
+                sinks.insert(sink.to_string(), health);
+            }
+        }
+    }
+
+    /// Records that every sink of the consumer took a batch.
+    pub fn record_push(&self, consumer: usize) {
+        self.consumers.lock().unwrap().entry(consumer).or_default().last_push = Some(Instant::now());
+    }
+
+    pub fn record_tag_sync(&self, result: Result<(), String>) {
+        let mut tag_sync = self.tag_sync.lock().unwrap();
+        match result {
+            Ok(()) => tag_sync.last_success = Some(Instant::now()),
+            Err(e) => tag_sync.last_error = Some(e),
+        }
+    }
+
+    pub fn liveness(&self) -> HealthReport {
+        let mut report = self.report();
+        report.problems = self.liveness_problems(&report);
+        report.healthy = report.problems.is_empty();
+        report
+    }
+
+    pub fn readiness(&self) -> HealthReport {
+        let mut report = self.report();
+        report.problems = self.liveness_problems(&report);
+        report.problems.extend(self.readiness_problems(&report));
+        report.healthy = report.problems.is_empty();
+        report
+    }
+
+    fn liveness_problems(&self, report: &HealthReport) -> Vec<String> {
+        let config = CONFIG.current();
+        let thresholds = config.get_health();
+        let mut problems = Vec::new();
+        for task in report.tasks.iter().filter(|task| task.state == TaskState::Failed) {
+            problems.push(format!("task {} failed too often and was given up", task.name));
+        }
+        if report.queue.fill >= thresholds.max_queue_fill {
+            problems.push(format!("queue is {:.0}% full", report.queue.fill * 100.0));
+        }
+        // A quiet site has nothing to deliver; only records left waiting count against the push age.
+        let push_age = report.last_push_age_secs.unwrap_or_else(|| self.started.elapsed().as_secs_f64());
+        if thresholds.max_push_age_secs > 0 && push_age > thresholds.max_push_age_secs as f64 && report.queue.depth > 0 {
+            problems.push(format!("no batch delivered for {:.0}s while {} records wait", push_age, report.queue.depth));
+        }
+        let sync_age = report.tag_sync.last_success_age_secs.unwrap_or_else(|| self.started.elapsed().as_secs_f64());
+        if thresholds.max_tag_sync_age_secs > 0 && sync_age > thresholds.max_tag_sync_age_secs as f64 {
+            problems.push(format!("tag sync last succeeded {:.0}s ago", sync_age));
+        }
+        problems
+    }
+
+    fn readiness_problems(&self, report: &HealthReport) -> Vec<String> {
+        let mut problems = Vec::new();
+        if report.shutting_down {
+            problems.push("shutting down".to_string());
+        }
+        for producer in report.producers.iter().filter(|producer| producer.connected_sessions == 0) {
+            problems.push(format!("producer {} has no OPC UA session to {}", producer.producer, producer.source));
+        }
+        for consumer in 1..=CONFIG.current().get_num_consumers() {
+            match report.consumers.iter().find(|report| report.consumer == consumer) {
+                Some(report) => {
+                    for sink in report.sinks.iter().filter(|sink| !sink.connected) {
+                        problems.push(format!("consumer {} is not connected to {}", consumer, sink.sink));
+                    }
+                }
+                None => problems.push(format!("consumer {} has not connected its sinks", consumer)),
+            }
+        }
+        problems
+    }
+
+    fn report(&self) -> HealthReport {
+        let producers = self
+            .producers
+            .lock()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .filter_map(|(producer, session_pool)| Some((producer, session_pool.upgrade()?)))
+            .map(|(producer, session_pool)| {
+                let sessions = session_pool.session_states();
+                ProducerReport {
+                    producer: producer + 1,
+                    source: session_pool.name().to_string(),
+                    endpoint: session_pool.active_endpoint().to_string(),
+                    sessions: sessions.len(),
+                    connected_sessions: sessions.into_iter().filter(|connected| *connected).count(),
+                }
+            })
+            .collect();
+
+        let consumers = self.consumers.lock().unwrap();
+        let last_push = consumers.values().filter_map(|consumer| consumer.last_push).max();
+        let consumers = consumers
+            .iter()
+            .map(|(consumer, health)| ConsumerReport {
+                consumer: consumer + 1,
+                sinks: health
+                    .sinks
+                    .iter()
+                    .map(|(sink, health)| SinkReport {
+                        sink: sink.clone(),
+                        connected: health.connected,
+                        last_error: health.last_error.clone(),
+                    })
+                    .collect(),
+                last_push_age_secs: health.last_push.map(|last_push| last_push.elapsed().as_secs_f64()),
+            })
+            .collect();
+
+        let tag_sync = self.tag_sync.lock().unwrap();
+        let (depth, capacity) = (QUEUE.len(), QUEUE.capacity());
+        HealthReport {
+            healthy: true,
+            problems: Vec::new(),
+            shutting_down: SHUTDOWN.is_started(),
+            producers,
+            consumers,
+            tag_sync: TagSyncReport {
+                last_success_age_secs: tag_sync.last_success.map(|last_success| last_success.elapsed().as_secs_f64()),
+                last_error: tag_sync.last_error.clone(),
+            },
+            queue: QueueReport { depth, capacity, fill: depth as f64 / capacity.max(1) as f64 },
+            last_push_age_secs: last_push.map(|last_push| last_push.elapsed().as_secs_f64()),
+            tasks: SUPERVISOR.statuses(),
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref HEALTH: Health = Health::new();
+}
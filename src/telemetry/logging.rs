@@ -0,0 +1,87 @@
+use std::fs::OpenOptions;
+use std::sync::{Mutex, OnceLock};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{runtime, trace, Resource};
+use tracing_subscriber::filter::{filter_fn, LevelFilter};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::{Layered, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, Layer, Registry};
+use crate::config::configuration::{LogFormat, LoggingConfig};
+
+/// Crates the OTLP exporter itself runs on; exporting their spans would feed the exporter its own traffic.
+const EXPORTER_TARGETS: &[&str] = &["h2", "hyper", "tonic", "tower"];
+
+/// Everything behind the level filter that `set_level` adjusts.
+type Filtered = Layered<reload::Layer<LevelFilter, Registry>, Registry>;
+
+static LEVEL: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+
+/// Writes `log` records and the tracing events of libraries such as hyper, tonic and rskafka, with
+/// the spans they happened in, to `logging.file` as text or JSON lines, and exports the spans to an
+/// OpenTelemetry collector when `logging.otlp_endpoint` is set.
+///
+/// Both kinds of records are filtered at `level`; `set_level` changes it on reload.
+pub fn init(config: &LoggingConfig, level: log::LevelFilter) -> Result<(), String> {
+    let writer = if config.file.trim().is_empty() {
+        BoxMakeWriter::new(std::io::stdout)
+    } else {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.file)
+            .map_err(|e| format!("Cannot open log file {}: {}", config.file, e))?;
+        BoxMakeWriter::new(Mutex::new(file))
+    };
+    let output = fmt::layer().with_ansi(false).with_writer(writer);
+    let mut layers: Vec<Box<dyn Layer<Filtered> + Send + Sync>> = vec![match config.format {
+        LogFormat::Text => output.boxed(),
+        LogFormat::Json => output.json().with_current_span(true).with_span_list(true).boxed(),
+    }];
+
+    let endpoint = config.otlp_endpoint.trim();
+    if !endpoint.is_empty() {
+        let resource = Resource::new(vec![KeyValue::new("service.name", config.service_name.clone())]);
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint))
+            .with_trace_config(trace::config().with_resource(resource))
+            .install_batch(runtime::Tokio)
+            .map_err(|e| format!("Cannot export spans to {}: {}", endpoint, e))?;
+        let exported = filter_fn(|metadata| !EXPORTER_TARGETS.iter().any(|target| metadata.target().starts_with(target)));
+        layers.push(tracing_opentelemetry::layer().with_tracer(tracer).with_filter(exported).boxed());
+    }
+
+    let (filter, handle) = reload::Layer::new(tracing_level(level));
+    tracing_subscriber::registry().with(filter).with(layers).try_init().map_err(|e| e.to_string())?;
+    log::set_max_level(level);
+    let _ = LEVEL.set(handle);
+    Ok(())
+}
+
+/// Applies `log_level` to `log` records and tracing events alike.
+pub fn set_level(level: log::LevelFilter) {
+    log::set_max_level(level);
+    if let Some(handle) = LEVEL.get() {
+        if let Err(e) = handle.reload(tracing_level(level)) {
+            log::error!("Cannot change the level of tracing events: {}", e);
+        }
+    }
+}
+
+fn tracing_level(level: log::LevelFilter) -> LevelFilter {
+    match level {
+        log::LevelFilter::Off => LevelFilter::OFF,
+        log::LevelFilter::Error => LevelFilter::ERROR,
+        log::LevelFilter::Warn => LevelFilter::WARN,
+        log::LevelFilter::Info => LevelFilter::INFO,
+        log::LevelFilter::Debug => LevelFilter::DEBUG,
+        log::LevelFilter::Trace => LevelFilter::TRACE,
+    }
+}
+
+/// Exports the spans still buffered; `std::process::exit` would drop them.
+pub fn shutdown() {
+    tokio::task::block_in_place(opentelemetry::global::shutdown_tracer_provider);
+}
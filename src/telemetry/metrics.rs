@@ -0,0 +1,351 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, Weak};
+use lazy_static::lazy_static;
+use crate::clients::c2_endpoints::C2_ENDPOINTS;
+use crate::clients::session_pool::SessionPool;
+use crate::config::configuration::CONFIG;
+use crate::system_initializer::data_queue::QUEUE;
+use crate::system_initializer::supervisor::{TaskState, SUPERVISOR};
+use crate::system_initializer::tag_watchdog::WATCHDOG;
+
+/// Upper bounds for batch sizes in records.
+const RECORD_BUCKETS: &[f64] = &[1.0, 10.0, 50.0, 100.0, 200.0, 500.0, 1000.0, 5000.0];
+/// Upper bounds for durations in seconds.
+const SECONDS_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+/// Upper bounds for the age of values in seconds, from milliseconds up to a backlog of minutes.
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0];
+
+/// Prometheus text format, built one metric family at a time.
+#[derive(Default)]
+pub struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+                .collect();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {}", value);
+    }
+
+    pub fn into_text(self) -> String {
+        self.text
+    }
+}
+
+/// Seconds between two wall-clock times in milliseconds; a source clock ahead of ours counts as no delay.
+pub fn latency_seconds(from: i64, to: i64) -> f64 {
+    (to - from).max(0) as f64 / 1000.0
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn labeled<'a>(names: &[&'a str], values: &'a [String]) -> Vec<(&'a str, &'a str)> {
+    names.iter().copied().zip(values.iter().map(String::as_str)).collect()
+}
+
+/// A counter with one value per combination of label values.
+pub struct CounterVec {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, u64>>,
+}
+
+impl CounterVec {
+    fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Self {
+        CounterVec { name, help, labels, values: Mutex::new(BTreeMap::new()) }
+    }
+
+    pub fn inc(&self, labels: &[&str]) {
+        self.inc_by(labels, 1);
+    }
+
+    pub fn inc_by(&self, labels: &[&str], amount: u64) {
+        let key = labels.iter().map(|label| label.to_string()).collect();
+        *self.values.lock().unwrap().entry(key).or_default() += amount;
+    }
+
+    fn render(&self, exposition: &mut Exposition) {
+        exposition.family(self.name, "counter", self.help);
+        for (labels, value) in self.values.lock().unwrap().iter() {
+            exposition.sample(self.name, &labeled(self.labels, labels), *value as f64);
+        }
+    }
+}
+
+/// A gauge with one value per combination of label values.
+pub struct GaugeVec {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, i64>>,
+}
+
+impl GaugeVec {
+    fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Self {
+        GaugeVec { name, help, labels, values: Mutex::new(BTreeMap::new()) }
+    }
+
+    pub fn add(&self, labels: &[&str], amount: i64) {
+        let key = labels.iter().map(|label| label.to_string()).collect();
+        *self.values.lock().unwrap().entry(key).or_default() += amount;
+    }
+
+    fn render(&self, exposition: &mut Exposition) {
+        exposition.family(self.name, "gauge", self.help);
+        for (labels, value) in self.values.lock().unwrap().iter() {
+            exposition.sample(self.name, &labeled(self.labels, labels), *value as f64);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct HistogramValues {
+    // Per bucket, not cumulative; summed up when rendered.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// A histogram with one set of buckets per combination of label values.
+pub struct HistogramVec {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    bounds: &'static [f64],
+    values: Mutex<BTreeMap<Vec<String>, HistogramValues>>,
+}
+
+impl HistogramVec {
+    fn new(name: &'static str, help: &'static str, labels: &'static [&'static str], bounds: &'static [f64]) -> Self {
+        HistogramVec { name, help, labels, bounds, values: Mutex::new(BTreeMap::new()) }
+    }
+
+    pub fn observe(&self, labels: &[&str], value: f64) {
+        let key = labels.iter().map(|label| label.to_string()).collect();
+        let mut values = self.values.lock().unwrap();
+        let histogram = values.entry(key).or_insert_with(|| HistogramValues {
+            buckets: vec![0; self.bounds.len()],
+            sum: 0.0,
+            count: 0,
+        });
+        if let Some(bucket) = self.bounds.iter().position(|bound| value <= *bound) {
+            histogram.buckets[bucket] += 1;
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+
+    fn render(&self, exposition: &mut Exposition) {
+        exposition.family(self.name, "histogram", self.help);
+        let bucket_name = format!("{}_bucket", self.name);
+        let sum_name = format!("{}_sum", self.name);
+        let count_name = format!("{}_count", self.name);
+        for (labels, histogram) in self.values.lock().unwrap().iter() {
+            let labels = labeled(self.labels, labels);
+            let mut cumulative = 0;
+            for (bound, count) in self.bounds.iter().zip(&histogram.buckets) {
+                cumulative += count;
+                let bound = bound.to_string();
+                let mut bucket_labels = labels.clone();
+                bucket_labels.push(("le", &bound));
+                exposition.sample(&bucket_name, &bucket_labels, cumulative as f64);
+            }
+            let mut bucket_labels = labels.clone();
+            bucket_labels.push(("le", "+Inf"));
+            exposition.sample(&bucket_name, &bucket_labels, histogram.count as f64);
+            exposition.sample(&sum_name, &labels, histogram.sum);
+            exposition.sample(&count_name, &labels, histogram.count as f64);
+        }
+    }
+}
+
+/// Metrics recorded as things happen. State that already lives elsewhere, such as the queue
+/// depth or C2 endpoint health, is read when `/metrics` is scraped instead.
+pub struct Metrics {
+    pub batch_records: HistogramVec,
+    pub sink_send_seconds: HistogramVec,
+    pub sink_errors: CounterVec,
+    pub sink_dropped_records: CounterVec,
+    pub dropped_samples: CounterVec,
+    pub monitored_items: GaugeVec,
+    pub source_to_receive_seconds: HistogramVec,
+    pub receive_to_dequeue_seconds: HistogramVec,
+    pub dequeue_to_ack_seconds: HistogramVec,
+    pub tag_state_changes: CounterVec,
+    pub suppressed_samples: CounterVec,
+    pub aggregate_windows: CounterVec,
+    pub aggregate_late_samples: CounterVec,
+    session_pools: Mutex<Vec<Weak<SessionPool>>>,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            batch_records: HistogramVec::new(
+                "opc_client_batch_records",
+                "Records per batch handed to the sinks.",
+                &[],
+                RECORD_BUCKETS,
+            ),
+            sink_send_seconds: HistogramVec::new(
+                "opc_client_sink_send_seconds",
+                "Time a sink took to take one batch.",
+                &["sink"],
+                SECONDS_BUCKETS,
+            ),
+            sink_errors: CounterVec::new("opc_client_sink_errors_total", "Batches a sink failed to take.", &["sink"]),
+            sink_dropped_records: CounterVec::new(
+                "opc_client_sink_dropped_records_total",
+                "Records in batches a sink failed to take.",
+                &["sink"],
+            ),
+            dropped_samples: CounterVec::new(
+                "opc_client_dropped_samples_total",
+                "Values that never made it into a batch, by reason.",
+                &["reason"],
+            ),
+            monitored_items: GaugeVec::new(
+                "opc_client_monitored_items",
+                "Monitored items producers hold on each OPC UA source.",
+                &["source"],
+            ),
+            source_to_receive_seconds: HistogramVec::new(
+                "opc_client_source_to_receive_seconds",
+                "From a value's source timestamp to the client receiving it.",
+                &[],
+                LATENCY_BUCKETS,
+            ),
+            receive_to_dequeue_seconds: HistogramVec::new(
+                "opc_client_receive_to_dequeue_seconds",
+                "Time a value spent in the data queue.",
+                &[],
+                LATENCY_BUCKETS,
+            ),
+            dequeue_to_ack_seconds: HistogramVec::new(
+                "opc_client_dequeue_to_ack_seconds",
+                "From a consumer taking a value from the queue to a sink accepting its batch.",
+                &["sink"],
+                LATENCY_BUCKETS,
+            ),
+            tag_state_changes: CounterVec::new(
+                "opc_client_tag_state_changes_total",
+                "Times the watchdog found a tag turned stale or bad, or ok again.",
+                &["state"],
+            ),
+            suppressed_samples: CounterVec::new(
+                "opc_client_suppressed_samples_total",
+                "Values the client-side filters or aggregations kept out of the queue, by group and reason.",
+                &["group", "reason"],
+            ),
+            aggregate_windows: CounterVec::new(
+                "opc_client_aggregate_windows_total",
+                "Windows aggregated and sent to C2, by aggregation group.",
+                &["group"],
+            ),
+            aggregate_late_samples: CounterVec::new(
+                "opc_client_aggregate_late_samples_total",
+                "Values left out of the aggregates because their window was already sent, by aggregation group.",
+                &["group"],
+            ),
+            session_pools: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Reports the connection state of the pool's sessions for as long as the pool exists.
+    pub fn watch_session_pool(&self, session_pool: &Arc<SessionPool>) {
+        self.session_pools.lock().unwrap().push(Arc::downgrade(session_pool));
+    }
+
+    pub fn render(&self) -> String {
+        let mut exposition = Exposition::default();
+
+        exposition.family("opc_client_queue_depth", "gauge", "Records waiting in the data queue.");
+        exposition.sample("opc_client_queue_depth", &[], QUEUE.len() as f64);
+        exposition.family("opc_client_queue_capacity", "gauge", "Records the data queue holds before producers wait.");
+        exposition.sample("opc_client_queue_capacity", &[], QUEUE.capacity() as f64);
+        exposition.family("opc_client_queue_enqueued_total", "counter", "Records added to the data queue.");
+        exposition.sample("opc_client_queue_enqueued_total", &[], QUEUE.get_produced_count() as f64);
+        exposition.family("opc_client_queue_dequeued_total", "counter", "Records taken from the data queue by consumers.");
+        exposition.sample("opc_client_queue_dequeued_total", &[], QUEUE.get_consumed_count() as f64);
+
+        self.batch_records.render(&mut exposition);
+        self.sink_send_seconds.render(&mut exposition);
+        self.sink_errors.render(&mut exposition);
+        self.sink_dropped_records.render(&mut exposition);
+        self.dropped_samples.render(&mut exposition);
+        self.monitored_items.render(&mut exposition);
+        self.source_to_receive_seconds.render(&mut exposition);
+        self.receive_to_dequeue_seconds.render(&mut exposition);
+        self.dequeue_to_ack_seconds.render(&mut exposition);
+        self.tag_state_changes.render(&mut exposition);
+        self.suppressed_samples.render(&mut exposition);
+        self.aggregate_windows.render(&mut exposition);
+        self.aggregate_late_samples.render(&mut exposition);
+
+        C2_ENDPOINTS.render_metrics(&mut exposition);
+        self.render_sessions(&mut exposition);
+        self.render_tasks(&mut exposition);
+        exposition.family("opc_client_tags", "gauge", "Collected tags by watchdog state.");
+        for (state, count) in WATCHDOG.state_counts() {
+            exposition.sample("opc_client_tags", &[("state", state.as_str())], count as f64);
+        }
+        if CONFIG.current().get_telemetry().per_tag_metrics {
+            self.render_tag_ages(&mut exposition);
+        }
+        exposition.into_text()
+    }
+
+    fn render_sessions(&self, exposition: &mut Exposition) {
+        exposition.family("opc_client_opc_session_connected", "gauge", "1 while an OPC UA session of the pool is connected.");
+        let mut session_pools = self.session_pools.lock().unwrap();
+        session_pools.retain(|session_pool| session_pool.strong_count() > 0);
+        for session_pool in session_pools.iter().filter_map(Weak::upgrade) {
+            let endpoint = session_pool.active_endpoint();
+            for (session, connected) in session_pool.session_states().into_iter().enumerate() {
+                let session = session.to_string();
+                let labels = [("source", session_pool.name()), ("session", session.as_str()), ("endpoint", endpoint)];
+                exposition.sample("opc_client_opc_session_connected", &labels, if connected { 1.0 } else { 0.0 });
+            }
+        }
+    }
+
+    fn render_tasks(&self, exposition: &mut Exposition) {
+        let tasks = SUPERVISOR.statuses();
+        exposition.family("opc_client_task_running", "gauge", "1 while a supervised task is running.");
+        for task in &tasks {
+            let running = if task.state == TaskState::Running { 1.0 } else { 0.0 };
+            exposition.sample("opc_client_task_running", &[("task", &task.name)], running);
+        }
+        exposition.family("opc_client_task_restarts_total", "counter", "Times the supervisor restarted a task.");
+        for task in &tasks {
+            exposition.sample("opc_client_task_restarts_total", &[("task", &task.name)], task.restarts as f64);
+        }
+    }
+
+    fn render_tag_ages(&self, exposition: &mut Exposition) {
+        exposition.family("opc_client_tag_last_update_age_seconds", "gauge", "Seconds since a tag last reported a value.");
+        for (tag, updated_at) in WATCHDOG.last_updates() {
+            exposition.sample("opc_client_tag_last_update_age_seconds", &[("tag", &tag)], updated_at.elapsed().as_secs_f64());
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref METRICS: Metrics = Metrics::new();
+}
@@ -0,0 +1,4 @@
+pub mod health;
+pub mod logging;
+pub mod metrics;
+pub mod server;
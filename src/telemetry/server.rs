@@ -0,0 +1,42 @@
+use std::net::SocketAddr;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{Router, Server};
+use log::info;
+use crate::telemetry::health::{HealthReport, HEALTH};
+use crate::telemetry::metrics::METRICS;
+
+/// Content type of the Prometheus text exposition format.
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Serves `/metrics`, `/healthz` and `/readyz` on `listen` until the process exits.
+pub async fn serve(listen: &str) -> Result<(), String> {
+    let address: SocketAddr = listen.parse().map_err(|e| format!("Invalid telemetry.listen {}: {}", listen, e))?;
+    let app = Router::new()
+        .route("/metrics", get(metrics))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz));
+    let server = Server::try_bind(&address).map_err(|e| format!("Cannot listen on {}: {}", address, e))?;
+    info!("Serving metrics and health checks on http://{}", address);
+    server.serve(app.into_make_service()).await.map_err(|e| e.to_string())
+}
+
+async fn metrics() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, METRICS_CONTENT_TYPE)], METRICS.render())
+}
+
+async fn healthz() -> impl IntoResponse {
+    health_response(HEALTH.liveness())
+}
+
+async fn readyz() -> impl IntoResponse {
+    health_response(HEALTH.readiness())
+}
+
+/// 200 when the check passed and 503 when it did not, with the report as JSON either way.
+fn health_response(report: HealthReport) -> impl IntoResponse {
+    let status = if report.healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    let body = serde_json::to_string_pretty(&report).unwrap_or_default();
+    (status, [(header::CONTENT_TYPE, "application/json")], body)
+}